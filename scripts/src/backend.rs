@@ -0,0 +1,121 @@
+use cosmwasm_std::Uint128;
+use cw_orch::daemon::{
+    tx_broadcaster::{account_sequence_strategy, insufficient_fee_strategy, TxBroadcaster},
+    TxBuilder, Wallet,
+};
+
+use crate::{
+    bundle::GasEstimate,
+    plan::PlanMsg,
+    precheck::{destinations, source_outflows},
+    snapshot::{
+        ChainSnapshot, Delegation, DelegatorShares, PendingReward, Unbonding, ValidatorState,
+    },
+};
+
+/// Staking queries the planner and the broadcast prechecks make. Implemented over gRPC by
+/// [`crate::query::ChainQuerier`] and in memory by [`crate::simulator::StakingSimulator`].
+#[allow(async_fn_in_trait)]
+pub trait StakingBackend {
+    async fn block_height(&self) -> anyhow::Result<u64>;
+    /// All delegations of `delegator`, across every page.
+    async fn delegator_delegations(&self, delegator: &str) -> anyhow::Result<Vec<Delegation>>;
+    /// All delegations of `delegator` and the shares behind each, from the same pages.
+    async fn delegator_stake(
+        &self,
+        delegator: &str,
+    ) -> anyhow::Result<(Vec<Delegation>, Vec<DelegatorShares>)>;
+    /// Amount `delegator` has staked on `validator`, zero if there is no delegation.
+    async fn delegation(&self, delegator: &str, validator: &str) -> anyhow::Result<Uint128>;
+    /// Bond status, jailing and signing info of `operator`, `None` if the validator does not exist.
+    async fn validator(&self, operator: &str) -> anyhow::Result<Option<ValidatorState>>;
+    /// Every unbonding entry of `delegator`, across every page.
+    async fn unbonding_delegations(&self, delegator: &str) -> anyhow::Result<Vec<Unbonding>>;
+
+    /// Fetches the delegations `msgs` move stake out of and the validators they move it to.
+    async fn live_state(&self, msgs: &[PlanMsg]) -> anyhow::Result<ChainSnapshot> {
+        let height = self.block_height().await?;
+
+        let mut delegations = Vec::new();
+        for (del_addr, operator_addr) in source_outflows(msgs)?.into_keys() {
+            let amount = self.delegation(&del_addr, &operator_addr).await?;
+            delegations.push(Delegation {
+                del_addr,
+                operator_addr,
+                amount,
+            });
+        }
+
+        let mut validators = Vec::new();
+        for operator in destinations(msgs) {
+            if let Some(state) = self.validator(&operator).await? {
+                validators.push(state);
+            }
+        }
+
+        Ok(ChainSnapshot {
+            height,
+            delegations,
+            validators,
+            ..Default::default()
+        })
+    }
+}
+
+#[allow(async_fn_in_trait)]
+pub trait BankBackend {
+    /// Liquid `denom` balance of `address`, zero if it holds none.
+    async fn balance(&self, address: &str, denom: &str) -> anyhow::Result<Uint128>;
+}
+
+#[allow(async_fn_in_trait)]
+pub trait DistributionBackend {
+    /// `denom` rewards `delegator` has not withdrawn yet, per validator.
+    async fn pending_rewards(
+        &self,
+        delegator: &str,
+        denom: &str,
+    ) -> anyhow::Result<Vec<PendingReward>>;
+}
+
+/// The account bundles are signed and broadcast with, the authz grantee of every DAO.
+#[allow(async_fn_in_trait)]
+pub trait WalletBackend {
+    fn address(&self) -> String;
+    async fn simulate(&self, msgs: Vec<cosmrs::Any>) -> anyhow::Result<GasEstimate>;
+    /// Broadcasts `msgs` in one tx that is dropped after `timeout_height`, returns its hash.
+    /// Inclusion is followed with a [`crate::broadcast::TxTracker`].
+    async fn broadcast(
+        &self,
+        msgs: Vec<cosmrs::Any>,
+        timeout_height: u64,
+    ) -> anyhow::Result<String>;
+}
+
+impl WalletBackend for Wallet {
+    fn address(&self) -> String {
+        self.pub_addr_str()
+    }
+
+    async fn simulate(&self, msgs: Vec<cosmrs::Any>) -> anyhow::Result<GasEstimate> {
+        let (gas, fee) = Wallet::simulate(self, msgs, None).await?;
+        Ok(GasEstimate {
+            gas,
+            fee: fee.amount,
+        })
+    }
+
+    async fn broadcast(
+        &self,
+        msgs: Vec<cosmrs::Any>,
+        timeout_height: u64,
+    ) -> anyhow::Result<String> {
+        let tx_builder = TxBuilder::new(TxBuilder::build_body(msgs, None, timeout_height));
+        let resp = TxBroadcaster::default()
+            .add_strategy(insufficient_fee_strategy())
+            .add_strategy(account_sequence_strategy())
+            .broadcast(tx_builder, self)
+            .await?;
+        Ok(resp.txhash)
+    }
+}
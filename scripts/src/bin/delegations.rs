@@ -1,52 +1,60 @@
-use std::{collections::HashMap, fs::File, io::Write, str::FromStr};
+use std::{cmp::Reverse, collections::BTreeMap, fs::File, io::Write, str::FromStr};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use cosmos_sdk_proto::cosmos::{
-    base::{query::v1beta1::PageRequest, v1beta1::Coin as ProtoCoin},
-    staking::v1beta1::{DelegationResponse, MsgBeginRedelegate, MsgDelegate, MsgUndelegate},
+    base::v1beta1::Coin as ProtoCoin,
+    staking::v1beta1::{MsgBeginRedelegate, MsgDelegate, MsgUndelegate},
 };
-use cosmrs::{tx::Msg, AccountId};
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Decimal, Uint128};
+use cosmwasm_std::{Coin, Uint128};
 use csv::ReaderBuilder;
-use cw_orch::{
-    daemon::{
-        queriers::{Bank, Staking},
-        DaemonBuilder, TxSender, Wallet,
+use cw_orch::daemon::DaemonBuilder;
+use futures::{stream, StreamExt, TryStreamExt};
+
+use delegation_scripts::{
+    backend::{BankBackend, DistributionBackend, StakingBackend, WalletBackend},
+    broadcast::{
+        wait_for_inclusion, GrpcTxTracker, TxTracker, INCLUSION_POLL_INTERVAL,
+        INCLUSION_TIMEOUT_BLOCKS,
+    },
+    bundle::{
+        needs_earlier_bundles, pack_bundles, schedule_bundles, total_fee, Bundle, GasEstimate,
+        GasLimits, PackedBundle, SubmissionLog, DEFAULT_MAX_TX_GAS, MAX_MSGS_PER_BUNDLE,
+    },
+    cache::{QueryCache, QUERY_CACHE_DIR},
+    chain::ChainProfile,
+    compound::{compound, CompoundMode, COMPOUNDED_TARGETS_CSV, DEFAULT_RESERVE},
+    csv_export::{write_messages_csv, write_validators_csv, MESSAGES_CSV, VALIDATORS_CSV},
+    diff::{diff, Epoch, DIFF_JSON},
+    dry_run::{dry_run_plan, offline_remote},
+    endpoints::{configured_urls, Endpoints, RetryPolicy},
+    errors::{bisect_failing_prefix, failing_exec_index, FailureKind, PlannerError},
+    plan::{
+        authz_exec, DelegateMsg, Delegations, MessageExport, PlanEntryId, PlanMsg, RedelegateMsg,
+        Redelegations, UndelegateMsg, Undelegations,
+    },
+    precheck::check_bundle,
+    query::ChainQuerier,
+    reconcile::{
+        reconcile, ReconcileReport, FOLLOWUP_MSG_JSON, RECONCILE_JSON, RECONCILE_VERIFICATION_JSON,
+    },
+    report::{epoch_report, plan_hash, EpochReport, REPORT_HTML, REPORT_JSON, REPORT_MD},
+    rewards::{prepend_withdrawals, withdrawn_rewards},
+    shares::fit_to_shares,
+    snapshot::{ChainSnapshot, Delegation, ValidatorClass, ValidatorState, SNAPSHOT_JSON},
+    treasury::{treasury_report, TREASURY_JSON},
+    unbonding::{route_freed_funds, unbonding_schedule, UNBONDING_FOLLOWUP_JSON, UNBONDING_JSON},
+    verify::{
+        replay_plan, verify_final_state, verify_plan, Verification, VerifyReport, VERIFICATION_JSON,
     },
-    environment::{ChainKind, NetworkInfo},
-    prelude::*,
 };
-
 use tokio::runtime::Runtime;
 
 pub const TOTAL_OBLIGATED_VALIDATORS: usize = 33;
-pub const TOTAL_OBLIGATED_DELEGATED_BTSG: Uint128 = Uint128::new(9_999_980_000_000u128);
-pub const NEW_DELS_FILE: &str = "./src/bin/data/new-delegations.csv";
 pub const RAW_MSG_JSON: &str = "delegation_messages.json";
-
-#[cw_serde]
-struct DelegationDaoEntity {
-    dao_add: String,
-    current_balance: Coin,
-    current_delegation: Uint128,
-    obligated_delegation: Uint128,
-    total_delegation_count: usize,
-}
-
-#[cw_serde]
-struct AlignedValidator {
-    operator_addr: String,
-    current_delegations: Vec<Delegation>,
-    new_delegation_amount: Uint128,
-}
-
-#[cw_serde]
-struct Delegation {
-    del_addr: String,
-    operator_addr: String,
-    amount: Uint128,
-}
+pub const BROADCAST_LOG_JSON: &str = "delegation_broadcast.json";
+/// Queries planning keeps in flight at once.
+pub const QUERY_CONCURRENCY: usize = 8;
 
 #[cw_serde]
 struct AllAlignedDelegations {
@@ -54,76 +62,16 @@ struct AllAlignedDelegations {
     total: Uint128,
 }
 
-#[cw_serde]
-struct RedelegateMsg {
-    delegator_address: String,
-    validator_src_address: String,
-    validator_dst_address: String,
-    amount: String,
-    denom: String,
-}
-
-#[cw_serde]
-struct UndelegateMsg {
-    delegator_address: String,
-    validator_address: String,
-    amount: String,
-    denom: String,
-}
-
-#[cw_serde]
-struct DelegateMsg {
-    delegator_address: String,
-    validator_address: String,
-    amount: String,
-    denom: String,
+/// What the plan does besides realigning the DAO stake.
+#[derive(Clone, Debug, Default)]
+struct PlanOptions {
+    /// Withdraw the rewards the plan's messages would withdraw anyway in messages of their own.
+    withdraw_rewards: bool,
+    /// Delegate each DAO's liquid funds above `reserve`.
+    compound: Option<CompoundMode>,
+    reserve: Uint128,
 }
 
-#[cw_serde]
-struct Redelegations {
-    data: Vec<RedelegateMsg>,
-    count: usize,
-    total_ubtsg: Uint128,
-}
-
-#[cw_serde]
-struct Delegations {
-    data: Vec<DelegateMsg>,
-    count: usize,
-    total_ubtsg: Uint128,
-}
-
-#[cw_serde]
-struct Undelegations {
-    data: Vec<UndelegateMsg>,
-    count: usize,
-    total_ubtsg: Uint128,
-}
-
-#[cw_serde]
-struct MessageExport {
-    redelegations: Redelegations,
-    delegations: Delegations,
-    undelegates: Undelegations,
-}
-
-pub const BITSONG_NETWORK: NetworkInfo = NetworkInfo {
-    chain_name: "Bitsong",
-    pub_address_prefix: "bitsong",
-    coin_type: 639u32,
-};
-
-pub const BITSONG_MAINNET: ChainInfo = ChainInfo {
-    kind: ChainKind::Mainnet,
-    chain_id: "bitsong-2b",
-    gas_denom: "ubtsg",
-    gas_price: 0.025,
-    grpc_urls: &["http://bitsong-grpc.polkachu.com:16090"],
-    network_info: BITSONG_NETWORK,
-    lcd_url: None,
-    fcd_url: None,
-};
-
 // todo: move to .env file
 pub const MNEMONIC: &str =
         "garage dial step tourist hint select patient eternal lesson raccoon shaft palace flee purpose vivid spend place year file life cliff winter race fox";
@@ -131,52 +79,254 @@ pub const MNEMONIC: &str =
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Network to deploy on: main, testnet, local
+    /// Network to deploy on: main, testnet, local. Every command needs it or `--chain-profile`
     #[clap(short, long)]
-    network: String,
+    network: Option<String>,
+    /// chain profile JSON with the denom, decimals, prefixes, coin type, endpoints, DAOs and targets to use instead of the network's
+    #[clap(long)]
+    chain_profile: Option<String>,
     /// whether or not to broadcast the txs formed
     #[clap(short, long)]
     broadcast: bool,
+    /// gas ceiling for a single bundle tx, bundles are shrunk until they fit
+    #[clap(long, default_value_t = DEFAULT_MAX_TX_GAS)]
+    max_tx_gas: u64,
+    /// maximum fee in the chain's denom all bundles together may spend
+    #[clap(long)]
+    fee_budget: Option<u128>,
+    /// export and broadcast a plan that failed verification, the reason is recorded with it
+    #[clap(long)]
+    override_verification: Option<String>,
+    /// gRPC endpoint to query, repeat it to fail over. Defaults to $GRPC_URLS_<CHAIN_ID>, e.g. $GRPC_URLS_BITSONG_2B, then the profile's endpoints
+    #[clap(long = "grpc-url")]
+    grpc_urls: Vec<String>,
+    /// attempts of each query before giving up, every retry goes to the next endpoint
+    #[clap(long, default_value_t = RetryPolicy::default().max_attempts)]
+    query_attempts: u32,
+    /// plan at this height instead of the latest one, queries already cached for it are not sent again
+    #[clap(long)]
+    height: Option<u64>,
+    /// withdraw the rewards the plan's messages would withdraw anyway in messages of their own, ahead of them
+    #[clap(long)]
+    withdraw_rewards: bool,
+    /// delegate each DAO's liquid funds above the reserve: under-target fills the targets left short, proportional raises them all
+    #[clap(long)]
+    compound: Option<CompoundMode>,
+    /// amount in the chain's denom each DAO keeps liquid when compounding
+    #[clap(long, default_value_t = DEFAULT_RESERVE)]
+    compound_reserve: u128,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Re-query the DAO delegations after execution, report the gaps to the targets and write a follow-up plan
+    Reconcile,
+    /// List the DAOs' unbonding entries and their liquid balance as these mature, and plan what the matured ones freed
+    Unbonding {
+        /// snapshot of an earlier plan, e.g. delegation_snapshot.json, its entries no longer unbonding are reported as matured
+        #[clap(long)]
+        snapshot: Option<String>,
+        /// send the freed funds to this address instead of delegating them to under-target validators
+        #[clap(long)]
+        reserve_address: Option<String>,
+        /// amount in the chain's denom each DAO keeps liquid
+        #[clap(long, default_value_t = DEFAULT_RESERVE)]
+        keep: u128,
+    },
+    /// Check a plan offline against the snapshot it was made from and the target CSV, exits 1 if it fails
+    Verify {
+        /// plan to check, e.g. delegation_messages.json
+        #[clap(long)]
+        plan: String,
+        /// chain state the plan starts from, e.g. delegation_snapshot.json
+        #[clap(long)]
+        snapshot: String,
+        /// target delegations, in the same CSV format as new-delegations.csv
+        #[clap(long)]
+        targets: String,
+    },
+    /// Execute every bundle of a plan on an offline fork of the snapshot and report the resulting gaps to the targets, authz grants are assumed
+    DryRun {
+        /// plan to execute, e.g. delegation_messages.json
+        #[clap(long)]
+        plan: String,
+        /// chain state to fork, e.g. delegation_snapshot.json
+        #[clap(long)]
+        snapshot: String,
+        /// target delegations, in the same CSV format as new-delegations.csv
+        #[clap(long)]
+        targets: String,
+        /// address the bundles are wrapped in authz for, the broadcasting wallet
+        #[clap(long)]
+        grantee: String,
+    },
+    /// Compare two plans, two snapshots or two target CSVs, e.g. the last epoch's and this one's
+    Diff {
+        /// earlier plan, snapshot or target CSV
+        #[clap(long)]
+        old: String,
+        /// later one of the same kind
+        #[clap(long)]
+        new: String,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
     // parse cargo command arguments for network type
     let args = Args::parse();
+    // logs any errors
+    env_logger::init();
+
+    let profile = match (&args.chain_profile, args.network.as_deref()) {
+        (Some(path), _) => ChainProfile::load(path)?,
+        (None, Some(network)) => ChainProfile::builtin(network).ok_or_else(|| {
+            anyhow::anyhow!(
+                "no built-in profile for network {}, pass --chain-profile",
+                network
+            )
+        })?,
+        (None, None) => anyhow::bail!("pass --network or --chain-profile"),
+    };
+
+    // reviewers verify a plan without connecting to the chain
+    if let Some(Command::Verify {
+        plan,
+        snapshot,
+        targets,
+    }) = &args.command
+    {
+        let report = verify_plan_files(&profile, plan, snapshot, targets)?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !report.passed {
+            ::std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    let delegation_dao_addrs: Vec<String> = [
-        "bitsong166d42nyufxrh3jps5wx3egdkmvvg7jl6k33yut".into(),
-        "bitsong1nphhydjshzjevd03afzlce0xnlrnsm27hy9hgd".into(),
-        "bitsong1tgzday8yewn8n5j0prgsc9t5r3gg2cwnyf9jlv".into(),
-    ]
-    .to_vec();
-
-    let bitsong_chain: ChainInfoOwned = match args.network.as_str() {
-        "main" => BITSONG_MAINNET.to_owned(),
-        // "testnet" => BITSONG_TESTNET.to_owned(),
-        // "local" => LOCAL_NETWORK1.to_owned(),
-        _ => panic!("Invalid network"),
+    if let Some(Command::Diff { old, new }) = &args.command {
+        let report = diff(&load_epoch(old)?, &load_epoch(new)?)?;
+        print!("{}", report.table(&profile));
+        if report.is_empty() {
+            println!("No differences");
+        }
+        serialize_and_print(
+            serde_json::to_string_pretty(&report)?,
+            DIFF_JSON.to_string(),
+        );
+        return Ok(());
+    }
+
+    anyhow::ensure!(
+        !profile.dao_addrs.is_empty(),
+        "chain profile {} lists no dao_addrs",
+        profile.chain_id
+    );
+    let delegation_dao_addrs = profile.dao_addrs.clone();
+
+    if let Some(Command::DryRun {
+        plan,
+        snapshot,
+        targets,
+        grantee,
+    }) = &args.command
+    {
+        let report = dry_run_plan_files(
+            &profile,
+            plan,
+            snapshot,
+            targets,
+            grantee,
+            &delegation_dao_addrs,
+        )?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !report.is_aligned() {
+            ::std::process::exit(1);
+        }
+        return Ok(());
     }
-    .into();
+
+    let mut chain_info = profile.chain_info();
+
+    // Create a new runtime for async execution
+    let rt = Runtime::new()?;
+
+    // queries fail over between the endpoints that serve the chain and are up to date
+    dotenv::dotenv().ok();
+    let urls = configured_urls(&args.grpc_urls, &profile.chain_id, &profile.grpc_urls);
+    let endpoints = rt.block_on(Endpoints::connect(
+        &urls,
+        &chain_info.chain_id,
+        RetryPolicy {
+            max_attempts: args.query_attempts,
+            ..Default::default()
+        },
+    ))?;
+    chain_info.grpc_urls = endpoints.urls();
 
     // connect to chain with mnemonic
-    let mut chain = DaemonBuilder::new(bitsong_chain.clone())
+    let mut chain = DaemonBuilder::new(chain_info.clone())
         .mnemonic(MNEMONIC)
         .build()?;
 
-    // create client
-    let staking_query_client: Staking = chain.querier();
-    let bank_query_client: Bank = chain.querier();
-    let node_query: queriers::Node = chain.node_querier();
+    // every planning query reads the same block, the snapshot and plan record its height
+    let height = args.height.unwrap_or_else(|| endpoints.common_height());
+    let pinned_querier = QueryCache::new(
+        rt.block_on(ChainQuerier::pinned(
+            endpoints.clone(),
+            &profile.consensus_prefix,
+            height,
+        ))?,
+        QUERY_CACHE_DIR,
+        &profile.chain_id,
+        height,
+    );
 
-    // Create a new runtime for async execution
-    let rt = Runtime::new()?;
+    if let Some(Command::Reconcile) = &args.command {
+        return rt.block_on(reconcile_delegations(
+            &profile,
+            &pinned_querier,
+            &pinned_querier,
+            &delegation_dao_addrs,
+            height,
+            args.override_verification,
+        ));
+    }
+
+    if let Some(Command::Unbonding {
+        snapshot,
+        reserve_address,
+        keep,
+    }) = &args.command
+    {
+        let earlier = snapshot.as_deref().map(ChainSnapshot::load).transpose()?;
+        return rt.block_on(track_unbonding(
+            &profile,
+            &pinned_querier,
+            &pinned_querier,
+            &delegation_dao_addrs,
+            height,
+            earlier,
+            Uint128::new(*keep),
+            reserve_address.as_deref(),
+        ));
+    }
 
     // Execute the async function using the runtime
     if let Err(err) = rt.block_on(realign_delegations(
-        staking_query_client,
-        bank_query_client,
+        &profile,
+        &pinned_querier,
+        &pinned_querier,
+        &pinned_querier,
         &delegation_dao_addrs,
-        chain.node_querier().latest_block()?.height,
+        height,
+        &PlanOptions {
+            withdraw_rewards: args.withdraw_rewards,
+            compound: args.compound,
+            reserve: Uint128::new(args.compound_reserve),
+        },
+        args.override_verification,
     )) {
         log::error!("{}", err);
         err.chain()
@@ -188,343 +338,232 @@ fn main() -> anyhow::Result<()> {
 
     if args.broadcast {
         //  Broadcast del/redel/undel msgs
-        let wallet = chain.sender_mut();
+        let wallet = chain.sender_mut().clone();
+        // broadcasting follows the chain as it moves
+        let live_querier = ChainQuerier::new(endpoints.clone(), &profile.consensus_prefix);
+        let mut tracker = GrpcTxTracker::new(endpoints);
         form_and_broadcast_obligated_msgs(
             rt,
-            node_query,
-            wallet.clone(),
+            &profile,
+            &live_querier,
+            &wallet,
+            &mut tracker,
             RAW_MSG_JSON,
             delegation_dao_addrs,
+            GasLimits {
+                max_tx_gas: args.max_tx_gas,
+                fee_budget: args.fee_budget.map(|amount| Coin {
+                    denom: profile.denom.clone(),
+                    amount: Uint128::new(amount),
+                }),
+            },
         )?;
     }
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn realign_delegations(
-    staking_query_client: Staking,
-    bank_client: Bank,
+    chain: &ChainProfile,
+    staking: &impl StakingBackend,
+    bank: &impl BankBackend,
+    distribution: &impl DistributionBackend,
     dao_addrs: &[String],
     height: u64,
+    options: &PlanOptions,
+    override_verification: Option<String>,
 ) -> anyhow::Result<()> {
-    // logs any errors
-    env_logger::init();
-    // Get unbonded validators
-    let unbonded_vals = staking_query_client
-        ._validators(queriers::StakingBondStatus::Unbonded)
-        .await?;
-    let unbonding_vals = staking_query_client
-        ._validators(queriers::StakingBondStatus::Unbonding)
-        .await?;
-
-    let val_historical = staking_query_client
-        ._historical_info(height.try_into().unwrap())
-        .await?;
-
     // Load new delegations from CSV file
-    let all_oblgated_dels = load_new_delegations(NEW_DELS_FILE, false);
+    let targets_csv = chain.targets_csv()?;
+    let all_oblgated_dels = load_new_delegations(targets_csv, false)?;
     let obligated_delegations = all_oblgated_dels.delegations;
-    let total_obligated_delegations = Uint128::from(all_oblgated_dels.total);
+    let total_obligated_delegations = all_oblgated_dels.total;
+    if let Some(expected) = chain.obligated_total {
+        anyhow::ensure!(
+            total_obligated_delegations == expected,
+            "{} obligates {}{}, expected {}{}",
+            targets_csv,
+            total_obligated_delegations,
+            chain.denom,
+            expected,
+            chain.denom
+        );
+    }
 
-    println!("Running Bitsong Delegation Realignment Protocol...");
     println!(
-        "{} dels with {}ubtsg",
+        "Running {} Delegation Realignment Protocol...",
+        chain.chain_name
+    );
+    println!(
+        "{} dels with {}{}",
         obligated_delegations.len(),
-        Decimal::from_atomics(
-            total_obligated_delegations.checked_mul(1000000u128.into())?,
-            6
-        )?
-        .to_string()
+        total_obligated_delegations,
+        chain.denom
     );
 
-    // collect all dao delegations
-    let mut all_dao_delegations = Vec::new();
-    let mut dao_entities = Vec::new();
-    let ommited_vals: Vec<String> = vec![
-        "bitsongvaloper19ah9302mh80pvv5zeztdr6qcqk6z52frn6rjj5".into(),
-        "bitsongvaloper1wf3q0a3uzechxvf27reuqts8nqm45sn2yq26g3".into(),
-        "bitsongvaloper10fg3yklae97g8ueh5ut29mlwz8fdr6z8zrak6x".into(),
-        "bitsongvaloper1fkj2cn209yeexxyets98evrcmmds23hck0lyzq".into(),
-        "bitsongvaloper1wetqg989uyj3mpk07h8yt3qvu2cdlsv7fp3zda".into(),
-        "bitsongvaloper1jxv0u20scum4trha72c7ltfgfqef6nscl86wxa".into(),
-    ];
-    for dao in dao_addrs {
-        let mut next_key = None;
-        loop {
-            let response = staking_query_client
-                ._delegator_delegations(&Addr::unchecked(dao), next_key)
-                .await?;
-
-            all_dao_delegations.extend(response.delegation_responses);
-            match response.pagination {
-                Some(pagination) => {
-                    if pagination.next_key.is_empty() {
-                        break;
-                    }
-                    next_key = Some(PageRequest {
-                        key: pagination.next_key,
-                        offset: 0,
-                        limit: 100,
-                        count_total: false,
-                        reverse: false,
-                    });
-                }
-                None => {
-                    break;
-                }
-            }
-        }
-
-        let entity_btsg_addr = bank_client
-            ._balance(&Addr::unchecked(dao), Some("ubtsg".into()))
-            .await?;
-
-        let total_non_team_de: Vec<&DelegationResponse> = all_dao_delegations
-            .iter()
-            .filter(|a| {
-                !ommited_vals.contains(&a.delegation.clone().expect("msg").validator_address)
-            })
-            .collect();
-
-        let new_total_delegation_amount = obligated_delegations
-            .clone()
-            .iter()
-            .find(|d| d.del_addr == *dao)
-            .map(|a| a.amount)
-            .into_iter()
-            .sum();
-        dao_entities.push(DelegationDaoEntity {
-            dao_add: dao.to_string(),
-
-            current_balance: entity_btsg_addr[0].clone(),
-            total_delegation_count: total_non_team_de.len(),
-            current_delegation: total_non_team_de
-                .clone()
-                .iter()
-                .map(|a| Uint128::from_str(&a.balance.clone().expect("msg").amount).expect("msg"))
-                .sum(),
-            obligated_delegation: new_total_delegation_amount,
-        });
-    }
-
-    // current_vals - array of validators and the DAOs delegations to them
-    let mut current_vals: Vec<AlignedValidator> = Vec::new();
-
-    // all delegations, enumerated
-    for (i, dels) in all_dao_delegations.clone().iter_mut().enumerate() {
-        let del = dels.delegation.clone().unwrap();
-        if !ommited_vals.contains(&del.validator_address) {
-            let balance = Uint128::from_str(&dels.balance.clone().unwrap().amount)
-                .expect("Failed to parse balance");
-            if !balance.is_zero() {
-                if let Some(exists) = current_vals
-                    .iter_mut()
-                    .find(|a| a.operator_addr == del.validator_address)
-                {
-                    exists.current_delegations.push(Delegation {
-                        del_addr: del.delegator_address,
-                        operator_addr: del.validator_address,
-                        amount: balance,
-                    });
-                } else {
-                    // initialize aligned validator
-                    let target_amount = obligated_delegations
-                        .iter()
-                        .find(|a| a.operator_addr == del.validator_address)
-                        .map_or(Uint128::zero(), |a| a.amount);
-                    if target_amount != Uint128::zero() {
-                        current_vals.push(AlignedValidator {
-                            operator_addr: del.validator_address.clone(),
-                            current_delegations: vec![Delegation {
-                                del_addr: del.delegator_address,
-                                operator_addr: del.validator_address.clone(),
-                                amount: balance,
-                            }],
-                            new_delegation_amount: target_amount,
-                        });
-                    }
-                }
-            }
-        } else {
-            // do not add to this list of delegators, and disregard remaining list
-            all_dao_delegations.remove(i);
-        }
-    }
-    //assert we omit private agreement validator operators
-    assert!(current_vals
-        .iter()
-        .all(|cv| !ommited_vals.contains(&cv.operator_addr)));
-
-    // Add any completely new validators from aligned_vals that don't exist in current_vals
-    for obligated in &obligated_delegations {
-        if !current_vals
-            .iter()
-            .any(|cv| cv.operator_addr == obligated.operator_addr)
-        {
-            current_vals.push(AlignedValidator {
-                operator_addr: obligated.operator_addr.clone(),
-                current_delegations: Vec::new(),
-                new_delegation_amount: obligated.amount,
-            });
+    let (snapshot, export, obligated_delegations) = plan_realignment(
+        chain,
+        staking,
+        bank,
+        distribution,
+        dao_addrs,
+        height,
+        &obligated_delegations,
+        options,
+    )
+    .await?;
+    // proportional compounding raised the targets, `verify` and `dry-run` need them
+    if options.compound == Some(CompoundMode::Proportional) {
+        let mut csv = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_path(COMPOUNDED_TARGETS_CSV)?;
+        for target in &obligated_delegations {
+            csv.write_record([target.operator_addr.clone(), target.amount.to_string()])?;
         }
+        csv.flush()?;
+        println!("Compounded targets written to {}", COMPOUNDED_TARGETS_CSV);
     }
 
-    // Modify the main delegation processing to use this debug function
-    debug_delegation_tracking(&all_dao_delegations, &obligated_delegations)?;
-
-    let mut all_redels: Vec<Delegation> = Vec::new();
-    let mut all_dels: Vec<Delegation> = Vec::new();
-    let mut del_total = Uint128::zero();
-    let mut redel_total = Uint128::zero();
-    let mut undel_total = Uint128::zero();
-
-    for mut val in current_vals {
-        // Calculate total current delegation to this validator
-        let mut total_current_del = Uint128::zero();
-        for cd in &val.current_delegations {
-            total_current_del += cd.amount;
-        }
-
-        let is_unbonded = unbonded_vals.iter().any(|v| v.address == val.operator_addr);
-        let is_unbonding = unbonding_vals
-            .iter()
-            .any(|v| v.address == val.operator_addr);
-        let is_jailed = match &val_historical.hist {
-            Some(hist) => hist
-                .valset
-                .iter()
-                .any(|v| v.operator_address == val.operator_addr && v.jailed),
-            None => false,
-        };
-
-        let sum_dao_delegation = val
-            .current_delegations
-            .iter()
-            .map(|a| a.amount)
-            .sum::<Uint128>();
-
-        if val.new_delegation_amount.is_zero() || is_unbonded || is_unbonding || is_jailed {
-            let reason = if is_unbonded {
-                "unbonded"
-            } else if is_unbonding {
-                "unbonding"
-            } else if val.new_delegation_amount.is_zero() {
-                "no-obligation-this-round"
-            } else {
-                "jailed"
-            };
-
-            println!("val.current_delegations: {:#?}", val.current_delegations);
-            println!(
-                "Will remove {}ubtsg from {} validator {}",
-                sum_dao_delegation, reason, val.operator_addr
-            );
-
-            redel_total += sum_dao_delegation;
-            all_redels.extend(val.current_delegations);
-            continue;
-        } else if total_current_del > val.new_delegation_amount {
-            // Need to reduce delegations, get difference and try to create
-            let diff = total_current_del
-                .checked_sub(val.new_delegation_amount)
-                .expect("dang");
-
-            // Sort current delegations by amount (largest first to optimize processing)
-            let mut sorted_delegations = val.current_delegations.clone();
-            sorted_delegations.sort_by(|a, b| b.amount.cmp(&a.amount));
-
-            let mut remaining_diff = diff;
-            let mut redelegations_to_add = Vec::new();
-
-            for delegation in sorted_delegations {
-                if remaining_diff.is_zero() {
-                    break; // We've consumed all the difference
-                }
-                if delegation.amount <= remaining_diff {
-                    // We can use this entire delegation
-                    remaining_diff = remaining_diff
-                        .checked_sub(delegation.amount)
-                        .expect("subtraction overflow");
-                    redelegations_to_add.push(delegation);
-                } else {
-                    // We need only part of this delegation
-                    let mut partial_delegation = delegation.clone();
-                    partial_delegation.amount = remaining_diff;
-                    redelegations_to_add.push(partial_delegation);
-                    remaining_diff = Uint128::zero();
-                }
-            }
-
-            // Add the selected delegations to all_redels
-            all_redels.extend(redelegations_to_add.clone());
-            println!(
-                "Will remove {}ubtsg from {}",
-                redelegations_to_add
-                    .iter()
-                    .map(|a| a.amount)
-                    .sum::<Uint128>(),
-                val.operator_addr
-            );
+    // record the state the plan is computed from, broadcasting re-checks it before every bundle
+    serialize_and_print(
+        serde_json::to_string_pretty(&snapshot)?,
+        SNAPSHOT_JSON.to_string(),
+    );
 
-            // only set remaining difference to unbond.
-        } else if total_current_del < val.new_delegation_amount {
-            //  need to delegate
-            let diff = val
-                .new_delegation_amount
-                .checked_sub(total_current_del)
-                .expect("darn");
+    let treasury = treasury_report(
+        &snapshot,
+        &export,
+        &obligated_delegations,
+        dao_addrs,
+        &chain.omitted(),
+    )?;
+    println!("\n--- DAO TREASURY AT HEIGHT {} ---", treasury.height);
+    print!("{}", treasury.table(chain));
+    serialize_and_print(
+        serde_json::to_string_pretty(&treasury)?,
+        TREASURY_JSON.to_string(),
+    );
 
-            let mut old_delegations = val.current_delegations.clone();
-            old_delegations.sort_by(|a, b| b.amount.cmp(&a.amount));
+    // assert with the new information that the obligated validators will have the correct balance once delegations are applied,
+    // a plan that does not is never exported and so never broadcast
+    verify_and_export(
+        chain,
+        &export,
+        &snapshot,
+        &obligated_delegations,
+        override_verification,
+        RAW_MSG_JSON,
+        VERIFICATION_JSON,
+    )?;
+
+    let bundles = schedule_bundles(&export.entries(), dao_addrs, MAX_MSGS_PER_BUNDLE)?;
+    let report = epoch_report(
+        chain,
+        &snapshot,
+        &export,
+        &obligated_delegations,
+        &treasury,
+        dao_addrs,
+        &chain.omitted(),
+        bundles.len(),
+    )?;
+    write_report(chain, &report)?;
+    println!(
+        "Report for plan {} written to {} and {}",
+        report.plan_hash, REPORT_MD, REPORT_HTML
+    );
+    // flat tables to cross-check against the program's spreadsheet
+    write_validators_csv(&report, chain, File::create(VALIDATORS_CSV)?)?;
+    write_messages_csv(&export, chain, File::create(MESSAGES_CSV)?)?;
+    println!(
+        "Spreadsheet exports written to {} and {}",
+        VALIDATORS_CSV, MESSAGES_CSV
+    );
 
-            let mut remaining_diff = diff;
-            let mut delegation_to_add = Vec::new();
+    Ok(())
+}
 
-            for old in old_delegations {
-                if remaining_diff.is_zero() {
-                    break; // We've consumed all the difference
-                }
+/// Writes the epoch report as JSON, Markdown and HTML.
+fn write_report(chain: &ChainProfile, report: &EpochReport) -> anyhow::Result<()> {
+    serialize_and_print(
+        serde_json::to_string_pretty(report)?,
+        REPORT_JSON.to_string(),
+    );
+    serialize_and_print(report.markdown(chain), REPORT_MD.to_string());
+    serialize_and_print(report.html(chain), REPORT_HTML.to_string());
+    Ok(())
+}
 
-                if old.amount <= remaining_diff {
-                    // We can use this entire delegation
-                    remaining_diff = remaining_diff
-                        .checked_sub(old.amount)
-                        .expect("subtraction overflow");
-                    del_total += old.amount;
-                    delegation_to_add.push(old);
-                } else {
-                    // We need only part of this delegation
-                    let mut partial_delegation = old.clone();
-                    partial_delegation.amount = remaining_diff;
-                    delegation_to_add.push(partial_delegation);
-                    remaining_diff = Uint128::zero();
-                }
-            }
+/// Fetches the DAOs' delegations and balances and computes the messages that move them onto
+/// `obligated_delegations`, along with the snapshot they were computed from.
+#[allow(clippy::too_many_arguments)]
+async fn plan_realignment(
+    chain: &ChainProfile,
+    staking: &impl StakingBackend,
+    bank: &impl BankBackend,
+    distribution: &impl DistributionBackend,
+    dao_addrs: &[String],
+    height: u64,
+    obligated_delegations: &[Delegation],
+    options: &PlanOptions,
+) -> anyhow::Result<(ChainSnapshot, MessageExport, Vec<Delegation>)> {
+    // fetch every DAO's delegations, shares, balance, unbonding entries and rewards, a few DAOs at a time
+    let fetched: Vec<_> = stream::iter(dao_addrs)
+        .map(|dao| async move {
+            futures::try_join!(
+                staking.delegator_stake(dao),
+                bank.balance(dao, &chain.denom),
+                staking.unbonding_delegations(dao),
+                distribution.pending_rewards(dao, &chain.denom)
+            )
+        })
+        .buffered(QUERY_CONCURRENCY)
+        .try_collect()
+        .await?;
 
-            // Add the selected delegations to all_redels
-            all_dels.extend(delegation_to_add);
-        }
+    // collect all dao delegations
+    let mut all_dao_delegations = Vec::new();
+    let mut liquid = BTreeMap::new();
+    let mut shares = Vec::new();
+    let mut unbonding = Vec::new();
+    let mut rewards = Vec::new();
+    let ommited_vals = &chain.omitted_validators;
+    for (dao, ((delegations, dao_shares), balance, dao_unbonding, dao_rewards)) in
+        dao_addrs.iter().zip(fetched)
+    {
+        all_dao_delegations.extend(delegations);
+        shares.extend(dao_shares);
+        liquid.insert(dao.clone(), balance);
+        unbonding.extend(dao_unbonding);
+        rewards.extend(dao_rewards);
     }
 
-    //         // Sort del_map by amount in ascending order
-    all_dels.sort_by(|a, b| a.amount.cmp(&b.amount));
-    // Sort redel_map by amount in decending order
-    all_redels.sort_by(|a, b| b.amount.cmp(&a.amount));
-    println!("sorted");
+    let snapshot = ChainSnapshot {
+        unbonding,
+        rewards,
+        shares,
+        ..chain_snapshot(
+            staking,
+            height,
+            &all_dao_delegations,
+            obligated_delegations,
+            liquid,
+        )
+        .await?
+    };
+    print_target_classes(&snapshot, obligated_delegations);
+
+    // private agreement delegations are never moved
+    all_dao_delegations.retain(|d| !ommited_vals.contains(&d.operator_addr));
+    debug_delegation_tracking(chain, &all_dao_delegations, obligated_delegations)?;
 
     // Generate redelegation and delegation messages
-    // In main processing function
     let (redelegation_msgs, delegation_msgs, undelegate_msgs) = optimize_delegations(
-        all_dao_delegations
-            .iter()
-            .map(|a| Delegation {
-                del_addr: a.delegation.clone().expect("msg").delegator_address,
-                operator_addr: a.delegation.clone().expect("msg").validator_address,
-                amount: Uint128::from_str(a.balance.clone().expect("msg").amount.as_str()).unwrap(),
-            })
-            .collect(), // Current delegations
-        &obligated_delegations, // Target delegations
-        "ubtsg",
+        all_dao_delegations,   // Current delegations
+        obligated_delegations, // Target delegations
+        &snapshot.liquid,
+        &chain.denom,
     );
 
     // Print summary of delegation changes
@@ -538,8 +577,9 @@ async fn realign_delegations(
     }
 
     println!(
-        "Total to delegate: {}. Amount: {}",
-        Decimal::from_atomics(total_del, 6)?,
+        "Total to delegate: {} {}. amount: {}",
+        chain.display_amount(total_del),
+        chain.display_denom,
         delegation_msgs.len()
     );
 
@@ -548,33 +588,105 @@ async fn realign_delegations(
     // println!("Total to redelegate: {}", redel_map.1);
     for redel in &redelegation_msgs {
         let uint_amnt = Uint128::from_str(redel.amount.clone().expect("shoot").amount.as_str())?;
-        let amnt = Decimal::from_atomics(uint_amnt, 6)?;
         total_redel += uint_amnt;
     }
     println!(
-        "Total to redelegate: {} BTSG. amount: {}",
-        Decimal::from_atomics(total_redel, 6)?,
+        "Total to redelegate: {} {}. amount: {}",
+        chain.display_amount(total_redel),
+        chain.display_denom,
         redelegation_msgs.len()
     );
 
     let mut total_undel = Uint128::zero();
     for undel in &undelegate_msgs {
         let uint_amnt = Uint128::from_str(undel.amount.clone().expect("shoot").amount.as_str())?;
-        let amnt = Decimal::from_atomics(uint_amnt, 6)?;
+        let amnt = chain.display_amount(uint_amnt);
         println!(
-            "{} →  {}BTSG → {}",
-            undel.delegator_address, amnt, undel.validator_address,
+            "{} →  {}{} → {}",
+            undel.delegator_address, amnt, chain.display_denom, undel.validator_address,
         );
         total_undel += uint_amnt;
     }
 
     println!(
-        "Total to start unbond: {} BTSG",
-        Decimal::from_atomics(total_undel, 6)?
+        "Total to start unbond: {} {}",
+        chain.display_amount(total_undel),
+        chain.display_denom
     );
 
-    // Uncomment and modify the export creation and serialization at the end of the function
-    let export = MessageExport {
+    let mut export = message_export(
+        height,
+        &redelegation_msgs,
+        &delegation_msgs,
+        &undelegate_msgs,
+    )?;
+
+    // amounts are planned in tokens, slashed validators' delegations move fewer tokens per share
+    for adjustment in fit_to_shares(&mut export, &snapshot)? {
+        println!(
+            "⚠️ {}: {} moves {}{} instead of {}{} from {}, all its shares allow",
+            adjustment.entry,
+            adjustment.delegator,
+            adjustment.adjusted,
+            chain.denom,
+            adjustment.planned,
+            chain.denom,
+            adjustment.validator
+        );
+    }
+
+    // liquid funds, rewards withdrawn by the plan included, is delegated in the same plan
+    let mut targets = obligated_delegations.to_vec();
+    if let Some(mode) = options.compound {
+        let compounding = compound(
+            &mut export,
+            &snapshot,
+            obligated_delegations,
+            dao_addrs,
+            mode,
+            options.reserve,
+            &chain.denom,
+        )?;
+        println!("\n--- COMPOUNDING ({}) ---", mode);
+        for (dao, amount) in &compounding.compounded {
+            println!(
+                "{} delegates {} {} above its reserve",
+                dao,
+                chain.display_amount(*amount),
+                chain.display_denom
+            );
+        }
+        targets = compounding.targets;
+    }
+
+    // redelegating, delegating or undelegating withdraws a delegation's rewards to the DAO
+    if options.withdraw_rewards {
+        prepend_withdrawals(&mut export, &snapshot.rewards, &chain.denom)?;
+    }
+    let msgs: Vec<PlanMsg> = export.entries().into_iter().map(|e| e.msg).collect();
+    for (dao, amount) in withdrawn_rewards(&snapshot.rewards, &msgs) {
+        println!(
+            "{} gets {} {} of rewards withdrawn to its liquid balance",
+            dao,
+            chain.display_amount(amount),
+            chain.display_denom
+        );
+    }
+
+    Ok((snapshot, export, targets))
+}
+
+/// Converts the planner's messages, computed from the state at `height`, into the JSON export.
+fn message_export(
+    height: u64,
+    redelegation_msgs: &[MsgBeginRedelegate],
+    delegation_msgs: &[MsgDelegate],
+    undelegate_msgs: &[MsgUndelegate],
+) -> anyhow::Result<MessageExport> {
+    let mut export = MessageExport {
+        height,
+        withdrawals: Default::default(),
+        sends: Default::default(),
         redelegations: Redelegations {
             data: redelegation_msgs
                 .iter()
@@ -590,7 +702,7 @@ async fn realign_delegations(
                 })
                 .collect(),
             count: redelegation_msgs.len(),
-            total_ubtsg: redel_total,
+            total_ubtsg: Uint128::zero(),
         },
         delegations: Delegations {
             data: delegation_msgs
@@ -606,7 +718,7 @@ async fn realign_delegations(
                 })
                 .collect(),
             count: delegation_msgs.len(),
-            total_ubtsg: del_total,
+            total_ubtsg: Uint128::zero(),
         },
         undelegates: Undelegations {
             data: undelegate_msgs
@@ -619,174 +731,613 @@ async fn realign_delegations(
                 })
                 .collect(),
             count: undelegate_msgs.len(),
-            total_ubtsg: undel_total,
+            total_ubtsg: Uint128::zero(),
         },
     };
+    export.recompute_totals()?;
+    Ok(export)
+}
 
-    // Serialize to JSON
-    let json = serde_json::to_string_pretty(&export).expect("Failed to serialize messages to JSON");
+/// Compares the live DAO delegations with the targets and writes a follow-up plan for what is left.
+async fn reconcile_delegations(
+    chain: &ChainProfile,
+    staking: &impl StakingBackend,
+    bank: &impl BankBackend,
+    dao_addrs: &[String],
+    height: u64,
+    override_verification: Option<String>,
+) -> anyhow::Result<()> {
+    let targets = load_new_delegations(chain.targets_csv()?, false)?.delegations;
 
-    serialize_and_print(json.clone(), RAW_MSG_JSON.to_string());
+    let mut live = Vec::new();
+    let mut liquid = BTreeMap::new();
+    for dao in dao_addrs {
+        liquid.insert(dao.clone(), bank.balance(dao, &chain.denom).await?);
+        live.extend(
+            staking
+                .delegator_delegations(dao)
+                .await?
+                .into_iter()
+                .filter(|d| !chain.omitted_validators.contains(&d.operator_addr)),
+        );
+    }
+
+    let report = reconcile(height, &live, &targets, dao_addrs);
+    println!("\n--- RECONCILIATION AT HEIGHT {} ---", report.height);
+    println!(
+        "Live DAO delegation: {} {}, target: {} {}, {} validators aligned",
+        chain.display_amount(report.total_live),
+        chain.display_denom,
+        chain.display_amount(report.total_target),
+        chain.display_denom,
+        report.aligned_validators
+    );
+    for gap in &report.validator_gaps {
+        println!(
+            "Validator {}: Live={}, Target={}, Surplus={}, Deficit={}",
+            gap.operator_addr,
+            chain.display_amount(gap.live),
+            chain.display_amount(gap.target),
+            chain.display_amount(gap.surplus()),
+            chain.display_amount(gap.deficit())
+        );
+    }
+    for dao in &report.daos {
+        println!(
+            "DAO {}: on target validators={}, elsewhere={} ({})",
+            dao.dao,
+            chain.display_amount(dao.on_target),
+            chain.display_amount(dao.off_target),
+            dao.off_target_validators.join(", ")
+        );
+    }
+    serialize_and_print(
+        serde_json::to_string_pretty(&report)?,
+        RECONCILE_JSON.to_string(),
+    );
 
-    // assert with the new information that the obligated validators will have the correct balance once delegations are applied
-    verify_final_state(RAW_MSG_JSON, &all_dao_delegations, &obligated_delegations)?;
+    if report.is_aligned() {
+        println!("✅ RECONCILED: on-chain delegations match the targets");
+        return Ok(());
+    }
 
+    let (redelegation_msgs, delegation_msgs, undelegate_msgs) =
+        optimize_delegations(live.clone(), &targets, &liquid, &chain.denom);
+    let followup = message_export(
+        height,
+        &redelegation_msgs,
+        &delegation_msgs,
+        &undelegate_msgs,
+    )?;
+    let snapshot = ChainSnapshot {
+        height,
+        delegations: live,
+        liquid,
+        ..Default::default()
+    };
+    verify_and_export(
+        chain,
+        &followup,
+        &snapshot,
+        &targets,
+        override_verification,
+        FOLLOWUP_MSG_JSON,
+        RECONCILE_VERIFICATION_JSON,
+    )?;
+    println!(
+        "❌ {} validators off target, follow-up plan with {} msgs written to {}",
+        report.validator_gaps.len(),
+        followup.redelegations.count + followup.delegations.count + followup.undelegates.count,
+        FOLLOWUP_MSG_JSON
+    );
     Ok(())
 }
 
-fn form_and_broadcast_obligated_msgs(
-    rt: Runtime,
-    node_query: queriers::Node,
-    mut wallet: Wallet,
-    json: &str,
-    dao_addrs: Vec<String>,
-) -> anyhow::Result<()> {
-    // load all msgs to broadcast
-    let mut mut_wallet = wallet;
-
-    // load json msgs
-    let file_content = std::fs::read_to_string(json.to_string())?;
-    let obligated_export: MessageExport = serde_json::from_str(&file_content)?;
+fn verify_plan_files(
+    chain: &ChainProfile,
+    plan: &str,
+    snapshot: &str,
+    targets: &str,
+) -> anyhow::Result<VerifyReport> {
+    let export: MessageExport = serde_json::from_str(
+        &std::fs::read_to_string(plan)
+            .map_err(|e| anyhow::anyhow!("failed to read plan {}: {}", plan, e))?,
+    )?;
+    let snapshot = ChainSnapshot::load(snapshot)?;
+    let targets = load_new_delegations(targets, false)?.delegations;
+    verify_plan(&export, &snapshot, &targets, &chain.omitted())
+}
 
-    for dao in dao_addrs {
-        mut_wallet.set_authz_granter(&Addr::unchecked(&dao));
-
-        let dao_msgs = filter_obligated_msgs(obligated_export.clone().clone(), dao);
-
-        // Combine all messages into a single vector
-        let mut all_msgs: Vec<cosmrs::Any> = Vec::new();
-        all_msgs.extend(dao_msgs.0.iter().map(|msg| cosmrs::Any {
-            type_url: "/cosmos.staking.v1beta1.MsgBeginRedelegate".to_string(),
-            value: msg.clone().into_any().unwrap().value,
-        }));
-        all_msgs.extend(dao_msgs.1.iter().map(|msg| cosmrs::Any {
-            type_url: "/cosmos.staking.v1beta1.MsgDelegate".to_string(),
-            value: msg.clone().into_any().unwrap().value,
-        }));
-        all_msgs.extend(dao_msgs.2.iter().map(|msg| cosmrs::Any {
-            type_url: "/cosmos.staking.v1beta1.MsgUndelegate".to_string(),
-            value: msg.clone().into_any().unwrap().value,
-        }));
-
-        if all_msgs.is_empty() {
-            continue;
-        }
+fn dry_run_plan_files(
+    chain: &ChainProfile,
+    plan: &str,
+    snapshot: &str,
+    targets: &str,
+    grantee: &str,
+    dao_addrs: &[String],
+) -> anyhow::Result<ReconcileReport> {
+    let export: MessageExport = serde_json::from_str(
+        &std::fs::read_to_string(plan)
+            .map_err(|e| anyhow::anyhow!("failed to read plan {}: {}", plan, e))?,
+    )?;
+    let snapshot = ChainSnapshot::load(snapshot)?;
+    let targets = load_new_delegations(targets, false)?.delegations;
+
+    // the fork is seeded entirely from the snapshot, no node is contacted
+    let rt = Runtime::new()?;
+    let remote = offline_remote(&rt, &chain.chain_id, &chain.account_prefix)?;
+    dry_run_plan(
+        remote,
+        chain,
+        &snapshot,
+        &export,
+        dao_addrs,
+        grantee,
+        &targets,
+        &chain.omitted(),
+        MAX_MSGS_PER_BUNDLE,
+    )
+}
 
-        // Calculate number of bundles needed (32 msgs per bundle)
-        let num_bundles = (all_msgs.len() as f64).ceil() / 32 as f64;
+/// Reads a target CSV, or a plan or snapshot JSON, for `diff`.
+fn load_epoch(path: &str) -> anyhow::Result<Epoch> {
+    if path.ends_with(".csv") {
+        return Ok(Epoch::Targets(
+            load_new_delegations(path, false)?.delegations,
+        ));
+    }
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path, e))?;
+    if let Ok(export) = serde_json::from_str::<MessageExport>(&content) {
+        return Ok(Epoch::Plan(export));
+    }
+    serde_json::from_str::<ChainSnapshot>(&content)
+        .map(Epoch::Snapshot)
+        .map_err(|e| anyhow::anyhow!("{} is neither a plan nor a snapshot: {}", path, e))
+}
 
-        // Broadcast each bundle
-        for i in 0..num_bundles as usize {
-            let start = i * 32;
-            let end = core::cmp::min(start + 32, all_msgs.len());
-            let bundle = all_msgs[start..end].to_vec();
+/// Reports the DAOs' unbonding entries and writes a plan for their liquid funds above `keep`.
+#[allow(clippy::too_many_arguments)]
+async fn track_unbonding(
+    chain: &ChainProfile,
+    staking: &impl StakingBackend,
+    bank: &impl BankBackend,
+    dao_addrs: &[String],
+    height: u64,
+    earlier: Option<ChainSnapshot>,
+    keep: Uint128,
+    reserve_address: Option<&str>,
+) -> anyhow::Result<()> {
+    let targets = load_new_delegations(chain.targets_csv()?, false)?.delegations;
+
+    let fetched: Vec<_> = stream::iter(dao_addrs)
+        .map(|dao| async move {
+            futures::try_join!(
+                staking.delegator_delegations(dao),
+                bank.balance(dao, &chain.denom),
+                staking.unbonding_delegations(dao),
+            )
+        })
+        .buffered(QUERY_CONCURRENCY)
+        .try_collect()
+        .await?;
+    let mut delegations = Vec::new();
+    let mut liquid = BTreeMap::new();
+    let mut unbonding = Vec::new();
+    for (dao, (dels, balance, entries)) in dao_addrs.iter().zip(fetched) {
+        delegations.extend(dels);
+        liquid.insert(dao.clone(), balance);
+        unbonding.extend(entries);
+    }
+    let live = ChainSnapshot {
+        unbonding,
+        ..chain_snapshot(staking, height, &delegations, &targets, liquid).await?
+    };
 
-            // simulate first, broadcast
-            rt.block_on(mut_wallet.simulate(bundle.clone(), None))?;
-            rt.block_on(mut_wallet.commit_tx_any(bundle, None))?;
+    let schedule = unbonding_schedule(&live, earlier.as_ref());
+    println!("\n--- UNBONDING AT HEIGHT {} ---", schedule.height);
+    print!("{}", schedule.table(chain));
+    serialize_and_print(
+        serde_json::to_string_pretty(&schedule)?,
+        UNBONDING_JSON.to_string(),
+    );
 
-            // Wait for 7 seconds before next batch
-            std::thread::sleep(std::time::Duration::new(7, 0));
-        }
+    let followup = route_freed_funds(
+        &live,
+        &targets,
+        dao_addrs,
+        keep,
+        reserve_address,
+        &chain.denom,
+    )?;
+    let entries = followup.entries();
+    if entries.is_empty() {
+        println!(
+            "No DAO holds more than {} {} liquid that a target can take, nothing to route",
+            chain.display_amount(keep),
+            chain.display_denom
+        );
+        return Ok(());
+    }
+    if let Some(failure) = replay_plan(&followup, &live)? {
+        anyhow::bail!("follow-up plan would fail: {}", failure);
     }
+    serialize_and_print(
+        serde_json::to_string_pretty(&followup)?,
+        UNBONDING_FOLLOWUP_JSON.to_string(),
+    );
+    println!(
+        "{} msgs routing {} {} to {} written to {}",
+        entries.len(),
+        chain.display_amount(followup.delegations.total_ubtsg + followup.sends.total_ubtsg),
+        chain.display_denom,
+        reserve_address.unwrap_or("under-target validators"),
+        UNBONDING_FOLLOWUP_JSON
+    );
     Ok(())
 }
 
-fn filter_obligated_msgs(
-    obligated_export: MessageExport,
-    dao: String,
-) -> (
-    Vec<cosmrs::staking::MsgBeginRedelegate>,
-    Vec<cosmrs::staking::MsgDelegate>,
-    Vec<cosmrs::staking::MsgUndelegate>,
-) {
-    // prepare 32 msgs batches. Include all msgs where this dao is delegator.
-    let redels: Vec<RedelegateMsg> = obligated_export
-        .redelegations
-        .data
-        .into_iter()
-        .filter(|rd| rd.delegator_address == dao)
+/// Snapshot of the fetched DAO delegations and the state of every validator they or the targets
+/// touch, queried concurrently with their signing infos. Validators the node does not know are left out.
+async fn chain_snapshot(
+    staking: &impl StakingBackend,
+    height: u64,
+    dao_delegations: &[Delegation],
+    obligated_delegations: &[Delegation],
+    liquid: BTreeMap<String, Uint128>,
+) -> anyhow::Result<ChainSnapshot> {
+    let mut operators: Vec<&str> = dao_delegations
+        .iter()
+        .chain(obligated_delegations)
+        .map(|d| d.operator_addr.as_str())
         .collect();
+    operators.sort();
+    operators.dedup();
 
-    let dels: Vec<DelegateMsg> = obligated_export
-        .delegations
-        .data
-        .into_iter()
-        .filter(|rd| rd.delegator_address == dao)
+    let states: Vec<Option<ValidatorState>> = stream::iter(&operators)
+        .map(|operator| staking.validator(operator))
+        .buffered(QUERY_CONCURRENCY)
+        .try_collect()
+        .await?;
+    let mut validators = Vec::new();
+    for (operator, state) in operators.iter().zip(states) {
+        match state {
+            Some(state) => validators.push(state),
+            None => log::warn!("validator {} does not exist", operator),
+        }
+    }
+    let target_classes = validators
+        .iter()
+        .filter(|v| {
+            obligated_delegations
+                .iter()
+                .any(|t| t.operator_addr == v.operator_address)
+        })
+        .map(|v| (v.operator_address.clone(), v.class()))
         .collect();
 
-    let undels: Vec<UndelegateMsg> = obligated_export
-        .undelegates
-        .data
-        .into_iter()
-        .filter(|rd| rd.delegator_address == dao)
-        .collect();
+    Ok(ChainSnapshot {
+        height,
+        delegations: dao_delegations.to_vec(),
+        validators,
+        liquid,
+        target_classes,
+        ..Default::default()
+    })
+}
 
-    // form into cosmrs msgs
-    let mut rdel_msgs = vec![];
-    let mut del_msgs = vec![];
-    let mut udel_msgs = vec![];
-    for rd in redels {
-        rdel_msgs.push(form_redel_msg(rd));
+/// Lists the targets that cannot safely receive stake, verification fails a plan that moves stake onto them.
+fn print_target_classes(snapshot: &ChainSnapshot, obligated_delegations: &[Delegation]) {
+    println!("\n--- TARGET VALIDATOR STATUS ---");
+    let mut bonded = 0;
+    for target in obligated_delegations {
+        match snapshot.target_classes.get(&target.operator_addr) {
+            Some(ValidatorClass::Bonded) => bonded += 1,
+            Some(class) => {
+                let signing = snapshot
+                    .validator(&target.operator_addr)
+                    .and_then(|v| v.signing.as_ref());
+                match signing {
+                    Some(signing) => println!(
+                        "⚠️ {} is {} (jailed until {}, {} missed blocks)",
+                        target.operator_addr,
+                        class,
+                        if signing.jailed_until.is_empty() {
+                            "-"
+                        } else {
+                            &signing.jailed_until
+                        },
+                        signing.missed_blocks
+                    ),
+                    None => println!("⚠️ {} is {}", target.operator_addr, class),
+                }
+            }
+            None => println!("⚠️ {} does not exist", target.operator_addr),
+        }
     }
-    for d in dels {
-        del_msgs.push(form_del_msg(d));
+    println!(
+        "{}/{} targets are bonded",
+        bonded,
+        obligated_delegations.len()
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+fn form_and_broadcast_obligated_msgs(
+    rt: Runtime,
+    chain: &ChainProfile,
+    querier: &impl StakingBackend,
+    wallet: &impl WalletBackend,
+    tracker: &mut impl TxTracker,
+    json: &str,
+    dao_addrs: Vec<String>,
+    limits: GasLimits,
+) -> anyhow::Result<()> {
+    // load json msgs
+    let file_content = std::fs::read_to_string(json)?;
+    let obligated_export: MessageExport = serde_json::from_str(&file_content)?;
+
+    let packed = pack_plan(&rt, wallet, &obligated_export, &dao_addrs, &limits)?;
+    let total_fee = total_fee(&packed);
+    println!(
+        "Broadcasting {} plan entries in {} bundles, expected fee: {} {}",
+        obligated_export.entries().len(),
+        packed.len(),
+        chain.display_amount(total_fee),
+        chain.display_denom
+    );
+    // the planning run's report gets the fee once the bundles are simulated
+    if let Ok(mut report) = EpochReport::load(REPORT_JSON) {
+        if report.plan_hash == plan_hash(&obligated_export)? {
+            report.bundles = packed.len();
+            report.fee = Some(total_fee);
+            write_report(chain, &report)?;
+        }
     }
-    for ud in undels {
-        udel_msgs.push(form_undel_msg(ud));
+    if !confirm("Proceed?")? {
+        println!("Aborted, nothing was broadcast");
+        return Ok(());
     }
 
-    (rdel_msgs, del_msgs, udel_msgs)
+    // state the plan expects, advanced after every included bundle
+    let expected = ChainSnapshot::load(SNAPSHOT_JSON)?;
+    broadcast_bundles(
+        &rt,
+        querier,
+        wallet,
+        tracker,
+        &obligated_export,
+        &packed,
+        expected,
+        |submission| {
+            serialize_and_print(
+                serde_json::to_string_pretty(submission)?,
+                BROADCAST_LOG_JSON.to_string(),
+            );
+            Ok(())
+        },
+    )?;
+    Ok(())
 }
 
-fn form_redel_msg(redel: RedelegateMsg) -> cosmrs::staking::MsgBeginRedelegate {
-    cosmrs::staking::MsgBeginRedelegate {
-        // Delegator's address.
-        delegator_address: AccountId::from_str(&redel.delegator_address).unwrap(),
-
-        // Source validator's address.
-        validator_src_address: AccountId::from_str(&redel.validator_src_address).unwrap(),
+/// Assigns every plan entry to exactly one bundle of its DAO and sizes the bundles by simulated
+/// gas, wrapped in authz exactly as they will be broadcast.
+fn pack_plan(
+    rt: &Runtime,
+    wallet: &impl WalletBackend,
+    export: &MessageExport,
+    dao_addrs: &[String],
+    limits: &GasLimits,
+) -> anyhow::Result<Vec<PackedBundle>> {
+    let entries = export.entries();
+    let bundles = schedule_bundles(&entries, dao_addrs, MAX_MSGS_PER_BUNDLE)?;
+    let needs_earlier = |bundle: &Bundle| {
+        let earlier: Vec<PlanMsg> = entries
+            .iter()
+            .filter(|e| e.msg.delegator() == bundle.dao)
+            .take_while(|e| e.id != bundle.entries[0])
+            .map(|e| e.msg.clone())
+            .collect();
+        needs_earlier_bundles(&earlier, &bundle_plan_msgs(export, bundle)?)
+    };
+    pack_bundles(bundles, limits, needs_earlier, |bundle| {
+        simulate_bundle(rt, wallet, export, bundle)
+    })
+}
 
-        // Destination validator's address.
-        validator_dst_address: AccountId::from_str(&redel.validator_dst_address).unwrap(),
+/// Broadcasts `packed` in order, each bundle only once the chain still matches `expected`, and
+/// stops at the first failed bundle. `on_record` sees the submission log after every bundle.
+#[allow(clippy::too_many_arguments)]
+fn broadcast_bundles(
+    rt: &Runtime,
+    querier: &impl StakingBackend,
+    wallet: &impl WalletBackend,
+    tracker: &mut impl TxTracker,
+    export: &MessageExport,
+    packed: &[PackedBundle],
+    mut expected: ChainSnapshot,
+    mut on_record: impl FnMut(&SubmissionLog) -> anyhow::Result<()>,
+) -> anyhow::Result<SubmissionLog> {
+    let grantee = wallet.address();
+    let mut submission = SubmissionLog::default();
+    for (i, PackedBundle { bundle, estimate }) in packed.iter().enumerate() {
+        let msgs = bundle_msgs(export, bundle)?;
+
+        // the chain may have moved since the plan was made, refuse to act on a stale plan
+        let plan_msgs = bundle_plan_msgs(export, bundle)?;
+        let live = rt.block_on(querier.live_state(&plan_msgs))?;
+        for drift in check_bundle(&bundle.dao, &plan_msgs, &expected, &live)? {
+            log::warn!("bundle {}/{}: {}", i + 1, packed.len(), drift);
+        }
 
-        // Amount to UnDelegate
-        amount: cosmrs::Coin {
-            amount: Uint128::from_str(&redel.amount).unwrap().u128(),
-            denom: cosmrs::Denom::from_str("ubtsg").unwrap(),
-        },
+        // the tx is dropped by the chain if it is not included before the timeout height
+        let timeout_height = rt.block_on(querier.block_height())? + INCLUSION_TIMEOUT_BLOCKS;
+        let txhash =
+            rt.block_on(wallet.broadcast(vec![authz_exec(&grantee, msgs)], timeout_height))?;
+
+        let outcome = rt.block_on(wait_for_inclusion(
+            tracker,
+            &txhash,
+            timeout_height,
+            INCLUSION_POLL_INTERVAL,
+        ))?;
+        println!(
+            "bundle {}/{} for {} ({} gas estimated, {} used): tx {} at height {} with code {} → {}",
+            i + 1,
+            packed.len(),
+            bundle.dao,
+            estimate.gas,
+            outcome.gas_used,
+            outcome.txhash,
+            outcome.height,
+            outcome.code,
+            bundle
+                .entries
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        submission.record(bundle, &outcome);
+        on_record(&submission)?;
+
+        // never keep broadcasting after a failed bundle
+        if !outcome.is_success() {
+            let kind = FailureKind::classify(&outcome.codespace, outcome.code, &outcome.raw_log);
+            let failing = failing_exec_index(&outcome.raw_log)
+                .map(|i| (i, outcome.raw_log.clone()))
+                .or_else(|| {
+                    bisect_failing_prefix(&bundle.entries, |prefix| {
+                        simulate_entries(rt, wallet, export, prefix)
+                            .err()
+                            .map(|e| e.to_string())
+                    })
+                });
+            return Err(explain_failure(
+                export,
+                bundle,
+                failing,
+                kind,
+                &outcome.raw_log,
+            ));
+        }
+        expected.apply(&plan_msgs)?;
     }
+
+    // hard check that nothing in the plan was skipped or sent twice
+    let entries = export.entries();
+    submission.ensure_complete(&entries)?;
+    println!("All {} plan entries submitted", entries.len());
+    Ok(submission)
 }
-fn form_del_msg(del: DelegateMsg) -> cosmrs::staking::MsgDelegate {
-    cosmrs::staking::MsgDelegate {
-        // Delegator's address.
-        delegator_address: AccountId::from_str(&del.delegator_address).unwrap(),
-        validator_address: AccountId::from_str(&del.validator_address).unwrap(),
-
-        // Amount to Delegate
-        amount: cosmrs::Coin {
-            amount: Uint128::from_str(&del.amount).unwrap().u128(),
-            denom: cosmrs::Denom::from_str("ubtsg").unwrap(),
-        },
+
+/// Simulates a bundle wrapped in authz. On failure the offending plan entry is isolated, from the
+/// error's message index or else by bisecting the bundle with more simulations.
+fn simulate_bundle(
+    rt: &Runtime,
+    wallet: &impl WalletBackend,
+    export: &MessageExport,
+    bundle: &Bundle,
+) -> anyhow::Result<GasEstimate> {
+    match simulate_entries(rt, wallet, export, &bundle.entries) {
+        Ok(estimate) => Ok(estimate),
+        Err(err) => {
+            let raw_log = err.to_string();
+            let failing = failing_exec_index(&raw_log)
+                .map(|i| (i, raw_log.clone()))
+                .or_else(|| {
+                    bisect_failing_prefix(&bundle.entries, |prefix| {
+                        simulate_entries(rt, wallet, export, prefix)
+                            .err()
+                            .map(|e| e.to_string())
+                    })
+                });
+            let kind = FailureKind::classify("", 0, &raw_log);
+            Err(explain_failure(export, bundle, failing, kind, &raw_log))
+        }
     }
 }
-fn form_undel_msg(del: UndelegateMsg) -> cosmrs::staking::MsgUndelegate {
-    cosmrs::staking::MsgUndelegate {
-        // Delegator's address.
-        delegator_address: AccountId::from_str(&del.delegator_address).unwrap(),
-        validator_address: AccountId::from_str(&del.validator_address).unwrap(),
-
-        // Amount to Delegate
-        amount: cosmrs::Coin {
-            amount: Uint128::from_str(&del.amount).unwrap().u128(),
-            denom: cosmrs::Denom::from_str("ubtsg").unwrap(),
-        },
+
+fn simulate_entries(
+    rt: &Runtime,
+    wallet: &impl WalletBackend,
+    export: &MessageExport,
+    ids: &[PlanEntryId],
+) -> anyhow::Result<GasEstimate> {
+    let msgs = entry_msgs(export, ids)?;
+    rt.block_on(wallet.simulate(vec![authz_exec(&wallet.address(), msgs)]))
+}
+
+/// Turns a failed bundle into a [`PlannerError`] naming the plan entry when it could be isolated.
+fn explain_failure(
+    export: &MessageExport,
+    bundle: &Bundle,
+    failing: Option<(usize, String)>,
+    kind: FailureKind,
+    raw_log: &str,
+) -> anyhow::Error {
+    let entry = failing.and_then(|(i, log)| {
+        let entry = export.entry(bundle.entries.get(i)?)?;
+        Some((entry, log))
+    });
+    match entry {
+        Some((entry, log)) => {
+            // the isolated entry's own error is more precise than the whole bundle's
+            let kind = FailureKind::from_log(&log).unwrap_or(kind);
+            PlannerError::entry(&entry, kind, &log).into()
+        }
+        None => PlannerError::Bundle {
+            dao: bundle.dao.clone(),
+            entries: bundle.entries.len(),
+            kind,
+            raw_log: raw_log.to_string(),
+        }
+        .into(),
     }
 }
 
-// Loads array of validators getting new delegations from file, returning the total new delegations
-fn load_new_delegations(fp: &str, has_header: bool) -> AllAlignedDelegations {
-    let file = File::open(fp).expect("Could not open file");
+fn bundle_msgs(export: &MessageExport, bundle: &Bundle) -> anyhow::Result<Vec<cosmrs::Any>> {
+    entry_msgs(export, &bundle.entries)
+}
+
+fn bundle_plan_msgs(export: &MessageExport, bundle: &Bundle) -> anyhow::Result<Vec<PlanMsg>> {
+    bundle
+        .entries
+        .iter()
+        .map(|id| {
+            export
+                .entry(id)
+                .map(|entry| entry.msg)
+                .ok_or_else(|| anyhow::anyhow!("{} is not in the plan", id))
+        })
+        .collect()
+}
+
+fn entry_msgs(export: &MessageExport, ids: &[PlanEntryId]) -> anyhow::Result<Vec<cosmrs::Any>> {
+    ids.iter()
+        .map(|id| {
+            export
+                .entry(id)
+                .ok_or_else(|| anyhow::anyhow!("{} is not in the plan", id))?
+                .msg
+                .to_any()
+        })
+        .collect()
+}
+
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(input.trim().eq_ignore_ascii_case("y"))
+}
+
+// Loads array of validators getting new delegations from file, returning the total new delegations.
+// Fails on a missing file or on any row that is not a validator and an amount.
+fn load_new_delegations(fp: &str, has_header: bool) -> anyhow::Result<AllAlignedDelegations> {
+    let file =
+        File::open(fp).map_err(|e| anyhow::anyhow!("failed to open targets {}: {}", fp, e))?;
 
     // Create a reader with configurable header setting
     let mut rdr = ReaderBuilder::new()
@@ -796,59 +1347,48 @@ fn load_new_delegations(fp: &str, has_header: bool) -> AllAlignedDelegations {
     let mut delegations = vec![];
     let mut total = Uint128::zero();
 
-    for result in rdr.records() {
-        match result {
-            Ok(record) => {
-                // Ensure there are at least two fields
-                if record.len() < 2 {
-                    eprintln!(
-                        "Invalid record format: expected at least 2 fields, got {}",
-                        record.len()
-                    );
-                    continue;
-                }
-
-                let addr = match record[0].parse::<String>() {
-                    Ok(addr) => addr,
-                    Err(e) => {
-                        eprintln!("Error parsing address: {}", e);
-                        continue;
-                    }
-                };
-
-                let amount = match record[1].parse::<Uint128>() {
-                    Ok(num) => num,
-                    Err(e) => {
-                        eprintln!("Error parsing amount: {}", e);
-                        continue;
-                    }
-                };
+    for (row, result) in rdr.records().enumerate() {
+        let record =
+            result.map_err(|e| anyhow::anyhow!("{} row {}: unreadable: {}", fp, row + 1, e))?;
+        anyhow::ensure!(
+            record.len() >= 2,
+            "{} row {}: expected a validator and an amount, got {} fields",
+            fp,
+            row + 1,
+            record.len()
+        );
+        let amount = record[1].parse::<Uint128>().map_err(|e| {
+            anyhow::anyhow!(
+                "{} row {}: invalid amount {:?}: {}",
+                fp,
+                row + 1,
+                &record[1],
+                e
+            )
+        })?;
 
-                delegations.push(Delegation {
-                    del_addr: String::default(),
-                    operator_addr: addr,
-                    amount,
-                });
+        delegations.push(Delegation {
+            del_addr: String::default(),
+            operator_addr: record[0].to_string(),
+            amount,
+        });
 
-                total += amount;
-            }
-            Err(e) => {
-                eprintln!("Error reading record: {}", e);
-            }
-        }
+        total += amount;
     }
 
-    println!(
+    // stderr, so `verify` keeps stdout for its JSON report
+    eprintln!(
         "Loaded {} delegations with total amount {}",
         delegations.len(),
         total
     );
-    AllAlignedDelegations { delegations, total }
+    Ok(AllAlignedDelegations { delegations, total })
 }
 
 fn optimize_delegations(
     current_delegations: Vec<Delegation>,
     obligated_delegations: &[Delegation],
+    liquid: &BTreeMap<String, Uint128>,
     denom: &str,
 ) -> (
     Vec<MsgBeginRedelegate>,
@@ -856,8 +1396,9 @@ fn optimize_delegations(
     Vec<MsgUndelegate>,
 ) {
     // Maps of all current and obligated delegations
-    let mut old_delegations: HashMap<String, Vec<Delegation>> = HashMap::new();
-    let mut obligated_delegations_map: HashMap<String, Uint128> = HashMap::new();
+    // ordered maps, so the same input always produces the same plan
+    let mut old_delegations: BTreeMap<String, Vec<Delegation>> = BTreeMap::new();
+    let mut obligated_delegations_map: BTreeMap<String, Uint128> = BTreeMap::new();
 
     // Preprocessing: Assert current and obligated delegations total
     let mut total_current_delegation = Uint128::zero();
@@ -866,6 +1407,8 @@ fn optimize_delegations(
     let mut redelegation_msgs = Vec::<MsgBeginRedelegate>::new();
     let mut delegation_msgs = Vec::<MsgDelegate>::new();
     let mut undelegate_msgs = Vec::<MsgUndelegate>::new();
+    // the targets CSV has no delegator, direct delegations are funded from the DAOs' liquid balances
+    let mut funds = liquid.clone();
 
     // save old delegations hash map with validator as key
     for del in current_delegations {
@@ -884,7 +1427,6 @@ fn optimize_delegations(
             .entry(del.operator_addr.clone())
             .or_default() += amount;
     }
-    assert_eq!(total_obligated_delegation, TOTAL_OBLIGATED_DELEGATED_BTSG);
     // First pass: Process validators that need additional delegations
     for target_del in obligated_delegations {
         let target_validator = &target_del.operator_addr;
@@ -924,15 +1466,16 @@ fn optimize_delegations(
             }
 
             // Sort current delegations to prioritize larger amounts
-            current_dels.sort_by(|a, b| b.amount.cmp(&a.amount));
+            current_dels.sort_by_key(|d| Reverse(d.amount));
 
+            // the excess is shared by all delegations on the source, never take more than it
+            let mut excess = src_current.saturating_sub(src_target);
             for del in current_dels.iter_mut() {
-                if remaining_needed.is_zero() {
+                if remaining_needed.is_zero() || excess.is_zero() {
                     break;
                 }
 
                 // Safely calculate redelegate amount
-                let excess = src_current.saturating_sub(src_target);
                 let available_to_redelegate = del.amount.min(excess);
                 let redelegate_amount = available_to_redelegate.min(remaining_needed);
 
@@ -951,6 +1494,7 @@ fn optimize_delegations(
                     remaining_needed = remaining_needed
                         .checked_sub(redelegate_amount)
                         .unwrap_or(Uint128::zero());
+                    excess -= redelegate_amount;
 
                     del.amount = del
                         .amount
@@ -965,16 +1509,31 @@ fn optimize_delegations(
             }
         }
 
-        // If still need delegation, add direct delegation
-        if !remaining_needed.is_zero() {
+        // If still need delegation, add direct delegations from the DAOs holding liquid funds
+        for (dao, balance) in funds.iter_mut() {
+            if remaining_needed.is_zero() {
+                break;
+            }
+            let delegate_amount = remaining_needed.min(*balance);
+            if delegate_amount.is_zero() {
+                continue;
+            }
             delegation_msgs.push(MsgDelegate {
-                delegator_address: target_del.del_addr.clone(), // Use a default or first DAO address
+                delegator_address: dao.clone(),
                 validator_address: target_validator.clone(),
                 amount: Some(ProtoCoin {
                     denom: denom.to_string(),
-                    amount: remaining_needed.to_string(),
+                    amount: delegate_amount.to_string(),
                 }),
             });
+            *balance -= delegate_amount;
+            remaining_needed -= delegate_amount;
+        }
+        if !remaining_needed.is_zero() {
+            println!(
+                "⚠️ {} stays {}{} short, no DAO holds the liquid funds to delegate it",
+                target_validator, remaining_needed, denom
+            );
         }
     }
 
@@ -1048,14 +1607,12 @@ fn optimize_delegations(
 
 // Add detailed logging to track delegation totals
 fn debug_delegation_tracking(
-    current_dao_delegations: &[DelegationResponse],
+    chain: &ChainProfile,
+    current_dao_delegations: &[Delegation],
     obligated_dao_delegations: &[Delegation],
 ) -> anyhow::Result<()> {
     // Track total current delegations
-    let total_current_delegations: Uint128 = current_dao_delegations
-        .iter()
-        .map(|a| Uint128::from_str(a.balance.clone().expect("msg").amount.as_str()).unwrap())
-        .sum();
+    let total_current_delegations: Uint128 = current_dao_delegations.iter().map(|a| a.amount).sum();
 
     // Track total target delegations
     let total_obligated_delegations: Uint128 =
@@ -1063,33 +1620,27 @@ fn debug_delegation_tracking(
 
     println!("\n--- DELEGATION TOTAL DEBUGGING ---");
     println!(
-        "Determined Current Delegations : {}  
-         Determine Obligated Delegations: {}",
-        Decimal::from_atomics(total_current_delegations, 6)?,
-        Decimal::from_atomics(total_obligated_delegations, 6)?
+        "Determined Current Delegations : {} {}  
+         Determine Obligated Delegations: {} {}",
+        chain.display_amount(total_current_delegations),
+        chain.display_denom,
+        chain.display_amount(total_obligated_delegations),
+        chain.display_denom
     );
 
     // Print detailed breakdown of current delegations
     println!("\nCurrent Delegation Breakdown:");
     let mut detailed_current_dels = current_dao_delegations
         .iter()
-        .map(|a| {
-            let balance =
-                Uint128::from_str(a.balance.clone().expect("msg").amount.as_str()).unwrap();
-            (
-                a.delegation.clone().expect("msg").validator_address,
-                balance,
-            )
-        })
+        .map(|a| (a.operator_addr.clone(), a.amount))
         .collect::<Vec<_>>();
 
-    detailed_current_dels.sort_by(|a, b| b.1.cmp(&a.1));
+    detailed_current_dels.sort_by_key(|d| Reverse(d.1));
     let mut sum = Uint128::zero();
-    for (validator, amount) in detailed_current_dels {
+    for (_, amount) in detailed_current_dels {
         sum += amount;
     }
-    let dec = Decimal::from_atomics(sum, 6)?;
-    println!("sum: {}", dec);
+    println!("sum: {} {}", chain.display_amount(sum), chain.display_denom);
 
     // Print detailed breakdown of target delegations
     println!("\n Obligated Delegation Breakdown:");
@@ -1098,177 +1649,116 @@ fn debug_delegation_tracking(
         .map(|d| (d.operator_addr.clone(), d.amount))
         .collect::<Vec<_>>();
 
-    detailed_obligated_delegations.sort_by(|a, b| b.1.cmp(&a.1));
+    detailed_obligated_delegations.sort_by_key(|d| Reverse(d.1));
 
     sum = Uint128::zero();
     for (_, amount) in detailed_obligated_delegations {
         sum += amount;
     }
-    println!("sum: {}", sum);
+    println!("sum: {} {}", chain.display_amount(sum), chain.display_denom);
 
     Ok(())
 }
 
-fn verify_final_state(
-    json_file: &str,
-    current_delegations: &[DelegationResponse],
-    obligated_delegations: &[Delegation],
-) -> anyhow::Result<()> {
+fn print_verification(chain: &ChainProfile, verification: &Verification) {
     println!("\n--- VERIFYING FINAL VALIDATOR STATE ---");
-
-    // Read and parse the JSON file
-    let file_content = std::fs::read_to_string(json_file)?;
-    let export: MessageExport = serde_json::from_str(&file_content)?;
-
-    // Create maps for current and target delegations by validator
-    let mut current_by_validator: HashMap<String, Uint128> = HashMap::new();
-    let mut obligated_by_validator: HashMap<String, Uint128> = HashMap::new();
-
-    // Populate current delegations map
-    for del in current_delegations {
-        let validator = del
-            .delegation
-            .as_ref()
-            .expect("msg")
-            .validator_address
-            .clone();
-        let amount = Uint128::from_str(&del.balance.as_ref().expect("msg").amount)?;
-        *current_by_validator.entry(validator).or_default() += amount;
-    }
-
-    // Populate obligated delegations map
-    for del in obligated_delegations {
-        *obligated_by_validator
-            .entry(del.operator_addr.clone())
-            .or_default() += del.amount;
-    }
-
-    // Apply all changes from the export to simulate final state
-    let mut final_state = current_by_validator.clone();
-
-    // Apply redelegations (subtract from source, add to destination)
-    for redel in &export.redelegations.data {
-        let amount = Uint128::from_str(&redel.amount)?;
-        *final_state
-            .entry(redel.validator_src_address.clone())
-            .or_default() -= amount;
-        *final_state
-            .entry(redel.validator_dst_address.clone())
-            .or_default() += amount;
-    }
-
-    // Apply delegations (add to validator)
-    for del in &export.delegations.data {
-        let amount = Uint128::from_str(&del.amount)?;
-        *final_state
-            .entry(del.validator_address.clone())
-            .or_default() += amount;
-    }
-
-    // Apply undelegations (subtract from validator)
-    for undel in &export.undelegates.data {
-        let amount = Uint128::from_str(&undel.amount)?;
-        *final_state
-            .entry(undel.validator_address.clone())
-            .or_default() -= amount;
-    }
-
-    // Verify the final state matches the obligated state
-    let mut discrepancies = Vec::new();
-    let mut total_final = Uint128::zero();
-    let mut total_obligated = Uint128::zero();
-
-    // Check each validator's final state against obligation
-    for (validator, &obligated_amount) in &obligated_by_validator {
-        let final_amount = final_state
-            .get(validator)
-            .copied()
-            .unwrap_or(Uint128::zero());
-        total_obligated += obligated_amount;
-        total_final += final_amount;
-
-        if final_amount != obligated_amount {
-            discrepancies.push((
-                validator.clone(),
-                final_amount,
-                obligated_amount,
-                final_amount
-                    .checked_sub(obligated_amount)
-                    .unwrap_or_else(|_| {
-                        obligated_amount
-                            .checked_sub(final_amount)
-                            .unwrap_or(Uint128::zero())
-                    }),
-            ));
-        }
-    }
-
-    // Print verification results
     println!(
         "Total final delegation amount: {}",
-        Decimal::from_atomics(total_final, 6)?
+        chain.display_amount(verification.total_final)
     );
     println!(
         "Total obligated delegation amount: {}",
-        Decimal::from_atomics(total_obligated, 6)?
+        chain.display_amount(verification.total_target)
     );
 
-    if discrepancies.is_empty() {
+    if verification.discrepancies.is_empty() {
         println!(
             "✅ VERIFICATION PASSED: All validators have the correct obligated delegation amount"
         );
     } else {
         println!(
             "❌ VERIFICATION FAILED: Found {} validators with discrepancies",
-            discrepancies.len()
+            verification.discrepancies.len()
         );
 
-        // Sort discrepancies by difference amount (largest first)
-        discrepancies.sort_by(|a, b| b.3.cmp(&a.3));
-
         println!("\nTop discrepancies:");
-        for (validator, final_amount, obligated_amount, diff) in discrepancies.iter().take(10) {
+        for discrepancy in verification.discrepancies.iter().take(10) {
             println!(
                 "Validator {}: Final={}, Obligated={}, Diff={}",
-                validator,
-                Decimal::from_atomics(*final_amount, 6)?,
-                Decimal::from_atomics(*obligated_amount, 6)?,
-                Decimal::from_atomics(*diff, 6)?
+                discrepancy.validator,
+                chain.display_amount(discrepancy.final_amount),
+                chain.display_amount(discrepancy.target),
+                chain.display_amount(discrepancy.diff())
             );
         }
     }
 
-    // Check for validators with redelegations or undelegations that aren't in obligated_delegations
-    let mut unexpected_validators = Vec::new();
-    for validator in final_state.keys() {
-        if !obligated_by_validator.contains_key(validator)
-            && final_state
-                .get(validator)
-                .copied()
-                .unwrap_or(Uint128::zero())
-                > Uint128::zero()
-        {
-            unexpected_validators.push((
-                validator.clone(),
-                final_state
-                    .get(validator)
-                    .copied()
-                    .unwrap_or(Uint128::zero()),
-            ));
+    if !verification.negative_balances.is_empty() {
+        println!(
+            "\n❌ Found {} validators the plan takes more stake from than is delegated:",
+            verification.negative_balances.len()
+        );
+        for negative in &verification.negative_balances {
+            println!(
+                "Validator {}: Shortfall={}",
+                negative.validator,
+                chain.display_amount(negative.shortfall)
+            );
         }
     }
 
-    if !unexpected_validators.is_empty() {
-        println!("\n⚠️ WARNING: Found {} validators with delegations that are not in the obligated list:", unexpected_validators.len());
-        for (validator, amount) in unexpected_validators {
+    if let Some(failure) = &verification.replay_failure {
+        println!("\n❌ PLAN NOT EXECUTABLE IN ORDER: {}", failure);
+    }
+
+    for inactive in &verification.inactive_destinations {
+        println!("❌ {}, the precheck would refuse its bundle", inactive);
+    }
+
+    for dust in &verification.share_dust {
+        println!(
+            "⚠️ {}: {} moves all its shares of {}, {} {} of the reported balance do not move with them",
+            dust.entry,
+            dust.delegator,
+            dust.validator,
+            chain.display_amount(dust.amount),
+            chain.display_denom
+        );
+    }
+
+    if !verification.unexpected_validators.is_empty() {
+        println!("\n⚠️ WARNING: Found {} validators with delegations that are not in the obligated list:", verification.unexpected_validators.len());
+        for unexpected in &verification.unexpected_validators {
             println!(
                 "Validator {}: Amount={}",
-                validator,
-                Decimal::from_atomics(amount, 6)?
+                unexpected.validator,
+                chain.display_amount(unexpected.amount)
             );
         }
     }
+}
 
+/// Verifies `export` against `snapshot` and only writes it to `json_file` if it passes or is
+/// overridden, the verification to `verification_file`.
+fn verify_and_export(
+    chain: &ChainProfile,
+    export: &MessageExport,
+    snapshot: &ChainSnapshot,
+    obligated_delegations: &[Delegation],
+    override_verification: Option<String>,
+    json_file: &str,
+    verification_file: &str,
+) -> anyhow::Result<()> {
+    let verification =
+        verify_final_state(export, snapshot, obligated_delegations, &chain.omitted())?;
+    print_verification(chain, &verification);
+    let verification = verification.gate(override_verification)?;
+
+    serialize_and_print(
+        serde_json::to_string_pretty(&verification)?,
+        verification_file.to_string(),
+    );
+    serialize_and_print(serde_json::to_string_pretty(export)?, json_file.to_string());
     Ok(())
 }
 
@@ -1281,10 +1771,14 @@ fn serialize_and_print(json: String, filepath: String) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use delegation_scripts::simulator::StakingSimulator;
+    use proptest::prelude::*;
 
     #[test]
     fn test_load_obligated_delegations_file() -> anyhow::Result<()> {
-        let aad = load_new_delegations(NEW_DELS_FILE, false);
+        let mainnet = ChainProfile::bitsong_mainnet();
+        let aad = load_new_delegations(mainnet.targets_csv()?, false)?;
+        let expected = mainnet.obligated_total.unwrap();
         // Check the calculated total from the struct
 
         // Calculate and check the sum of individual
@@ -1294,20 +1788,361 @@ mod tests {
 
         // Both should match the expected value
         assert_eq!(aad.delegations.len(), TOTAL_OBLIGATED_VALIDATORS);
-        assert_eq!(aad.total.u128(), TOTAL_OBLIGATED_DELEGATED_BTSG.u128());
+        assert_eq!(aad.total, expected);
+        assert_eq!(obligated_delegation_sum, expected);
+
+        Ok(())
+    }
+
+    const GRANTEE: &str = "bitsong1enndx0fjq23urqc9fpf66y7xwvhe2ajh8c0aah";
+    // validators outside the targets still holding DAO stake, one of them jailed
+    const STRAY_VALIDATOR: &str = "bitsongvaloper1ugjdm344ttut92yyqjstdjexzldsmlp9tfcc7h";
+    const JAILED_VALIDATOR: &str = "bitsongvaloper1gw032vwu5mrk04dkc47vpdaralzqy4zpvkvah3";
+
+    fn dao_addrs() -> Vec<String> {
+        ChainProfile::bitsong_mainnet().dao_addrs
+    }
+
+    fn omitted_validator() -> String {
+        ChainProfile::bitsong_mainnet().omitted_validators[0].clone()
+    }
+
+    /// The DAOs hold half of every target, spread round robin, the rest sits on a stray and a
+    /// jailed validator. One DAO also delegates to a validator under a private agreement, and
+    /// another earned rewards on the stray validator.
+    fn seeded_chain(targets: &[Delegation]) -> StakingSimulator {
+        let mut sim = StakingSimulator::new("ubtsg", GRANTEE, 1_000);
+        let daos = dao_addrs();
+        for dao in &daos {
+            sim = sim.with_balance(dao, 5_000_000).with_grant(dao);
+        }
+        for v in [STRAY_VALIDATOR, JAILED_VALIDATOR, &omitted_validator()] {
+            sim = sim.with_validator(v);
+        }
+
+        let mut seeded = Uint128::zero();
+        for (i, target) in targets.iter().enumerate() {
+            let half = target.amount.u128() / 2;
+            seeded += Uint128::new(half);
+            sim = sim.with_validator(&target.operator_addr).with_delegation(
+                &daos[i % 3],
+                &target.operator_addr,
+                half,
+            );
+        }
+        let total = ChainProfile::bitsong_mainnet().obligated_total.unwrap();
+        let rest = (total - seeded).u128();
+        let sim = sim
+            .with_delegation(&daos[0], JAILED_VALIDATOR, rest / 2)
+            .with_delegation(&daos[1], STRAY_VALIDATOR, rest - rest / 2)
+            .with_delegation(&daos[2], &omitted_validator(), 42_000_000)
+            .with_rewards(&daos[1], STRAY_VALIDATOR, 1_234);
+        sim.jail(JAILED_VALIDATOR);
+        sim
+    }
+
+    #[test]
+    fn test_accuracy_delegations_message_json() -> anyhow::Result<()> {
+        let mainnet = ChainProfile::bitsong_mainnet();
+        let targets = load_new_delegations(mainnet.targets_csv()?, false)?.delegations;
+        let sim = seeded_chain(&targets);
+        let rt = Runtime::new()?;
+
+        let height = rt.block_on(sim.block_height())?;
+        let (snapshot, export, _) = rt.block_on(plan_realignment(
+            &mainnet,
+            &sim,
+            &sim,
+            &sim,
+            &dao_addrs(),
+            height,
+            &targets,
+            &PlanOptions {
+                withdraw_rewards: true,
+                ..Default::default()
+            },
+        ))?;
+        let verification = verify_final_state(&export, &snapshot, &targets, &mainnet.omitted())?;
+        assert!(verification.passed(), "{:?}", verification);
+        assert_eq!(export.height, snapshot.height);
+        assert_eq!(
+            snapshot.validator(JAILED_VALIDATOR).map(|v| v.class()),
+            Some(ValidatorClass::Jailed)
+        );
+        assert_eq!(snapshot.target_classes.len(), targets.len());
+        assert!(snapshot
+            .target_classes
+            .values()
+            .all(|class| *class == ValidatorClass::Bonded));
+
+        // broadcast what a reviewer would see in delegation_messages.json
+        let export: MessageExport = serde_json::from_str(&serde_json::to_string_pretty(&export)?)?;
+        assert_eq!(export.delegations.count, 0);
+        assert_eq!(export.undelegates.count, 0);
+        assert_eq!(export.withdrawals.count, 1);
+        let packed = pack_plan(&rt, &sim, &export, &dao_addrs(), &GasLimits::default())?;
+        let submission = broadcast_bundles(
+            &rt,
+            &sim,
+            &sim,
+            &mut sim.clone(),
+            &export,
+            &packed,
+            snapshot,
+            |_| Ok(()),
+        )?;
+        submission.ensure_complete(&export.entries())?;
+        assert!(sim.txs().iter().all(|tx| tx.is_success()));
+
+        // every target validator holds exactly its target, nothing is left elsewhere
+        let mut live: BTreeMap<String, Uint128> = BTreeMap::new();
+        for del in sim.delegations() {
+            *live.entry(del.operator_addr).or_default() += del.amount;
+        }
+        for target in &targets {
+            assert_eq!(
+                live.remove(&target.operator_addr),
+                Some(target.amount),
+                "{}",
+                target.operator_addr
+            );
+        }
         assert_eq!(
-            obligated_delegation_sum.u128(),
-            TOTAL_OBLIGATED_DELEGATED_BTSG.u128()
+            live,
+            BTreeMap::from([(omitted_validator(), Uint128::new(42_000_000))])
         );
 
+        // stake only moved through redelegations, liquid balances only gained the rewards
+        let entries = sim.redelegation_entries();
+        assert_eq!(entries.len(), export.redelegations.count);
+        assert!(entries
+            .iter()
+            .all(|e| e.src_validator == JAILED_VALIDATOR || e.src_validator == STRAY_VALIDATOR));
+        assert!(sim.unbonding_entries().is_empty());
+        let daos = dao_addrs();
+        for dao in &daos {
+            let rewards = if *dao == daos[1] { 1_234 } else { 0 };
+            assert_eq!(sim.liquid(dao), Uint128::new(5_000_000 + rewards));
+        }
         Ok(())
     }
 
-    // Add this function near the end of realign_delegations,
-    // just before the final Ok(()) return
+    fn amount(coin: &Option<ProtoCoin>) -> Uint128 {
+        Uint128::from_str(&coin.as_ref().expect("amount").amount).expect("amount")
+    }
+
+    fn take(
+        state: &mut BTreeMap<(String, String), Uint128>,
+        del: &str,
+        val: &str,
+        amount: Uint128,
+    ) -> Result<(), TestCaseError> {
+        let held = state.entry((del.to_string(), val.to_string())).or_default();
+        prop_assert!(
+            *held >= amount,
+            "{} takes {} from {} but only {} is delegated",
+            del,
+            amount,
+            val,
+            held
+        );
+        *held -= amount;
+        Ok(())
+    }
+
+    /// Replays the planner's messages over `current` and checks the invariants every plan must
+    /// hold, whatever the input: totals are conserved, no delegation is overdrawn, every target is
+    /// hit exactly, no message moves zero and nothing is redelegated to its own validator.
+    fn check_plan(current: &[Delegation], targets: &[Delegation]) -> Result<(), TestCaseError> {
+        // every DAO holds enough to fund any direct delegation
+        let liquid: BTreeMap<String, Uint128> = (0..3)
+            .map(|dao| (format!("dao{}", dao), Uint128::new(10_000)))
+            .collect();
+        let (redelegations, delegations, undelegations) =
+            optimize_delegations(current.to_vec(), targets, &liquid, "ubtsg");
+
+        let mut state: BTreeMap<(String, String), Uint128> = BTreeMap::new();
+        for d in current {
+            *state
+                .entry((d.del_addr.clone(), d.operator_addr.clone()))
+                .or_default() += d.amount;
+        }
+        for msg in &redelegations {
+            let moved = amount(&msg.amount);
+            prop_assert!(!moved.is_zero(), "zero redelegation {:?}", msg);
+            prop_assert_ne!(&msg.validator_src_address, &msg.validator_dst_address);
+            take(
+                &mut state,
+                &msg.delegator_address,
+                &msg.validator_src_address,
+                moved,
+            )?;
+            *state
+                .entry((
+                    msg.delegator_address.clone(),
+                    msg.validator_dst_address.clone(),
+                ))
+                .or_default() += moved;
+        }
+        let mut delegated = Uint128::zero();
+        for msg in &delegations {
+            let added = amount(&msg.amount);
+            prop_assert!(!added.is_zero(), "zero delegation {:?}", msg);
+            prop_assert!(
+                liquid.contains_key(&msg.delegator_address),
+                "delegation not funded by a DAO {:?}",
+                msg
+            );
+            delegated += added;
+            *state
+                .entry((msg.delegator_address.clone(), msg.validator_address.clone()))
+                .or_default() += added;
+        }
+        let mut undelegated = Uint128::zero();
+        for msg in &undelegations {
+            let removed = amount(&msg.amount);
+            prop_assert!(!removed.is_zero(), "zero undelegation {:?}", msg);
+            undelegated += removed;
+            take(
+                &mut state,
+                &msg.delegator_address,
+                &msg.validator_address,
+                removed,
+            )?;
+        }
+
+        // stake only enters or leaves the DAOs by the difference between the totals
+        let total_current: Uint128 = current.iter().map(|d| d.amount).sum();
+        let total_target: Uint128 = targets.iter().map(|d| d.amount).sum();
+        prop_assert_eq!(
+            total_current + delegated,
+            total_target + undelegated,
+            "totals not conserved"
+        );
+        prop_assert!(
+            delegated.is_zero() || undelegated.is_zero(),
+            "plan both delegates {} and undelegates {}",
+            delegated,
+            undelegated
+        );
+
+        let mut live: BTreeMap<String, Uint128> = BTreeMap::new();
+        for ((_, val), held) in state {
+            *live.entry(val).or_default() += held;
+        }
+        for target in targets {
+            prop_assert_eq!(
+                live.remove(&target.operator_addr).unwrap_or_default(),
+                target.amount,
+                "target {} missed",
+                target.operator_addr
+            );
+        }
+        prop_assert!(
+            live.values().all(|held| held.is_zero()),
+            "stake left off target: {:?}",
+            live
+        );
+        Ok(())
+    }
+
+    fn delegation(del: &str, val: &str, amount: u128) -> Delegation {
+        Delegation {
+            del_addr: del.to_string(),
+            operator_addr: val.to_string(),
+            amount: Uint128::new(amount),
+        }
+    }
+
+    /// Up to 3 DAOs with at most one delegation each on up to 8 validators, and targets on a
+    /// random subset of the same validators, delegated from the CSV's empty delegator.
+    fn plan_inputs() -> impl Strategy<Value = (Vec<Delegation>, Vec<Delegation>)> {
+        let current = prop::collection::btree_map((0..3usize, 0..8usize), 1..1_000u128, 0..12);
+        let targets = prop::collection::btree_map(0..8usize, 1..1_000u128, 1..8);
+        (current, targets).prop_map(|(current, targets)| {
+            (
+                current
+                    .into_iter()
+                    .map(|((dao, val), amount)| {
+                        delegation(&format!("dao{}", dao), &format!("val{}", val), amount)
+                    })
+                    .collect(),
+                targets
+                    .into_iter()
+                    .map(|(val, amount)| delegation("", &format!("val{}", val), amount))
+                    .collect(),
+            )
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_optimize_delegations_invariants((current, targets) in plan_inputs()) {
+            check_plan(&current, &targets)?;
+        }
+
+        #[test]
+        fn test_optimize_delegations_invariants_at_equal_totals(
+            (current, mut targets) in plan_inputs()
+        ) {
+            // realignment proper: the DAOs already hold exactly the obligated total
+            let total_current: u128 = current.iter().map(|d| d.amount.u128()).sum();
+            let total_target: u128 = targets.iter().map(|d| d.amount.u128()).sum();
+            if total_current > total_target {
+                targets[0].amount += Uint128::new(total_current - total_target);
+            } else {
+                prop_assume!(total_current > 0);
+                let mut excess = total_target - total_current;
+                for target in targets.iter_mut() {
+                    let cut = excess.min(target.amount.u128());
+                    target.amount -= Uint128::new(cut);
+                    excess -= cut;
+                }
+                targets.retain(|t| !t.amount.is_zero());
+            }
+            check_plan(&current, &targets)?;
+        }
+    }
 
     #[test]
-    fn test_accuracy_delegations_message_json() -> anyhow::Result<()> {
+    fn test_shared_excess_is_not_overdrawn() -> anyhow::Result<()> {
+        // val0 has an excess of 1 over two delegations, only 1 may leave it
+        let current = [
+            delegation("dao0", "val0", 629),
+            delegation("dao2", "val0", 1),
+        ];
+        let targets = [delegation("", "val0", 629), delegation("", "val1", 2)];
+        check_plan(&current, &targets).map_err(|e| anyhow::anyhow!("{}", e))
+    }
+
+    #[test]
+    fn test_direct_delegations_are_funded_by_dao_balances() {
+        let current = [delegation("dao0", "val0", 100)];
+        let targets = [delegation("", "val0", 100), delegation("", "val1", 50)];
+        let liquid = BTreeMap::from([
+            ("dao0".to_string(), Uint128::new(20)),
+            ("dao1".to_string(), Uint128::new(10)),
+        ]);
+        let (_, delegations, _) =
+            optimize_delegations(current.to_vec(), &targets, &liquid, "ubtsg");
+
+        // 20 short of the target, nothing is left to a delegator-less message
+        let funded: Vec<_> = delegations
+            .iter()
+            .map(|m| (m.delegator_address.as_str(), amount(&m.amount).u128()))
+            .collect();
+        assert_eq!(funded, [("dao0", 20), ("dao1", 10)]);
+    }
+
+    #[test]
+    fn test_unparsable_target_rows_fail_the_load() -> anyhow::Result<()> {
+        let csv = std::env::temp_dir().join(format!("targets-test-{}.csv", std::process::id()));
+        std::fs::write(&csv, "valA,100\nvalB,ten\n")?;
+        let err = load_new_delegations(csv.to_str().unwrap(), false).unwrap_err();
+        std::fs::remove_file(&csv)?;
+        assert!(err.to_string().contains("row 2: invalid amount"), "{}", err);
+
+        assert!(load_new_delegations("missing-targets.csv", false).is_err());
         Ok(())
     }
 
@@ -1315,8 +2150,9 @@ mod tests {
     #[test]
     fn test_yes_no_load_obligated_delegations_file() -> anyhow::Result<()> {
         // Try with both header settings to see which matches expected value
-        let aad_with_header = load_new_delegations(NEW_DELS_FILE, true);
-        let aad_without_header = load_new_delegations(NEW_DELS_FILE, false);
+        let targets_csv = ChainProfile::bitsong_mainnet().targets_csv.unwrap();
+        let aad_with_header = load_new_delegations(&targets_csv, true)?;
+        let aad_without_header = load_new_delegations(&targets_csv, false)?;
 
         println!(
             "With header: {} delegations, total {}",
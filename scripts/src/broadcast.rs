@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use cosmos_sdk_proto::cosmos::{
+    base::abci::v1beta1::TxResponse,
+    tx::v1beta1::{service_client::ServiceClient, GetTxRequest},
+};
+use cosmwasm_schema::cw_serde;
+
+use crate::endpoints::Endpoints;
+
+/// Blocks after broadcast a bundle may take to be included before it is considered lost.
+pub const INCLUSION_TIMEOUT_BLOCKS: u64 = 10;
+pub const INCLUSION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Result of a tx once it landed in a block.
+#[cw_serde]
+pub struct TxOutcome {
+    pub txhash: String,
+    pub height: u64,
+    pub codespace: String,
+    pub code: u32,
+    pub raw_log: String,
+    pub gas_used: u64,
+}
+
+impl TxOutcome {
+    pub fn from_response(resp: TxResponse) -> Self {
+        TxOutcome {
+            txhash: resp.txhash,
+            height: resp.height.max(0) as u64,
+            codespace: resp.codespace,
+            code: resp.code,
+            raw_log: resp.raw_log,
+            gas_used: resp.gas_used.max(0) as u64,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.code == 0
+    }
+}
+
+/// Chain access needed to follow a broadcast tx until it is included.
+#[allow(async_fn_in_trait)]
+pub trait TxTracker {
+    /// Returns `None` while the tx is not indexed yet.
+    async fn find_tx(&mut self, hash: &str) -> anyhow::Result<Option<TxOutcome>>;
+    async fn latest_height(&mut self) -> anyhow::Result<u64>;
+}
+
+/// Polls `hash` until it is included, failing once the chain moves past `timeout_height` without it.
+pub async fn wait_for_inclusion(
+    tracker: &mut impl TxTracker,
+    hash: &str,
+    timeout_height: u64,
+    poll_interval: Duration,
+) -> anyhow::Result<TxOutcome> {
+    loop {
+        if let Some(outcome) = tracker.find_tx(hash).await? {
+            return Ok(outcome);
+        }
+
+        let latest = tracker.latest_height().await?;
+        anyhow::ensure!(
+            latest <= timeout_height,
+            "tx {} was not included before timeout height {} (latest height {})",
+            hash,
+            timeout_height,
+            latest
+        );
+        log::debug!(
+            "tx {} not included yet at height {}, waiting until {}",
+            hash,
+            latest,
+            timeout_height
+        );
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+/// [`TxTracker`] backed by the gRPC tx service of the healthy endpoints.
+pub struct GrpcTxTracker {
+    endpoints: Endpoints,
+}
+
+impl GrpcTxTracker {
+    pub fn new(endpoints: Endpoints) -> Self {
+        GrpcTxTracker { endpoints }
+    }
+}
+
+impl TxTracker for GrpcTxTracker {
+    async fn find_tx(&mut self, hash: &str) -> anyhow::Result<Option<TxOutcome>> {
+        let resp = self
+            .endpoints
+            .call("tx/get_tx", |channel| {
+                let request = GetTxRequest {
+                    hash: hash.to_string(),
+                };
+                async move { ServiceClient::new(channel).get_tx(request).await }
+            })
+            .await;
+
+        match resp {
+            Ok(resp) => Ok(resp.into_inner().tx_response.map(TxOutcome::from_response)),
+            Err(status) if status.code() == tonic::Code::NotFound => Ok(None),
+            // the tx indexer reports not-yet-indexed txs as a generic error on some nodes
+            Err(status) if status.message().contains("not found") => Ok(None),
+            Err(status) => Err(status.into()),
+        }
+    }
+
+    async fn latest_height(&mut self) -> anyhow::Result<u64> {
+        self.endpoints.latest_height().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    struct MockTracker {
+        height: u64,
+        included_at: Option<u64>,
+        code: u32,
+    }
+
+    impl TxTracker for MockTracker {
+        async fn find_tx(&mut self, hash: &str) -> anyhow::Result<Option<TxOutcome>> {
+            Ok(match self.included_at {
+                Some(height) if self.height >= height => Some(TxOutcome {
+                    txhash: hash.to_string(),
+                    height,
+                    codespace: if self.code == 0 { "" } else { "staking" }.to_string(),
+                    code: self.code,
+                    raw_log: String::new(),
+                    gas_used: 0,
+                }),
+                _ => None,
+            })
+        }
+
+        async fn latest_height(&mut self) -> anyhow::Result<u64> {
+            // every poll sees a new block
+            self.height += 1;
+            Ok(self.height)
+        }
+    }
+
+    #[test]
+    fn test_waits_until_tx_is_included() -> anyhow::Result<()> {
+        let mut tracker = MockTracker {
+            height: 100,
+            included_at: Some(103),
+            code: 0,
+        };
+        let outcome = Runtime::new()?.block_on(wait_for_inclusion(
+            &mut tracker,
+            "HASH",
+            110,
+            Duration::ZERO,
+        ))?;
+
+        assert_eq!(outcome.height, 103);
+        assert!(outcome.is_success());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gives_up_after_timeout_height() -> anyhow::Result<()> {
+        let mut tracker = MockTracker {
+            height: 100,
+            included_at: None,
+            code: 0,
+        };
+        let err = Runtime::new()?
+            .block_on(wait_for_inclusion(
+                &mut tracker,
+                "HASH",
+                105,
+                Duration::ZERO,
+            ))
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("before timeout height 105"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_code_is_reported() -> anyhow::Result<()> {
+        let mut tracker = MockTracker {
+            height: 100,
+            included_at: Some(101),
+            code: 33,
+        };
+        let outcome = Runtime::new()?.block_on(wait_for_inclusion(
+            &mut tracker,
+            "HASH",
+            110,
+            Duration::ZERO,
+        ))?;
+
+        assert!(!outcome.is_success());
+        assert_eq!((outcome.codespace.as_str(), outcome.code), ("staking", 33));
+        Ok(())
+    }
+}
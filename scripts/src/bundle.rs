@@ -0,0 +1,509 @@
+use std::collections::BTreeMap;
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Coin, Uint128};
+
+use crate::{
+    broadcast::TxOutcome,
+    plan::{PlanEntry, PlanEntryId, PlanMsg},
+    precheck::{destinations, source_outflows},
+    rewards::withdrawn_from,
+};
+
+pub const MAX_MSGS_PER_BUNDLE: usize = 32;
+pub const DEFAULT_MAX_TX_GAS: u64 = 10_000_000;
+
+/// One transaction worth of plan entries, all signed on behalf of `dao` via authz.
+#[cw_serde]
+pub struct Bundle {
+    pub dao: String,
+    pub entries: Vec<PlanEntryId>,
+}
+
+/// Partitions every plan entry into bundles of at most `max_msgs`, grouped by DAO in `dao_addrs` order.
+/// Entries keep their plan order inside a DAO. Fails instead of dropping entries whose delegator is not one of `dao_addrs`.
+pub fn schedule_bundles(
+    entries: &[PlanEntry],
+    dao_addrs: &[String],
+    max_msgs: usize,
+) -> anyhow::Result<Vec<Bundle>> {
+    anyhow::ensure!(max_msgs > 0, "bundles must hold at least one message");
+
+    let mut by_dao: BTreeMap<&str, Vec<PlanEntryId>> = BTreeMap::new();
+    for dao in dao_addrs {
+        anyhow::ensure!(
+            by_dao.insert(dao.as_str(), Vec::new()).is_none(),
+            "DAO {} is listed more than once",
+            dao
+        );
+    }
+
+    let mut unknown = Vec::new();
+    for entry in entries {
+        match by_dao.get_mut(entry.msg.delegator()) {
+            Some(ids) => ids.push(entry.id),
+            None => unknown.push(format!("{} ({})", entry.id, entry.msg.delegator())),
+        }
+    }
+    anyhow::ensure!(
+        unknown.is_empty(),
+        "plan entries delegate from addresses that are not DAOs: {}",
+        unknown.join(", ")
+    );
+
+    let mut bundles = Vec::new();
+    for dao in dao_addrs {
+        for chunk in by_dao[dao.as_str()].chunks(max_msgs) {
+            bundles.push(Bundle {
+                dao: dao.clone(),
+                entries: chunk.to_vec(),
+            });
+        }
+    }
+    Ok(bundles)
+}
+
+/// Ceilings a bundle (and the whole plan) must fit under once simulated.
+#[derive(Clone, Debug)]
+pub struct GasLimits {
+    /// Maximum gas a single bundle tx may use, must stay below the block gas limit.
+    pub max_tx_gas: u64,
+    /// Maximum fee, in the chain's fee denom, the whole plan may spend.
+    pub fee_budget: Option<Coin>,
+}
+
+impl Default for GasLimits {
+    fn default() -> Self {
+        GasLimits {
+            max_tx_gas: DEFAULT_MAX_TX_GAS,
+            fee_budget: None,
+        }
+    }
+}
+
+/// Gas and fee a bundle is expected to cost, as returned by simulation.
+#[cw_serde]
+#[derive(Copy)]
+pub struct GasEstimate {
+    pub gas: u64,
+    pub fee: Uint128,
+}
+
+#[cw_serde]
+pub struct PackedBundle {
+    pub bundle: Bundle,
+    pub estimate: GasEstimate,
+}
+
+/// Whether `msgs` rely on what the DAO's `earlier` messages, broadcast in earlier bundles, leave
+/// behind: they spend liquid funds after rewards were withdrawn, or move stake away from a
+/// validator that received some. Simulated against the chain as it is, they would fail.
+pub fn needs_earlier_bundles(earlier: &[PlanMsg], msgs: &[PlanMsg]) -> anyhow::Result<bool> {
+    let withdrew = earlier.iter().any(|m| !withdrawn_from(m).is_empty());
+    let spends = msgs
+        .iter()
+        .any(|m| matches!(m, PlanMsg::Delegate(_) | PlanMsg::Send(_)));
+    let received = destinations(earlier);
+    let moves_received = source_outflows(msgs)?
+        .into_keys()
+        .any(|(_, validator)| received.contains(&validator));
+    Ok((withdrew && spends) || moves_received)
+}
+
+/// Estimate of `bundle` at the highest gas per entry of its DAO's bundles packed so far, `None`
+/// before the first of them.
+fn extrapolate(packed: &[PackedBundle], bundle: &Bundle) -> Option<GasEstimate> {
+    let costliest = packed
+        .iter()
+        .filter(|p| p.bundle.dao == bundle.dao && p.estimate.gas > 0)
+        .max_by(|a, b| {
+            (a.estimate.gas as u128 * b.bundle.entries.len() as u128)
+                .cmp(&(b.estimate.gas as u128 * a.bundle.entries.len() as u128))
+        })?;
+    let gas = (costliest.estimate.gas as u128 * bundle.entries.len() as u128)
+        .div_ceil(costliest.bundle.entries.len() as u128);
+    Some(GasEstimate {
+        gas: u64::try_from(gas).unwrap_or(u64::MAX),
+        fee: costliest
+            .estimate
+            .fee
+            .multiply_ratio(gas, costliest.estimate.gas),
+    })
+}
+
+/// Simulates each scheduled bundle and splits it until it fits `limits.max_tx_gas`.
+/// The shrunk bundle is re-simulated, and the remaining entries become the next candidate, so no entry is dropped.
+/// Bundles that `needs_earlier` says depend on the state earlier bundles leave cannot be simulated
+/// before those executed, they are estimated from their DAO's simulated bundles instead and
+/// simulated on broadcast.
+/// Fails if a single entry exceeds the ceiling or the plan's total fee exceeds `limits.fee_budget`.
+pub fn pack_bundles(
+    bundles: Vec<Bundle>,
+    limits: &GasLimits,
+    needs_earlier: impl Fn(&Bundle) -> anyhow::Result<bool>,
+    mut simulate: impl FnMut(&Bundle) -> anyhow::Result<GasEstimate>,
+) -> anyhow::Result<Vec<PackedBundle>> {
+    let mut packed = Vec::new();
+
+    for bundle in bundles {
+        let mut remaining = bundle.entries.as_slice();
+        while !remaining.is_empty() {
+            let mut len = remaining.len();
+            loop {
+                let candidate = Bundle {
+                    dao: bundle.dao.clone(),
+                    entries: remaining[..len].to_vec(),
+                };
+                let estimate = match needs_earlier(&candidate)? {
+                    true => match extrapolate(&packed, &candidate) {
+                        Some(estimate) => {
+                            log::info!(
+                                "bundle of {} msgs for {} relies on earlier bundles, estimated at {} gas instead of simulated",
+                                len,
+                                bundle.dao,
+                                estimate.gas
+                            );
+                            estimate
+                        }
+                        None => simulate(&candidate)?,
+                    },
+                    false => simulate(&candidate)?,
+                };
+                if estimate.gas <= limits.max_tx_gas {
+                    packed.push(PackedBundle {
+                        bundle: candidate,
+                        estimate,
+                    });
+                    break;
+                }
+
+                anyhow::ensure!(
+                    len > 1,
+                    "{} alone needs {} gas, above the {} gas ceiling",
+                    remaining[0],
+                    estimate.gas,
+                    limits.max_tx_gas
+                );
+                // shrink proportionally to the overshoot, always dropping at least one entry
+                let fitting =
+                    (len as u128 * limits.max_tx_gas as u128 / estimate.gas as u128) as usize;
+                let shrunk = fitting.clamp(1, len - 1);
+                log::info!(
+                    "bundle of {} msgs for {} needs {} gas (ceiling {}), retrying with {}",
+                    len,
+                    bundle.dao,
+                    estimate.gas,
+                    limits.max_tx_gas,
+                    shrunk
+                );
+                len = shrunk;
+            }
+            remaining = &remaining[len..];
+        }
+    }
+
+    let total_fee = total_fee(&packed);
+    if let Some(budget) = &limits.fee_budget {
+        anyhow::ensure!(
+            total_fee <= budget.amount,
+            "expected fee of {}{} for {} bundles exceeds the fee budget of {}",
+            total_fee,
+            budget.denom,
+            packed.len(),
+            budget
+        );
+    }
+    Ok(packed)
+}
+
+pub fn total_fee(packed: &[PackedBundle]) -> Uint128 {
+    packed.iter().map(|p| p.estimate.fee).sum()
+}
+
+#[cw_serde]
+pub struct SubmittedBundle {
+    pub dao: String,
+    pub entries: Vec<PlanEntryId>,
+    pub txhash: String,
+    pub height: u64,
+    pub code: u32,
+}
+
+/// Which plan entries went out in which transaction.
+#[cw_serde]
+#[derive(Default)]
+pub struct SubmissionLog {
+    pub submitted: Vec<SubmittedBundle>,
+}
+
+impl SubmissionLog {
+    pub fn record(&mut self, bundle: &Bundle, outcome: &TxOutcome) {
+        self.submitted.push(SubmittedBundle {
+            dao: bundle.dao.clone(),
+            entries: bundle.entries.clone(),
+            txhash: outcome.txhash.clone(),
+            height: outcome.height,
+            code: outcome.code,
+        });
+    }
+
+    /// Errors unless every plan entry was submitted exactly once.
+    pub fn ensure_complete(&self, entries: &[PlanEntry]) -> anyhow::Result<()> {
+        let mut seen: BTreeMap<PlanEntryId, usize> = BTreeMap::new();
+        for id in self.submitted.iter().flat_map(|b| &b.entries) {
+            *seen.entry(*id).or_default() += 1;
+        }
+
+        let missing: Vec<String> = entries
+            .iter()
+            .filter(|e| !seen.contains_key(&e.id))
+            .map(|e| e.id.to_string())
+            .collect();
+        let duplicated: Vec<String> = seen
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|(id, _)| id.to_string())
+            .collect();
+        let unplanned: Vec<String> = seen
+            .keys()
+            .filter(|id| !entries.iter().any(|e| e.id == **id))
+            .map(|id| id.to_string())
+            .collect();
+
+        anyhow::ensure!(
+            missing.is_empty() && duplicated.is_empty() && unplanned.is_empty(),
+            "submission does not match the plan. missing: [{}], submitted twice: [{}], not in plan: [{}]",
+            missing.join(", "),
+            duplicated.join(", "),
+            unplanned.join(", ")
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::{DelegateMsg, PlanSection, WithdrawRewardsMsg};
+
+    fn included(txhash: &str) -> TxOutcome {
+        TxOutcome {
+            txhash: txhash.to_string(),
+            height: 1,
+            codespace: String::new(),
+            code: 0,
+            raw_log: String::new(),
+            gas_used: 0,
+        }
+    }
+
+    fn delegations(dao: &str, count: usize, offset: usize) -> Vec<PlanEntry> {
+        (0..count)
+            .map(|i| PlanEntry {
+                id: PlanEntryId {
+                    section: PlanSection::Delegation,
+                    index: offset + i,
+                },
+                msg: PlanMsg::Delegate(DelegateMsg {
+                    delegator_address: dao.to_string(),
+                    validator_address: format!("val{}", i),
+                    amount: "1".to_string(),
+                    denom: "ubtsg".to_string(),
+                }),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_schedule_keeps_trailing_partial_bundle() -> anyhow::Result<()> {
+        let entries = delegations("dao1", 40, 0);
+        let bundles = schedule_bundles(&entries, &["dao1".to_string()], MAX_MSGS_PER_BUNDLE)?;
+
+        assert_eq!(bundles.len(), 2);
+        assert_eq!(bundles[0].entries.len(), 32);
+        assert_eq!(bundles[1].entries.len(), 8);
+
+        let mut log = SubmissionLog::default();
+        for (i, bundle) in bundles.iter().enumerate() {
+            log.record(bundle, &included(&format!("hash{}", i)));
+        }
+        log.ensure_complete(&entries)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_schedule_partitions_every_dao_exactly_once() -> anyhow::Result<()> {
+        let daos = vec!["dao1".to_string(), "dao2".to_string()];
+        let mut entries = delegations("dao2", 64, 0);
+        entries.extend(delegations("dao1", 5, 64));
+
+        let bundles = schedule_bundles(&entries, &daos, MAX_MSGS_PER_BUNDLE)?;
+        let sizes: Vec<(&str, usize)> = bundles
+            .iter()
+            .map(|b| (b.dao.as_str(), b.entries.len()))
+            .collect();
+        assert_eq!(sizes, vec![("dao1", 5), ("dao2", 32), ("dao2", 32)]);
+
+        let mut scheduled: Vec<PlanEntryId> =
+            bundles.iter().flat_map(|b| b.entries.clone()).collect();
+        scheduled.sort();
+        let mut planned: Vec<PlanEntryId> = entries.iter().map(|e| e.id).collect();
+        planned.sort();
+        assert_eq!(scheduled, planned);
+        Ok(())
+    }
+
+    #[test]
+    fn test_schedule_rejects_unknown_delegator() {
+        let entries = delegations("not-a-dao", 1, 0);
+        let err = schedule_bundles(&entries, &["dao1".to_string()], MAX_MSGS_PER_BUNDLE)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("delegations[0]"), "{}", err);
+    }
+
+    // Every message costs 100k gas and 1000ubtsg of fee
+    fn linear_estimate(bundle: &Bundle) -> anyhow::Result<GasEstimate> {
+        Ok(GasEstimate {
+            gas: 100_000 * bundle.entries.len() as u64,
+            fee: Uint128::new(1000 * bundle.entries.len() as u128),
+        })
+    }
+
+    #[test]
+    fn test_pack_shrinks_bundles_over_gas_ceiling() -> anyhow::Result<()> {
+        let entries = delegations("dao1", 40, 0);
+        let bundles = schedule_bundles(&entries, &["dao1".to_string()], MAX_MSGS_PER_BUNDLE)?;
+        let limits = GasLimits {
+            max_tx_gas: 1_250_000,
+            fee_budget: None,
+        };
+
+        let mut simulations = 0;
+        let packed = pack_bundles(
+            bundles,
+            &limits,
+            |_| Ok(false),
+            |b| {
+                simulations += 1;
+                linear_estimate(b)
+            },
+        )?;
+
+        let sizes: Vec<usize> = packed.iter().map(|p| p.bundle.entries.len()).collect();
+        assert_eq!(sizes, vec![12, 12, 8, 8]);
+        assert!(packed.iter().all(|p| p.estimate.gas <= limits.max_tx_gas));
+        assert_eq!(total_fee(&packed), Uint128::new(40_000));
+        assert_eq!(simulations, 6);
+
+        let mut log = SubmissionLog::default();
+        for p in &packed {
+            log.record(&p.bundle, &included("hash"));
+        }
+        log.ensure_complete(&entries)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_fails_on_single_entry_over_ceiling() {
+        let entries = delegations("dao1", 2, 0);
+        let bundles =
+            schedule_bundles(&entries, &["dao1".to_string()], MAX_MSGS_PER_BUNDLE).unwrap();
+        let limits = GasLimits {
+            max_tx_gas: 50_000,
+            fee_budget: None,
+        };
+
+        let err = pack_bundles(bundles, &limits, |_| Ok(false), linear_estimate)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("delegations[0] alone needs 100000 gas"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_pack_enforces_fee_budget() {
+        let entries = delegations("dao1", 10, 0);
+        let bundles =
+            schedule_bundles(&entries, &["dao1".to_string()], MAX_MSGS_PER_BUNDLE).unwrap();
+        let limits = GasLimits {
+            max_tx_gas: DEFAULT_MAX_TX_GAS,
+            fee_budget: Some(Coin {
+                denom: "uatom".to_string(),
+                amount: Uint128::new(9_999),
+            }),
+        };
+
+        let err = pack_bundles(bundles, &limits, |_| Ok(false), linear_estimate)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("exceeds the fee budget of 9999uatom"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_bundles_spending_earlier_withdrawals_are_not_simulated() -> anyhow::Result<()> {
+        let mut entries = delegations("dao1", 3, 0);
+        entries.insert(
+            0,
+            PlanEntry {
+                id: PlanEntryId {
+                    section: PlanSection::Withdrawal,
+                    index: 0,
+                },
+                msg: PlanMsg::WithdrawRewards(WithdrawRewardsMsg {
+                    delegator_address: "dao1".to_string(),
+                    validator_address: "val9".to_string(),
+                    amount: "5".to_string(),
+                    denom: "ubtsg".to_string(),
+                }),
+            },
+        );
+        let msgs: Vec<PlanMsg> = entries.iter().map(|e| e.msg.clone()).collect();
+        assert!(!needs_earlier_bundles(&[], &msgs)?);
+        assert!(needs_earlier_bundles(&msgs[..1], &msgs[1..])?);
+
+        // the second bundle delegates what the first withdrew, the chain cannot simulate it yet
+        let bundles = schedule_bundles(&entries, &["dao1".to_string()], 2)?;
+        let first = bundles[0].entries[0];
+        let packed = pack_bundles(
+            bundles,
+            &GasLimits::default(),
+            |b| Ok(b.entries[0] != first),
+            |b| {
+                anyhow::ensure!(b.entries[0] == first, "simulated {:?}", b.entries);
+                linear_estimate(b)
+            },
+        )?;
+        assert_eq!(packed.len(), 2);
+        assert_eq!(packed[1].estimate, linear_estimate(&packed[1].bundle)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_incomplete_submission_is_reported() {
+        let entries = delegations("dao1", 3, 0);
+        let mut log = SubmissionLog::default();
+        log.record(
+            &Bundle {
+                dao: "dao1".to_string(),
+                entries: vec![entries[0].id, entries[0].id],
+            },
+            &included("hash"),
+        );
+
+        let err = log.ensure_complete(&entries).unwrap_err().to_string();
+        assert!(
+            err.contains("missing: [delegations[1], delegations[2]]"),
+            "{}",
+            err
+        );
+        assert!(err.contains("submitted twice: [delegations[0]]"), "{}", err);
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use cosmwasm_std::Uint128;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    backend::{BankBackend, DistributionBackend, StakingBackend},
+    snapshot::{Delegation, DelegatorShares, PendingReward, Unbonding, ValidatorState},
+};
+
+/// Directory queries of pinned heights are cached in, one subdirectory per chain and height.
+pub const QUERY_CACHE_DIR: &str = ".query-cache";
+
+/// Caches the answers of a backend pinned to `height` on disk, keyed by chain, height and query.
+/// State at a height never changes, so entries never expire: planning again at the same height
+/// reads the cache instead of the node.
+pub struct QueryCache<B> {
+    inner: B,
+    dir: PathBuf,
+}
+
+impl<B> QueryCache<B> {
+    /// `inner` must answer every query of `chain_id` at `height`.
+    pub fn new(inner: B, root: impl AsRef<Path>, chain_id: &str, height: u64) -> Self {
+        QueryCache {
+            inner,
+            dir: root.as_ref().join(chain_id).join(height.to_string()),
+        }
+    }
+
+    fn path(&self, query: &str, args: &[&str]) -> PathBuf {
+        let mut name = query.to_string();
+        for arg in args {
+            name.push('_');
+            // bech32 addresses and denoms are safe, anything else is escaped
+            name.extend(arg.chars().map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+                _ => '~',
+            }));
+        }
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// The cached answer to `query`, else the answer of `fetch` after it was cached.
+    async fn cached<T, Fut>(&self, query: &str, args: &[&str], fetch: Fut) -> anyhow::Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        Fut: std::future::Future<Output = anyhow::Result<T>>,
+    {
+        let path = self.path(query, args);
+        if let Ok(content) = fs::read_to_string(&path) {
+            match serde_json::from_str(&content) {
+                Ok(value) => {
+                    log::debug!("{} {:?} served by {}", query, args, path.display());
+                    return Ok(value);
+                }
+                Err(err) => log::warn!("ignoring corrupt cache entry {}: {}", path.display(), err),
+            }
+        }
+
+        let value = fetch.await?;
+        fs::create_dir_all(&self.dir)?;
+        // written aside and renamed, so concurrent and interrupted runs never read half an entry
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&tmp, serde_json::to_string(&value)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(value)
+    }
+}
+
+impl<B: StakingBackend> StakingBackend for QueryCache<B> {
+    async fn block_height(&self) -> anyhow::Result<u64> {
+        self.inner.block_height().await
+    }
+
+    async fn delegator_delegations(&self, delegator: &str) -> anyhow::Result<Vec<Delegation>> {
+        self.cached(
+            "delegator_delegations",
+            &[delegator],
+            self.inner.delegator_delegations(delegator),
+        )
+        .await
+    }
+
+    async fn delegator_stake(
+        &self,
+        delegator: &str,
+    ) -> anyhow::Result<(Vec<Delegation>, Vec<DelegatorShares>)> {
+        self.cached(
+            "delegator_stake",
+            &[delegator],
+            self.inner.delegator_stake(delegator),
+        )
+        .await
+    }
+
+    async fn delegation(&self, delegator: &str, validator: &str) -> anyhow::Result<Uint128> {
+        self.cached(
+            "delegation",
+            &[delegator, validator],
+            self.inner.delegation(delegator, validator),
+        )
+        .await
+    }
+
+    async fn validator(&self, operator: &str) -> anyhow::Result<Option<ValidatorState>> {
+        self.cached("validator", &[operator], self.inner.validator(operator))
+            .await
+    }
+
+    async fn unbonding_delegations(&self, delegator: &str) -> anyhow::Result<Vec<Unbonding>> {
+        self.cached(
+            "unbonding_delegations",
+            &[delegator],
+            self.inner.unbonding_delegations(delegator),
+        )
+        .await
+    }
+}
+
+impl<B: BankBackend> BankBackend for QueryCache<B> {
+    async fn balance(&self, address: &str, denom: &str) -> anyhow::Result<Uint128> {
+        self.cached(
+            "balance",
+            &[address, denom],
+            self.inner.balance(address, denom),
+        )
+        .await
+    }
+}
+
+impl<B: DistributionBackend> DistributionBackend for QueryCache<B> {
+    async fn pending_rewards(
+        &self,
+        delegator: &str,
+        denom: &str,
+    ) -> anyhow::Result<Vec<PendingReward>> {
+        self.cached(
+            "pending_rewards",
+            &[delegator, denom],
+            self.inner.pending_rewards(delegator, denom),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::StakingSimulator;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_cached_queries_survive_a_changed_chain() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("query-cache-test-{}", std::process::id()));
+        let chain = StakingSimulator::new("ubtsg", "grantee", 100)
+            .with_delegation("dao1", "valA", 50)
+            .with_balance("dao1", 7);
+        let rt = Runtime::new()?;
+
+        let cache = QueryCache::new(chain.clone(), &dir, "bitsong-2b", 100);
+        assert_eq!(
+            rt.block_on(cache.delegator_delegations("dao1"))?[0].amount,
+            Uint128::new(50)
+        );
+        assert_eq!(
+            rt.block_on(cache.balance("dao1", "ubtsg"))?,
+            Uint128::new(7)
+        );
+
+        // a later run at the same height reads the cache, another height or chain reads the chain
+        let moved = chain.clone().with_delegation("dao1", "valB", 5);
+        let cache = QueryCache::new(moved.clone(), &dir, "bitsong-2b", 100);
+        assert_eq!(rt.block_on(cache.delegator_delegations("dao1"))?.len(), 1);
+        let cache = QueryCache::new(moved.clone(), &dir, "bitsong-testnet-1", 100);
+        assert_eq!(rt.block_on(cache.delegator_delegations("dao1"))?.len(), 2);
+        let cache = QueryCache::new(moved, &dir, "bitsong-2b", 101);
+        assert_eq!(rt.block_on(cache.delegator_delegations("dao1"))?.len(), 2);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Decimal, Uint128};
+use cw_orch::environment::{ChainInfoOwned, ChainKind, NetworkInfoOwned};
+
+/// Everything the planner assumes about the chain it runs on: its staking denom, how amounts
+/// are displayed, its address prefixes and key derivation, and the endpoints to query.
+#[cw_serde]
+pub struct ChainProfile {
+    pub chain_id: String,
+    /// Network identifier, e.g. `bitsong`.
+    pub chain_name: String,
+    /// `mainnet`, `testnet` or `local`.
+    pub kind: String,
+    /// Staking and fee denom, e.g. `ubtsg`.
+    pub denom: String,
+    /// Denom amounts are displayed in, e.g. `BTSG`.
+    pub display_denom: String,
+    /// Decimals between `denom` and `display_denom`.
+    pub decimals: u32,
+    /// Bech32 prefix of accounts, e.g. `bitsong`.
+    pub account_prefix: String,
+    /// Bech32 prefix of validator operators, e.g. `bitsongvaloper`.
+    pub validator_prefix: String,
+    /// Bech32 prefix of validator consensus addresses, e.g. `bitsongvalcons`.
+    pub consensus_prefix: String,
+    /// BIP-44 coin type the wallet's key is derived with.
+    pub coin_type: u32,
+    /// Gas price in `denom`.
+    pub gas_price: f64,
+    /// Seconds undelegated stake takes to unbond, the staking module's `unbonding_time`.
+    pub unbonding_time: u64,
+    pub grpc_urls: Vec<String>,
+    /// DAOs whose stake is realigned.
+    #[serde(default)]
+    pub dao_addrs: Vec<String>,
+    /// Validators under private agreements, the DAOs' stake on them is never realigned.
+    #[serde(default)]
+    pub omitted_validators: Vec<String>,
+    /// CSV of the target delegations, one `validator,amount` row each.
+    #[serde(default)]
+    pub targets_csv: Option<String>,
+    /// Total in `denom` the targets CSV must add up to, unchecked when unset.
+    #[serde(default)]
+    pub obligated_total: Option<Uint128>,
+}
+
+impl ChainProfile {
+    pub fn bitsong_mainnet() -> Self {
+        ChainProfile {
+            chain_id: "bitsong-2b".to_string(),
+            chain_name: "bitsong".to_string(),
+            kind: "mainnet".to_string(),
+            denom: "ubtsg".to_string(),
+            display_denom: "BTSG".to_string(),
+            decimals: 6,
+            account_prefix: "bitsong".to_string(),
+            validator_prefix: "bitsongvaloper".to_string(),
+            consensus_prefix: "bitsongvalcons".to_string(),
+            coin_type: 639,
+            gas_price: 0.025,
+            unbonding_time: 21 * 24 * 60 * 60,
+            grpc_urls: vec!["http://bitsong-grpc.polkachu.com:16090".to_string()],
+            dao_addrs: [
+                "bitsong166d42nyufxrh3jps5wx3egdkmvvg7jl6k33yut",
+                "bitsong1nphhydjshzjevd03afzlce0xnlrnsm27hy9hgd",
+                "bitsong1tgzday8yewn8n5j0prgsc9t5r3gg2cwnyf9jlv",
+            ]
+            .map(String::from)
+            .to_vec(),
+            omitted_validators: [
+                "bitsongvaloper19ah9302mh80pvv5zeztdr6qcqk6z52frn6rjj5",
+                "bitsongvaloper1wf3q0a3uzechxvf27reuqts8nqm45sn2yq26g3",
+                "bitsongvaloper10fg3yklae97g8ueh5ut29mlwz8fdr6z8zrak6x",
+                "bitsongvaloper1fkj2cn209yeexxyets98evrcmmds23hck0lyzq",
+                "bitsongvaloper1wetqg989uyj3mpk07h8yt3qvu2cdlsv7fp3zda",
+                "bitsongvaloper1jxv0u20scum4trha72c7ltfgfqef6nscl86wxa",
+            ]
+            .map(String::from)
+            .to_vec(),
+            targets_csv: Some("./src/bin/data/new-delegations.csv".to_string()),
+            obligated_total: Some(Uint128::new(9_999_980_000_000)),
+        }
+    }
+
+    /// The profile `--network` names, `None` for networks without a built-in profile.
+    pub fn builtin(network: &str) -> Option<Self> {
+        match network {
+            "main" => Some(Self::bitsong_mainnet()),
+            _ => None,
+        }
+    }
+
+    /// Reads a profile from a JSON file, for chains and testnets without a built-in one.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read chain profile {}: {}", path, e))?;
+        let profile: Self = serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("invalid chain profile {}: {}", path, e))?;
+        anyhow::ensure!(
+            profile.decimals <= Decimal::DECIMAL_PLACES,
+            "{} has {} decimals, at most {} are supported",
+            profile.display_denom,
+            profile.decimals,
+            Decimal::DECIMAL_PLACES
+        );
+        Ok(profile)
+    }
+
+    /// The targets CSV, which only profiles of a realigned program name.
+    pub fn targets_csv(&self) -> anyhow::Result<&str> {
+        self.targets_csv
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("chain profile {} names no targets_csv", self.chain_id))
+    }
+
+    /// `omitted_validators` as the planner's ignore list.
+    pub fn omitted(&self) -> Vec<&str> {
+        self.omitted_validators.iter().map(String::as_str).collect()
+    }
+
+    /// `amount` of `denom` in `display_denom`, e.g. `1.5` for 1500000ubtsg.
+    pub fn display_amount(&self, amount: Uint128) -> String {
+        Decimal::from_atomics(amount, self.decimals)
+            .map(|d| d.to_string())
+            .unwrap_or_else(|_| format!("{}{}", amount, self.denom))
+    }
+
+    /// The profile as cw-orch connects to and signs for the chain.
+    pub fn chain_info(&self) -> ChainInfoOwned {
+        ChainInfoOwned {
+            chain_id: self.chain_id.clone(),
+            gas_denom: self.denom.clone(),
+            gas_price: self.gas_price,
+            grpc_urls: self.grpc_urls.clone(),
+            lcd_url: None,
+            fcd_url: None,
+            network_info: NetworkInfoOwned {
+                chain_name: self.chain_name.clone(),
+                pub_address_prefix: self.account_prefix.clone(),
+                coin_type: self.coin_type,
+            },
+            kind: ChainKind::from(self.kind.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amounts_are_displayed_with_the_profiles_decimals() {
+        let bitsong = ChainProfile::bitsong_mainnet();
+        assert_eq!(bitsong.display_amount(Uint128::new(1_500_000)), "1.5");
+
+        let eighteen = ChainProfile {
+            denom: "aevmos".to_string(),
+            decimals: 18,
+            ..bitsong.clone()
+        };
+        assert_eq!(
+            eighteen.display_amount(Uint128::new(2_000_000_000_000_000_000)),
+            "2"
+        );
+        assert_eq!(bitsong.chain_info().network_info.coin_type, 639);
+        assert_eq!(bitsong.chain_info().kind, ChainKind::Mainnet);
+    }
+
+    #[test]
+    fn test_profiles_without_a_program_load() {
+        let profile: ChainProfile = serde_json::from_str(
+            r#"{
+                "chain_id": "testnet-1",
+                "chain_name": "bitsong",
+                "kind": "testnet",
+                "denom": "utest",
+                "display_denom": "TEST",
+                "decimals": 6,
+                "account_prefix": "bitsong",
+                "validator_prefix": "bitsongvaloper",
+                "consensus_prefix": "bitsongvalcons",
+                "coin_type": 639,
+                "gas_price": 0.025,
+                "unbonding_time": 1814400,
+                "grpc_urls": []
+            }"#,
+        )
+        .unwrap();
+        assert!(profile.dao_addrs.is_empty());
+        assert!(profile.omitted().is_empty());
+        assert_eq!(profile.obligated_total, None);
+        assert!(profile
+            .targets_csv()
+            .unwrap_err()
+            .to_string()
+            .contains("testnet-1 names no targets_csv"));
+    }
+}
@@ -0,0 +1,386 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::Uint128;
+
+use crate::{
+    plan::{DelegateMsg, MessageExport, PlanMsg, PlanSection},
+    snapshot::{ChainSnapshot, Delegation},
+};
+
+/// Targets a compounded plan aligns to, written next to `delegation_messages.json` in the
+/// target CSV format so `verify` and `dry-run` can check the plan against them.
+pub const COMPOUNDED_TARGETS_CSV: &str = "delegation_targets_compounded.csv";
+
+/// Base units of the staking denom each DAO keeps liquid for fees unless `--compound-reserve`
+/// says otherwise.
+pub const DEFAULT_RESERVE: u128 = 10_000_000;
+
+/// Where the DAOs' liquid funds above the reserve are delegated to.
+#[cw_serde]
+#[derive(Copy)]
+pub enum CompoundMode {
+    /// Fills the validators the plan leaves below their target, largest gap first. Targets do not
+    /// change, what no gap takes stays liquid.
+    UnderTarget,
+    /// Spreads it over the active targets by their weight, raising every target alike.
+    Proportional,
+}
+
+impl FromStr for CompoundMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "under-target" => Ok(CompoundMode::UnderTarget),
+            "proportional" => Ok(CompoundMode::Proportional),
+            other => anyhow::bail!(
+                "unknown compounding mode {}, expected under-target or proportional",
+                other
+            ),
+        }
+    }
+}
+
+impl fmt::Display for CompoundMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CompoundMode::UnderTarget => "under-target",
+            CompoundMode::Proportional => "proportional",
+        })
+    }
+}
+
+/// Delegations one compounding run added to the plan.
+#[cw_serde]
+pub struct Compounding {
+    pub mode: CompoundMode,
+    /// Amount each DAO keeps liquid.
+    pub reserve: Uint128,
+    /// Amount each DAO delegated, keyed by DAO.
+    pub compounded: BTreeMap<String, Uint128>,
+    /// Targets the plan aligns to once compounded, the loaded ones unless `Proportional`.
+    pub targets: Vec<Delegation>,
+}
+
+/// Delegates each DAO's liquid `denom` above `reserve` in the plan's delegations section. The
+/// liquid balance is the one left once the messages ahead of these delegations executed,
+/// undelegations are broadcast after them. The planner's own delegations are kept and count
+/// towards the gaps, only what is left above the reserve once they are funded is compounded.
+/// Inactive targets receive nothing, delegating to them fails the bundle's precheck.
+pub fn compound(
+    export: &mut MessageExport,
+    snapshot: &ChainSnapshot,
+    targets: &[Delegation],
+    dao_addrs: &[String],
+    mode: CompoundMode,
+    reserve: Uint128,
+    denom: &str,
+) -> anyhow::Result<Compounding> {
+    let ahead: Vec<PlanMsg> = export
+        .entries()
+        .into_iter()
+        .filter(|e| e.id.section != PlanSection::Undelegation)
+        .map(|e| e.msg)
+        .collect();
+    let mut projected = snapshot.clone();
+    projected.apply(&ahead)?;
+
+    let mut weights: BTreeMap<&str, Uint128> = BTreeMap::new();
+    for target in targets {
+        let active = snapshot
+            .validator(&target.operator_addr)
+            .is_some_and(|v| v.is_active());
+        if active {
+            *weights.entry(&target.operator_addr).or_default() += target.amount;
+        }
+    }
+    // the DAOs' stake on each target once the messages ahead executed
+    let mut gaps: Vec<(&str, Uint128)> = weights
+        .iter()
+        .map(|(validator, target)| {
+            let staked: Uint128 = dao_addrs
+                .iter()
+                .map(|dao| projected.delegation(dao, validator))
+                .sum();
+            (*validator, target.saturating_sub(staked))
+        })
+        .filter(|(_, gap)| !gap.is_zero())
+        .collect();
+    gaps.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let total_weight: Uint128 = weights.values().sum();
+
+    let mut compounded = BTreeMap::new();
+    let mut added: BTreeMap<String, Uint128> = BTreeMap::new();
+    for dao in dao_addrs {
+        let liquid = projected.liquid.get(dao).copied().unwrap_or_default();
+        let mut surplus = liquid.saturating_sub(reserve);
+        if surplus.is_zero() {
+            continue;
+        }
+
+        let allocation: Vec<(&str, Uint128)> = match mode {
+            CompoundMode::UnderTarget => gaps
+                .iter_mut()
+                .map(|(validator, gap)| {
+                    let amount = surplus.min(*gap);
+                    *gap -= amount;
+                    surplus -= amount;
+                    (*validator, amount)
+                })
+                .collect(),
+            CompoundMode::Proportional if !total_weight.is_zero() => weights
+                .iter()
+                .map(|(validator, weight)| {
+                    (*validator, surplus.multiply_ratio(*weight, total_weight))
+                })
+                .collect(),
+            CompoundMode::Proportional => vec![],
+        };
+
+        for (validator, amount) in allocation {
+            if amount.is_zero() {
+                continue;
+            }
+            export.delegations.data.push(DelegateMsg {
+                delegator_address: dao.clone(),
+                validator_address: validator.to_string(),
+                amount: amount.to_string(),
+                denom: denom.to_string(),
+            });
+            *compounded.entry(dao.clone()).or_default() += amount;
+            *added.entry(validator.to_string()).or_default() += amount;
+        }
+    }
+    export.recompute_totals()?;
+
+    let targets = match mode {
+        CompoundMode::UnderTarget => targets.to_vec(),
+        CompoundMode::Proportional => targets
+            .iter()
+            .map(|t| Delegation {
+                amount: t.amount + added.remove(&t.operator_addr).unwrap_or_default(),
+                ..t.clone()
+            })
+            .collect(),
+    };
+    Ok(Compounding {
+        mode,
+        reserve,
+        compounded,
+        targets,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{del, validator},
+        plan::{Delegations, RedelegateMsg, Redelegations, Undelegations},
+    };
+
+    /// dao1 moves 60 of its 100 on a stray onto valA, leaving valA 40 and valB 100 short, and
+    /// holds 130 liquid.
+    fn planned() -> (MessageExport, ChainSnapshot) {
+        let export = MessageExport {
+            height: 1,
+            withdrawals: Default::default(),
+            sends: Default::default(),
+            redelegations: Redelegations {
+                data: vec![RedelegateMsg {
+                    delegator_address: "dao1".to_string(),
+                    validator_src_address: "stray".to_string(),
+                    validator_dst_address: "valA".to_string(),
+                    amount: "60".to_string(),
+                    denom: "ubtsg".to_string(),
+                }],
+                count: 1,
+                total_ubtsg: Uint128::new(60),
+            },
+            delegations: Delegations {
+                data: vec![],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            undelegates: Undelegations {
+                data: vec![],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+        };
+        let snapshot = ChainSnapshot {
+            height: 1,
+            delegations: vec![del("dao1", "stray", 100)],
+            validators: vec![
+                validator("stray", false),
+                validator("valA", false),
+                validator("valB", false),
+                validator("valC", true),
+            ],
+            liquid: BTreeMap::from([
+                ("dao1".to_string(), Uint128::new(130)),
+                ("dao2".to_string(), Uint128::new(5)),
+            ]),
+            ..Default::default()
+        };
+        (export, snapshot)
+    }
+
+    fn delegate(dao: &str, val: &str, amount: u128) -> DelegateMsg {
+        DelegateMsg {
+            delegator_address: dao.to_string(),
+            validator_address: val.to_string(),
+            amount: amount.to_string(),
+            denom: "ubtsg".to_string(),
+        }
+    }
+
+    fn targets() -> Vec<Delegation> {
+        vec![
+            del("", "valA", 100),
+            del("", "valB", 100),
+            del("", "valC", 100),
+        ]
+    }
+
+    fn delegated(export: &MessageExport) -> Vec<(&str, &str, &str)> {
+        export
+            .delegations
+            .data
+            .iter()
+            .map(|d| {
+                (
+                    d.delegator_address.as_str(),
+                    d.validator_address.as_str(),
+                    d.amount.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_under_target_fills_the_largest_gaps_above_the_reserve() -> anyhow::Result<()> {
+        let (mut export, snapshot) = planned();
+        let daos = ["dao1".to_string(), "dao2".to_string()];
+        let compounding = compound(
+            &mut export,
+            &snapshot,
+            &targets(),
+            &daos,
+            CompoundMode::UnderTarget,
+            Uint128::new(10),
+            "ubtsg",
+        )?;
+
+        // valC is jailed, dao2 holds no more than the reserve
+        assert_eq!(
+            delegated(&export),
+            [("dao1", "valB", "100"), ("dao1", "valA", "20")]
+        );
+        assert_eq!(export.delegations.total_ubtsg, Uint128::new(120));
+        assert_eq!(compounding.targets, targets());
+        assert_eq!(
+            compounding.compounded,
+            BTreeMap::from([("dao1".to_string(), Uint128::new(120))])
+        );
+        Ok(())
+    }
+
+    /// Projects the whole plan onto `snapshot`.
+    fn executed(export: &MessageExport, snapshot: &ChainSnapshot) -> anyhow::Result<ChainSnapshot> {
+        let mut projected = snapshot.clone();
+        projected.apply(
+            &export
+                .entries()
+                .into_iter()
+                .map(|e| e.msg)
+                .collect::<Vec<_>>(),
+        )?;
+        Ok(projected)
+    }
+
+    #[test]
+    fn test_under_target_compounds_what_the_planners_delegations_leave() -> anyhow::Result<()> {
+        // the planner already delegates valB's gap from dao1's liquid funds
+        let (mut export, snapshot) = planned();
+        export.delegations.data = vec![delegate("dao1", "valB", 100)];
+        export.recompute_totals()?;
+        compound(
+            &mut export,
+            &snapshot,
+            &targets(),
+            &["dao1".to_string()],
+            CompoundMode::UnderTarget,
+            Uint128::new(10),
+            "ubtsg",
+        )?;
+
+        // the 30 left liquid fill valA above the reserve, valB is not filled twice
+        assert_eq!(
+            delegated(&export),
+            [("dao1", "valB", "100"), ("dao1", "valA", "20")]
+        );
+        let projected = executed(&export, &snapshot)?;
+        assert_eq!(projected.delegation("dao1", "valB"), Uint128::new(100));
+        assert_eq!(projected.delegation("dao1", "valA"), Uint128::new(80));
+        assert_eq!(projected.liquid["dao1"], Uint128::new(10));
+        Ok(())
+    }
+
+    #[test]
+    fn test_under_target_keeps_the_planners_delegations_below_the_reserve() -> anyhow::Result<()> {
+        // dao1 holds less than valB's gap plus the reserve, the planner delegates all of it
+        let (mut export, mut snapshot) = planned();
+        snapshot
+            .liquid
+            .insert("dao1".to_string(), Uint128::new(100));
+        export.delegations.data = vec![delegate("dao1", "valB", 100)];
+        export.recompute_totals()?;
+        let compounding = compound(
+            &mut export,
+            &snapshot,
+            &targets(),
+            &["dao1".to_string()],
+            CompoundMode::UnderTarget,
+            Uint128::new(10),
+            "ubtsg",
+        )?;
+
+        // compounding leaves the plan as it was, valB still reaches its target
+        assert_eq!(delegated(&export), [("dao1", "valB", "100")]);
+        assert!(compounding.compounded.is_empty());
+        let projected = executed(&export, &snapshot)?;
+        assert_eq!(projected.delegation("dao1", "valB"), Uint128::new(100));
+        Ok(())
+    }
+
+    #[test]
+    fn test_proportional_raises_the_active_targets_alike() -> anyhow::Result<()> {
+        let (mut export, snapshot) = planned();
+        let compounding = compound(
+            &mut export,
+            &snapshot,
+            &targets(),
+            &["dao1".to_string()],
+            CompoundMode::Proportional,
+            Uint128::new(20),
+            "ubtsg",
+        )?;
+
+        assert_eq!(
+            delegated(&export),
+            [("dao1", "valA", "55"), ("dao1", "valB", "55")]
+        );
+        assert_eq!(
+            compounding.targets,
+            [
+                del("", "valA", 155),
+                del("", "valB", 155),
+                del("", "valC", 100)
+            ]
+        );
+        assert_eq!("proportional".parse::<CompoundMode>()?, compounding.mode);
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use std::io;
+
+use cosmwasm_std::Uint128;
+
+use crate::{
+    chain::ChainProfile,
+    plan::{MessageExport, PlanMsg},
+    report::{signed, EpochReport},
+};
+
+/// Current-vs-target table of the epoch report, one row per validator.
+pub const VALIDATORS_CSV: &str = "delegation_validators.csv";
+/// Every planned message in broadcast order, one row each.
+pub const MESSAGES_CSV: &str = "delegation_messages.csv";
+
+/// Writes the report's validator table with amounts in both denoms, for importing next to the
+/// allocation tab of the program's spreadsheet.
+pub fn write_validators_csv(
+    report: &EpochReport,
+    chain: &ChainProfile,
+    writer: impl io::Write,
+) -> anyhow::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    let mut header = vec!["validator".to_string()];
+    for column in ["current", "target", "delta", "planned"] {
+        header.push(format!("{} {}", column, chain.denom));
+        header.push(format!("{} {}", column, chain.display_denom));
+    }
+    header.extend(["status".to_string(), "reason".to_string()]);
+    csv.write_record(&header)?;
+
+    for row in &report.validators {
+        let mut record = vec![row.operator_addr.clone()];
+        for amount in [row.current, row.target] {
+            record.push(amount.to_string());
+            record.push(chain.display_amount(amount));
+        }
+        record.push(row.delta.to_string());
+        record.push(signed(chain, row.delta));
+        record.push(row.planned.to_string());
+        record.push(chain.display_amount(row.planned));
+        record.extend([row.status.clone(), row.reason.clone()]);
+        csv.write_record(&record)?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+/// Type, source and destination of `msg`, empty where it has none.
+pub(crate) fn columns(msg: &PlanMsg) -> (&'static str, &str, &str) {
+    match msg {
+        PlanMsg::WithdrawRewards(m) => ("withdraw-rewards", &m.validator_address, ""),
+        PlanMsg::Redelegate(m) => (
+            "redelegate",
+            &m.validator_src_address,
+            &m.validator_dst_address,
+        ),
+        PlanMsg::Delegate(m) => ("delegate", "", &m.validator_address),
+        PlanMsg::Undelegate(m) => ("undelegate", &m.validator_address, ""),
+        PlanMsg::Send(m) => ("send", "", &m.to_address),
+    }
+}
+
+/// Writes the plan's messages flat, in the order they are broadcast.
+pub fn write_messages_csv(
+    export: &MessageExport,
+    chain: &ChainProfile,
+    writer: impl io::Write,
+) -> anyhow::Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record([
+        "entry".to_string(),
+        "type".to_string(),
+        "dao".to_string(),
+        "src".to_string(),
+        "dst".to_string(),
+        format!("amount {}", chain.denom),
+        format!("amount {}", chain.display_denom),
+    ])?;
+    for entry in export.entries() {
+        let (kind, src, dst) = columns(&entry.msg);
+        let amount: Uint128 = entry.msg.amount().parse()?;
+        csv.write_record([
+            entry.id.to_string(),
+            kind.to_string(),
+            entry.msg.delegator().to_string(),
+            src.to_string(),
+            dst.to_string(),
+            amount.to_string(),
+            chain.display_amount(amount),
+        ])?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::Int128;
+
+    use super::*;
+    use crate::{
+        plan::{DelegateMsg, RedelegateMsg},
+        report::ValidatorRow,
+    };
+
+    #[test]
+    fn test_messages_are_exported_flat_in_both_denoms() -> anyhow::Result<()> {
+        let mut export = MessageExport::default();
+        export.redelegations.data.push(RedelegateMsg {
+            delegator_address: "dao1".to_string(),
+            validator_src_address: "valA".to_string(),
+            validator_dst_address: "valB".to_string(),
+            amount: "1500000".to_string(),
+            denom: "ubtsg".to_string(),
+        });
+        export.delegations.data.push(DelegateMsg {
+            delegator_address: "dao2".to_string(),
+            validator_address: "valC".to_string(),
+            amount: "7".to_string(),
+            denom: "ubtsg".to_string(),
+        });
+
+        let mut out = Vec::new();
+        write_messages_csv(&export, &ChainProfile::bitsong_mainnet(), &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "entry,type,dao,src,dst,amount ubtsg,amount BTSG\n\
+             redelegations[0],redelegate,dao1,valA,valB,1500000,1.5\n\
+             delegations[0],delegate,dao2,,valC,7,0.000007\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validator_table_keeps_the_sign_of_the_delta() -> anyhow::Result<()> {
+        let report = EpochReport {
+            height: 1,
+            chain_id: "bitsong-2b".to_string(),
+            plan_hash: String::new(),
+            daos: vec![],
+            validators: vec![ValidatorRow {
+                operator_addr: "valC".to_string(),
+                current: Uint128::new(2_500_000),
+                target: Uint128::zero(),
+                delta: Int128::new(-2_500_000),
+                planned: Uint128::zero(),
+                status: "removed".to_string(),
+                reason: "jailed, plan leaves 1ubtsg off target".to_string(),
+            }],
+            dao_totals: vec![],
+            messages: vec![],
+            bundles: 0,
+            fee: None,
+        };
+
+        let mut out = Vec::new();
+        write_validators_csv(&report, &ChainProfile::bitsong_mainnet(), &mut out)?;
+        let out = String::from_utf8(out)?;
+        let rows: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            rows[0],
+            "validator,current ubtsg,current BTSG,target ubtsg,target BTSG,delta ubtsg,delta BTSG,planned ubtsg,planned BTSG,status,reason"
+        );
+        assert_eq!(
+            rows[1],
+            "valC,2500000,2.5,0,0,-2500000,-2.5,0,0,removed,\"jailed, plan leaves 1ubtsg off target\""
+        );
+        Ok(())
+    }
+}
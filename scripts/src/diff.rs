@@ -0,0 +1,429 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Int128, Uint128};
+
+use crate::{
+    chain::ChainProfile,
+    csv_export::columns,
+    plan::{MessageExport, PlanMsg},
+    report::signed,
+    snapshot::{ChainSnapshot, Delegation},
+};
+
+/// Report of the `diff` command.
+pub const DIFF_JSON: &str = "delegation_diff.json";
+
+/// One side of a diff, read from a plan, a snapshot or a target CSV.
+#[derive(Clone, Debug)]
+pub enum Epoch {
+    Plan(MessageExport),
+    Snapshot(ChainSnapshot),
+    Targets(Vec<Delegation>),
+}
+
+impl Epoch {
+    fn kind(&self) -> &'static str {
+        match self {
+            Epoch::Plan(_) => "plans",
+            Epoch::Snapshot(_) => "snapshots",
+            Epoch::Targets(_) => "targets",
+        }
+    }
+
+    fn height(&self) -> Option<u64> {
+        match self {
+            Epoch::Plan(export) => Some(export.height),
+            Epoch::Snapshot(snapshot) => Some(snapshot.height),
+            Epoch::Targets(_) => None,
+        }
+    }
+
+    /// Per validator: the net stake a plan moves onto it, the DAO stake of a snapshot, or the target.
+    fn by_validator(&self) -> anyhow::Result<BTreeMap<String, Int128>> {
+        let mut amounts: BTreeMap<String, Int128> = BTreeMap::new();
+        let mut add = |validator: &str, amount: Int128| {
+            *amounts.entry(validator.to_string()).or_default() += amount;
+        };
+        match self {
+            Epoch::Plan(export) => {
+                for entry in export.entries() {
+                    let amount = Int128::try_from(Uint128::from_str(entry.msg.amount())?)?;
+                    match &entry.msg {
+                        PlanMsg::Redelegate(m) => {
+                            add(&m.validator_src_address, -amount);
+                            add(&m.validator_dst_address, amount);
+                        }
+                        PlanMsg::Delegate(m) => add(&m.validator_address, amount),
+                        PlanMsg::Undelegate(m) => add(&m.validator_address, -amount),
+                        PlanMsg::WithdrawRewards(_) | PlanMsg::Send(_) => {}
+                    }
+                }
+            }
+            Epoch::Snapshot(snapshot) => {
+                for del in &snapshot.delegations {
+                    add(&del.operator_addr, Int128::try_from(del.amount)?);
+                }
+            }
+            Epoch::Targets(targets) => {
+                for target in targets {
+                    add(&target.operator_addr, Int128::try_from(target.amount)?);
+                }
+            }
+        }
+        Ok(amounts)
+    }
+
+    /// What a plan redelegates, delegates, undelegates and sends, summed from its messages rather
+    /// than its declared totals, or the total stake or target.
+    fn totals(&self) -> anyhow::Result<Vec<(&'static str, Uint128)>> {
+        Ok(match self {
+            Epoch::Plan(export) => {
+                let mut moved = export.clone();
+                moved.recompute_totals()?;
+                vec![
+                    ("redelegated", moved.redelegations.total_ubtsg),
+                    ("delegated", moved.delegations.total_ubtsg),
+                    ("undelegated", moved.undelegates.total_ubtsg),
+                    ("sent", moved.sends.total_ubtsg),
+                ]
+            }
+            Epoch::Snapshot(snapshot) => vec![(
+                "staked",
+                snapshot.delegations.iter().map(|d| d.amount).sum(),
+            )],
+            Epoch::Targets(targets) => vec![("target", targets.iter().map(|t| t.amount).sum())],
+        })
+    }
+
+    /// Planned amount per (type, DAO, src, dst), empty for anything but a plan.
+    fn messages(&self) -> anyhow::Result<BTreeMap<MessageKey, Uint128>> {
+        let mut amounts = BTreeMap::new();
+        if let Epoch::Plan(export) = self {
+            for entry in export.entries() {
+                let (kind, src, dst) = columns(&entry.msg);
+                let key = (
+                    kind.to_string(),
+                    entry.msg.delegator().to_string(),
+                    src.to_string(),
+                    dst.to_string(),
+                );
+                *amounts.entry(key).or_default() += Uint128::from_str(entry.msg.amount())?;
+            }
+        }
+        Ok(amounts)
+    }
+}
+
+type MessageKey = (String, String, String, String);
+
+/// A validator whose amount differs between the two sides.
+#[cw_serde]
+pub struct ValidatorDelta {
+    pub operator_addr: String,
+    pub old: Int128,
+    pub new: Int128,
+}
+
+impl ValidatorDelta {
+    pub fn delta(&self) -> Int128 {
+        self.new - self.old
+    }
+}
+
+/// One total on both sides: an amount the plans move, or the total stake or target.
+#[cw_serde]
+pub struct TotalDelta {
+    /// `redelegated`, `delegated`, `undelegated` or `sent` for plans, `staked` or `target`.
+    pub total: String,
+    pub old: Uint128,
+    pub new: Uint128,
+    /// `new - old`.
+    pub delta: Int128,
+}
+
+/// Messages of one type, DAO, source and destination whose amount differs between two plans,
+/// zero on the side that does not have them.
+#[cw_serde]
+pub struct MessageDelta {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub dao: String,
+    pub src: String,
+    pub dst: String,
+    pub old: Uint128,
+    pub new: Uint128,
+}
+
+#[cw_serde]
+pub struct DiffReport {
+    /// `plans`, `snapshots` or `targets`.
+    pub kind: String,
+    pub old_height: Option<u64>,
+    pub new_height: Option<u64>,
+    /// Validators only the new side has.
+    pub added: Vec<String>,
+    /// Validators only the old side has.
+    pub removed: Vec<String>,
+    /// Net stake planned onto, DAO stake on, or target of every validator that differs.
+    pub validators: Vec<ValidatorDelta>,
+    pub messages: Vec<MessageDelta>,
+    /// What the plans move by message type, or the total stake or target, on each side.
+    pub totals: Vec<TotalDelta>,
+}
+
+/// Compares two plans, two snapshots or two target lists.
+pub fn diff(old: &Epoch, new: &Epoch) -> anyhow::Result<DiffReport> {
+    anyhow::ensure!(
+        old.kind() == new.kind(),
+        "cannot compare {} with {}",
+        old.kind(),
+        new.kind()
+    );
+
+    let old_amounts = old.by_validator()?;
+    let new_amounts = new.by_validator()?;
+    let added = new_amounts
+        .keys()
+        .filter(|v| !old_amounts.contains_key(*v))
+        .cloned()
+        .collect();
+    let removed = old_amounts
+        .keys()
+        .filter(|v| !new_amounts.contains_key(*v))
+        .cloned()
+        .collect();
+    let mut operators: Vec<&String> = old_amounts.keys().chain(new_amounts.keys()).collect();
+    operators.sort();
+    operators.dedup();
+    let validators = operators
+        .into_iter()
+        .map(|operator| ValidatorDelta {
+            operator_addr: operator.clone(),
+            old: old_amounts.get(operator).copied().unwrap_or_default(),
+            new: new_amounts.get(operator).copied().unwrap_or_default(),
+        })
+        .filter(|v| v.old != v.new)
+        .collect();
+
+    let old_msgs = old.messages()?;
+    let new_msgs = new.messages()?;
+    let mut keys: Vec<&MessageKey> = old_msgs.keys().chain(new_msgs.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    let messages = keys
+        .into_iter()
+        .map(|key| MessageDelta {
+            kind: key.0.clone(),
+            dao: key.1.clone(),
+            src: key.2.clone(),
+            dst: key.3.clone(),
+            old: old_msgs.get(key).copied().unwrap_or_default(),
+            new: new_msgs.get(key).copied().unwrap_or_default(),
+        })
+        .filter(|m| m.old != m.new)
+        .collect();
+
+    let totals = old
+        .totals()?
+        .into_iter()
+        .zip(new.totals()?)
+        .map(|((total, old), (_, new))| {
+            Ok(TotalDelta {
+                total: total.to_string(),
+                old,
+                new,
+                delta: Int128::try_from(new)? - Int128::try_from(old)?,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(DiffReport {
+        kind: old.kind().to_string(),
+        old_height: old.height(),
+        new_height: new.height(),
+        added,
+        removed,
+        validators,
+        messages,
+        totals,
+    })
+}
+
+impl DiffReport {
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty() && self.messages.is_empty()
+    }
+
+    /// The diff as text, amounts in the chain's display denom.
+    pub fn table<'a>(&'a self, chain: &'a ChainProfile) -> DiffTable<'a> {
+        DiffTable { diff: self, chain }
+    }
+}
+
+pub struct DiffTable<'a> {
+    diff: &'a DiffReport,
+    chain: &'a ChainProfile,
+}
+
+impl fmt::Display for DiffTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let diff = self.diff;
+        let height = |h: Option<u64>| h.map_or("-".to_string(), |h| h.to_string());
+        writeln!(
+            f,
+            "{} at height {} → {}",
+            diff.kind,
+            height(diff.old_height),
+            height(diff.new_height)
+        )?;
+        for validator in &diff.added {
+            writeln!(f, "+ {}", validator)?;
+        }
+        for validator in &diff.removed {
+            writeln!(f, "- {}", validator)?;
+        }
+        writeln!(
+            f,
+            "{:<53} {:>16} {:>16} {:>16}",
+            "validator", "old", "new", "delta"
+        )?;
+        for v in &diff.validators {
+            writeln!(
+                f,
+                "{:<53} {:>16} {:>16} {:>16}",
+                v.operator_addr,
+                signed(self.chain, v.old),
+                signed(self.chain, v.new),
+                signed(self.chain, v.delta())
+            )?;
+        }
+        for m in &diff.messages {
+            writeln!(
+                f,
+                "{} {} {} → {}: {} → {} {}",
+                m.kind,
+                m.dao,
+                if m.src.is_empty() { "-" } else { &m.src },
+                if m.dst.is_empty() { "-" } else { &m.dst },
+                self.chain.display_amount(m.old),
+                self.chain.display_amount(m.new),
+                self.chain.display_denom
+            )?;
+        }
+        for total in &diff.totals {
+            writeln!(
+                f,
+                "{}: {} {} ({} → {})",
+                total.total,
+                signed(self.chain, total.delta),
+                self.chain.display_denom,
+                self.chain.display_amount(total.old),
+                self.chain.display_amount(total.new)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::del,
+        plan::{DelegateMsg, RedelegateMsg},
+    };
+
+    fn plan(height: u64, redelegated: &str, delegated: Option<&str>) -> MessageExport {
+        let mut export = MessageExport {
+            height,
+            ..Default::default()
+        };
+        export.redelegations.data.push(RedelegateMsg {
+            delegator_address: "dao1".to_string(),
+            validator_src_address: "valA".to_string(),
+            validator_dst_address: "valB".to_string(),
+            amount: redelegated.to_string(),
+            denom: "ubtsg".to_string(),
+        });
+        if let Some(amount) = delegated {
+            export.delegations.data.push(DelegateMsg {
+                delegator_address: "dao2".to_string(),
+                validator_address: "valC".to_string(),
+                amount: amount.to_string(),
+                denom: "ubtsg".to_string(),
+            });
+        }
+        export.recompute_totals().unwrap();
+        export
+    }
+
+    #[test]
+    fn test_targets_diff_lists_added_removed_and_changed_validators() -> anyhow::Result<()> {
+        let old = Epoch::Targets(vec![del("", "valA", 100), del("", "valB", 50)]);
+        let new = Epoch::Targets(vec![del("", "valB", 80), del("", "valC", 30)]);
+        let report = diff(&old, &new)?;
+
+        assert_eq!(report.added, ["valC"]);
+        assert_eq!(report.removed, ["valA"]);
+        let deltas: Vec<_> = report
+            .validators
+            .iter()
+            .map(|v| (v.operator_addr.as_str(), v.delta().i128()))
+            .collect();
+        assert_eq!(deltas, [("valA", -100), ("valB", 30), ("valC", 30)]);
+        assert_eq!(report.totals.len(), 1);
+        assert_eq!(report.totals[0].delta, Int128::new(-40));
+        assert!(report.messages.is_empty());
+
+        let err = diff(&old, &Epoch::Plan(MessageExport::default())).unwrap_err();
+        assert_eq!(err.to_string(), "cannot compare targets with plans");
+        Ok(())
+    }
+
+    #[test]
+    fn test_plans_diff_by_message() -> anyhow::Result<()> {
+        let old = Epoch::Plan(plan(10, "40", None));
+        let new = Epoch::Plan(plan(20, "25", Some("5")));
+        let report = diff(&old, &new)?;
+
+        let messages: Vec<_> = report
+            .messages
+            .iter()
+            .map(|m| (m.kind.as_str(), m.old.u128(), m.new.u128()))
+            .collect();
+        assert_eq!(messages, [("delegate", 0, 5), ("redelegate", 40, 25)]);
+        assert_eq!(report.added, ["valC"]);
+        let totals: Vec<_> = report
+            .totals
+            .iter()
+            .map(|t| (t.total.as_str(), t.old.u128(), t.new.u128()))
+            .collect();
+        assert_eq!(
+            totals,
+            [
+                ("redelegated", 40, 25),
+                ("delegated", 0, 5),
+                ("undelegated", 0, 0),
+                ("sent", 0, 0)
+            ]
+        );
+
+        let text = report.table(&ChainProfile::bitsong_mainnet()).to_string();
+        assert!(text.starts_with("plans at height 10 → 20"), "{}", text);
+        assert!(text.contains("redelegated: -0.000015 BTSG"), "{}", text);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_totals_are_summed_from_the_messages() -> anyhow::Result<()> {
+        let old = plan(10, "40", None);
+        let mut tampered = plan(20, "40", None);
+        tampered.redelegations.total_ubtsg = Uint128::new(1_000);
+        tampered.delegations.total_ubtsg = Uint128::new(7);
+
+        let report = diff(&Epoch::Plan(old), &Epoch::Plan(tampered))?;
+        assert!(report.is_empty());
+        assert!(report.totals.iter().all(|t| t.delta.is_zero()));
+        Ok(())
+    }
+}
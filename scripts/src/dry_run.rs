@@ -0,0 +1,684 @@
+use std::collections::BTreeMap;
+
+use cosmos_sdk_proto::{cosmos::authz::v1beta1::MsgExec, prost::Message};
+use cosmrs::tx::Msg;
+use cosmwasm_std::{
+    Addr, Api, BankMsg, CanonicalAddr, Coin, CosmosMsg, Decimal, DistributionMsg,
+    RecoverPubkeyError, StakingMsg, StdError, StdResult, Uint128, Validator, VerificationError,
+};
+use cw_orch_clone_testing::cw_multi_test::{
+    addons::MockApiBech32, wasm_emulation::channel::RemoteChannel, App, AppBuilder, BankKeeper,
+    Executor, StakingInfo,
+};
+use tokio::runtime::Runtime;
+
+use crate::{
+    bundle::schedule_bundles,
+    chain::ChainProfile,
+    plan::{authz_exec, MessageExport, PlanMsg},
+    precheck::{destinations, Violation},
+    reconcile::{reconcile, ReconcileReport},
+    rewards::RewardLedger,
+    snapshot::{ChainSnapshot, Delegation, ValidatorState},
+};
+
+/// Holds the snapshot's pending rewards until the messages that withdraw them execute.
+const REWARD_POOL: &str = "distribution_module";
+
+/// Account and validator operator addresses use different bech32 prefixes on chain, the staking
+/// module of the test app needs to validate both. The prefix is kept as a leading tag byte.
+pub struct DryRunApi {
+    accounts: MockApiBech32,
+    operators: MockApiBech32,
+}
+
+impl DryRunApi {
+    pub fn new(account_prefix: &str, operator_prefix: &str) -> Self {
+        DryRunApi {
+            accounts: MockApiBech32::new(account_prefix),
+            operators: MockApiBech32::new(operator_prefix),
+        }
+    }
+}
+
+impl Api for DryRunApi {
+    fn addr_validate(&self, human: &str) -> StdResult<Addr> {
+        self.addr_humanize(&self.addr_canonicalize(human)?)
+    }
+
+    fn addr_canonicalize(&self, human: &str) -> StdResult<CanonicalAddr> {
+        let (tag, canonical) = match self.accounts.addr_canonicalize(human) {
+            Ok(canonical) => (0u8, canonical),
+            Err(_) => (1u8, self.operators.addr_canonicalize(human)?),
+        };
+        Ok([&[tag], canonical.as_slice()].concat().into())
+    }
+
+    fn addr_humanize(&self, canonical: &CanonicalAddr) -> StdResult<Addr> {
+        match canonical.as_slice().split_first() {
+            Some((0, bytes)) => self.accounts.addr_humanize(&bytes.to_vec().into()),
+            Some((1, bytes)) => self.operators.addr_humanize(&bytes.to_vec().into()),
+            _ => Err(StdError::generic_err("Invalid canonical address")),
+        }
+    }
+
+    fn secp256k1_verify(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+    ) -> Result<bool, VerificationError> {
+        self.accounts
+            .secp256k1_verify(message_hash, signature, public_key)
+    }
+
+    fn secp256k1_recover_pubkey(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        recovery_param: u8,
+    ) -> Result<Vec<u8>, RecoverPubkeyError> {
+        self.accounts
+            .secp256k1_recover_pubkey(message_hash, signature, recovery_param)
+    }
+
+    fn ed25519_verify(
+        &self,
+        message: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+    ) -> Result<bool, VerificationError> {
+        self.accounts.ed25519_verify(message, signature, public_key)
+    }
+
+    fn ed25519_batch_verify(
+        &self,
+        messages: &[&[u8]],
+        signatures: &[&[u8]],
+        public_keys: &[&[u8]],
+    ) -> Result<bool, VerificationError> {
+        self.accounts
+            .ed25519_batch_verify(messages, signatures, public_keys)
+    }
+
+    fn debug(&self, message: &str) {
+        self.accounts.debug(message)
+    }
+}
+
+pub type DryRunApp = App<BankKeeper, DryRunApi>;
+
+/// A channel that is never dialed, for dry runs that only touch state seeded from a snapshot.
+pub fn offline_remote(rt: &Runtime, chain_id: &str, prefix: &str) -> anyhow::Result<RemoteChannel> {
+    // the lazy channel spawns its worker on the runtime, nothing connects until it is used
+    let _guard = rt.enter();
+    Ok(RemoteChannel {
+        rt: rt.handle().clone(),
+        channel: tonic::transport::Endpoint::from_static("http://127.0.0.1:9090").connect_lazy(),
+        pub_address_prefix: prefix.to_string(),
+        chain_id: chain_id.to_string(),
+    })
+}
+
+/// In-process staking state forked from a [`ChainSnapshot`], on which bundles are executed exactly
+/// as they would be broadcast. Authz grants are not modelled, every DAO is taken to have granted
+/// the grantee.
+pub struct DryRun {
+    app: DryRunApp,
+    denom: String,
+    /// Validator states of the snapshot, the app itself only knows active validators.
+    validators: BTreeMap<String, ValidatorState>,
+    rewards: RewardLedger,
+}
+
+impl DryRun {
+    /// Seeds validators, DAO delegations, liquid balances and pending rewards. Anything else the
+    /// app reads from the bank is fetched through `remote`, so a snapshot-only run never needs the
+    /// network.
+    pub fn fork(
+        remote: RemoteChannel,
+        snapshot: &ChainSnapshot,
+        chain: &ChainProfile,
+    ) -> anyhow::Result<Self> {
+        let api = DryRunApi::new(&remote.pub_address_prefix, &chain.validator_prefix);
+        let mut app = AppBuilder::default()
+            .with_bank(BankKeeper::new().with_remote(remote.clone()))
+            .with_api(api)
+            .with_remote(remote)
+            .build(|_, _, _| {})?;
+
+        let mut operators: Vec<&str> = snapshot
+            .delegations
+            .iter()
+            .map(|d| d.operator_addr.as_str())
+            .chain(
+                snapshot
+                    .validators
+                    .iter()
+                    .map(|v| v.operator_address.as_str()),
+            )
+            .collect();
+        operators.sort();
+        operators.dedup();
+
+        let mut funds: BTreeMap<&str, Uint128> = BTreeMap::new();
+        for (dao, liquid) in &snapshot.liquid {
+            *funds.entry(dao).or_default() += *liquid;
+        }
+        for del in &snapshot.delegations {
+            *funds.entry(&del.del_addr).or_default() += del.amount;
+        }
+        let pending: Uint128 = snapshot.rewards.iter().map(|r| r.amount).sum();
+
+        app.init_modules(|router, api, storage| -> anyhow::Result<()> {
+            let block = cosmwasm_std::testing::mock_env().block;
+            router.staking.setup(
+                storage,
+                StakingInfo {
+                    bonded_denom: chain.denom.clone(),
+                    unbonding_time: chain.unbonding_time,
+                    apr: Decimal::zero(),
+                },
+            )?;
+            for operator in &operators {
+                router.staking.add_validator(
+                    api,
+                    storage,
+                    &block,
+                    Validator::create(
+                        operator.to_string(),
+                        Decimal::zero(),
+                        Decimal::one(),
+                        Decimal::one(),
+                    ),
+                )?;
+            }
+            router
+                .bank
+                .init_balance(storage, &Addr::unchecked("staking_module"), vec![])?;
+            router.bank.init_balance(
+                storage,
+                &Addr::unchecked(REWARD_POOL),
+                vec![Coin::new(pending, &chain.denom)],
+            )?;
+            for (dao, amount) in &funds {
+                router.bank.init_balance(
+                    storage,
+                    &Addr::unchecked(*dao),
+                    vec![Coin::new(*amount, &chain.denom)],
+                )?;
+            }
+            Ok(())
+        })?;
+
+        // existing stake is delegated from the funded DAOs, leaving exactly their liquid balance
+        for del in &snapshot.delegations {
+            app.execute(
+                Addr::unchecked(&del.del_addr),
+                StakingMsg::Delegate {
+                    validator: del.operator_addr.clone(),
+                    amount: Coin::new(del.amount, &chain.denom),
+                }
+                .into(),
+            )
+            .map_err(|e| anyhow::anyhow!("failed to seed {:?}: {}", del, e))?;
+        }
+
+        Ok(DryRun {
+            app,
+            denom: chain.denom.clone(),
+            validators: snapshot
+                .validators
+                .iter()
+                .map(|v| (v.operator_address.clone(), v.clone()))
+                .collect(),
+            rewards: RewardLedger::new(&snapshot.rewards),
+        })
+    }
+
+    /// Refuses a bundle that sends stake to a validator that is jailed, tombstoned, not bonded or
+    /// unknown at the snapshot, as broadcasting does.
+    pub fn check_destinations(&self, msgs: &[PlanMsg]) -> anyhow::Result<()> {
+        for validator in destinations(msgs) {
+            let violation = match self.validators.get(&validator) {
+                Some(state) if state.is_active() => continue,
+                Some(state) => Violation::InactiveDestination {
+                    class: state.class(),
+                    validator,
+                },
+                None => Violation::UnknownDestination { validator },
+            };
+            anyhow::bail!("{}", violation);
+        }
+        Ok(())
+    }
+
+    /// Pays out the pending rewards `msgs` withdraw to their delegators, before the bundle executes.
+    pub fn withdraw_rewards(&mut self, msgs: &[PlanMsg]) -> anyhow::Result<()> {
+        for msg in msgs {
+            let amount = self.rewards.withdraw(msg);
+            if amount.is_zero() {
+                continue;
+            }
+            self.app.send_tokens(
+                Addr::unchecked(REWARD_POOL),
+                Addr::unchecked(msg.delegator()),
+                &[Coin::new(amount, self.denom.clone())],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Executes an authz `MsgExec` signed by `grantee`, all wrapped messages atomically and on behalf
+    /// of their delegator.
+    pub fn execute_authz(&mut self, grantee: &str, exec: &cosmrs::Any) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            exec.type_url == "/cosmos.authz.v1beta1.MsgExec",
+            "expected an authz MsgExec, got {}",
+            exec.type_url
+        );
+        let exec = MsgExec::decode(exec.value.as_slice())?;
+        anyhow::ensure!(
+            exec.grantee == grantee,
+            "MsgExec is for grantee {}, signed by {}",
+            exec.grantee,
+            grantee
+        );
+
+        let mut granter = None;
+        let mut msgs: Vec<CosmosMsg> = Vec::new();
+        for any in &exec.msgs {
+            let (delegator, msg) = self.cosmos_msg(any)?;
+            anyhow::ensure!(
+                granter.get_or_insert_with(|| delegator.clone()) == &delegator,
+                "a MsgExec can only act for one granter"
+            );
+            msgs.push(msg);
+        }
+
+        if let Some(granter) = granter {
+            self.app.execute_multi(Addr::unchecked(granter), msgs)?;
+        }
+        Ok(())
+    }
+
+    fn cosmos_msg(&self, any: &cosmrs::Any) -> anyhow::Result<(String, CosmosMsg)> {
+        let err = |e: cosmrs::ErrorReport| anyhow::anyhow!("{}", e);
+        let coin = |c: cosmrs::Coin| -> anyhow::Result<Coin> {
+            anyhow::ensure!(
+                c.denom.as_ref() == self.denom,
+                "unexpected denom {}",
+                c.denom
+            );
+            Ok(Coin::new(c.amount, self.denom.clone()))
+        };
+        Ok(match any.type_url.as_str() {
+            "/cosmos.staking.v1beta1.MsgBeginRedelegate" => {
+                let msg = cosmrs::staking::MsgBeginRedelegate::from_any(any).map_err(err)?;
+                (
+                    msg.delegator_address.to_string(),
+                    StakingMsg::Redelegate {
+                        src_validator: msg.validator_src_address.to_string(),
+                        dst_validator: msg.validator_dst_address.to_string(),
+                        amount: coin(msg.amount)?,
+                    }
+                    .into(),
+                )
+            }
+            "/cosmos.staking.v1beta1.MsgDelegate" => {
+                let msg = cosmrs::staking::MsgDelegate::from_any(any).map_err(err)?;
+                (
+                    msg.delegator_address.to_string(),
+                    StakingMsg::Delegate {
+                        validator: msg.validator_address.to_string(),
+                        amount: coin(msg.amount)?,
+                    }
+                    .into(),
+                )
+            }
+            "/cosmos.staking.v1beta1.MsgUndelegate" => {
+                let msg = cosmrs::staking::MsgUndelegate::from_any(any).map_err(err)?;
+                (
+                    msg.delegator_address.to_string(),
+                    StakingMsg::Undelegate {
+                        validator: msg.validator_address.to_string(),
+                        amount: coin(msg.amount)?,
+                    }
+                    .into(),
+                )
+            }
+            "/cosmos.bank.v1beta1.MsgSend" => {
+                let msg = cosmrs::bank::MsgSend::from_any(any).map_err(err)?;
+                (
+                    msg.from_address.to_string(),
+                    BankMsg::Send {
+                        to_address: msg.to_address.to_string(),
+                        amount: msg.amount.into_iter().map(coin).collect::<Result<_, _>>()?,
+                    }
+                    .into(),
+                )
+            }
+            "/cosmos.distribution.v1beta1.MsgWithdrawDelegatorReward" => {
+                let msg =
+                    cosmrs::distribution::MsgWithdrawDelegatorReward::from_any(any).map_err(err)?;
+                (
+                    msg.delegator_address.to_string(),
+                    DistributionMsg::WithdrawDelegatorReward {
+                        validator: msg.validator_address.to_string(),
+                    }
+                    .into(),
+                )
+            }
+            other => anyhow::bail!("dry run does not support {}", other),
+        })
+    }
+
+    /// Delegations of `daos` in the forked state.
+    pub fn delegations(&self, daos: &[String]) -> anyhow::Result<Vec<Delegation>> {
+        let querier = self.app.wrap();
+        let mut delegations = Vec::new();
+        for dao in daos {
+            for del in querier.query_all_delegations(dao)? {
+                delegations.push(Delegation {
+                    del_addr: del.delegator.to_string(),
+                    operator_addr: del.validator,
+                    amount: del.amount.amount,
+                });
+            }
+        }
+        Ok(delegations)
+    }
+}
+
+/// Runs every bundle of `export` on a fork of `snapshot`, in broadcast order and wrapped in authz for
+/// `grantee`, and compares the resulting DAO delegations with the targets.
+#[allow(clippy::too_many_arguments)]
+pub fn dry_run_plan(
+    remote: RemoteChannel,
+    chain: &ChainProfile,
+    snapshot: &ChainSnapshot,
+    export: &MessageExport,
+    dao_addrs: &[String],
+    grantee: &str,
+    targets: &[Delegation],
+    ignored: &[&str],
+    max_msgs: usize,
+) -> anyhow::Result<ReconcileReport> {
+    let mut fork = DryRun::fork(remote, snapshot, chain)?;
+
+    let entries = export.entries();
+    for bundle in schedule_bundles(&entries, dao_addrs, max_msgs)? {
+        let plan_msgs = bundle
+            .entries
+            .iter()
+            .map(|id| {
+                Ok(export
+                    .entry(id)
+                    .ok_or_else(|| anyhow::anyhow!("{} is not in the plan", id))?
+                    .msg)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let msgs = plan_msgs
+            .iter()
+            .map(|msg| msg.to_any())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        fork.check_destinations(&plan_msgs)
+            .and_then(|_| fork.withdraw_rewards(&plan_msgs))
+            .and_then(|_| fork.execute_authz(grantee, &authz_exec(grantee, msgs)))
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "dry run of the bundle for {} failed: {}\n  entries: {}",
+                    bundle.dao,
+                    e,
+                    bundle
+                        .entries
+                        .iter()
+                        .map(|id| id.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+    }
+
+    let live: Vec<Delegation> = fork
+        .delegations(dao_addrs)?
+        .into_iter()
+        .filter(|d| !ignored.contains(&d.operator_addr.as_str()))
+        .collect();
+    Ok(reconcile(snapshot.height, &live, targets, dao_addrs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{del, validator},
+        plan::{
+            DelegateMsg, Delegations, RedelegateMsg, Redelegations, UndelegateMsg, Undelegations,
+        },
+        snapshot::{PendingReward, ValidatorState},
+    };
+
+    fn account(name: &str) -> String {
+        MockApiBech32::new("bitsong").addr_make(name).to_string()
+    }
+
+    fn operator(name: &str) -> String {
+        MockApiBech32::new("bitsongvaloper")
+            .addr_make(name)
+            .to_string()
+    }
+
+    fn bonded(operators: &[&str]) -> Vec<ValidatorState> {
+        operators.iter().map(|op| validator(op, false)).collect()
+    }
+
+    fn plan(
+        dao: &str,
+        redels: Vec<(&str, &str, u128)>,
+        undels: Vec<(&str, u128)>,
+    ) -> MessageExport {
+        let mut export = MessageExport {
+            height: 0,
+            withdrawals: Default::default(),
+            sends: Default::default(),
+            redelegations: Redelegations {
+                data: redels
+                    .into_iter()
+                    .map(|(src, dst, amount)| RedelegateMsg {
+                        delegator_address: dao.to_string(),
+                        validator_src_address: src.to_string(),
+                        validator_dst_address: dst.to_string(),
+                        amount: amount.to_string(),
+                        denom: "ubtsg".to_string(),
+                    })
+                    .collect(),
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            delegations: Delegations {
+                data: vec![],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            undelegates: Undelegations {
+                data: undels
+                    .into_iter()
+                    .map(|(val, amount)| UndelegateMsg {
+                        delegator_address: dao.to_string(),
+                        validator_address: val.to_string(),
+                        amount: amount.to_string(),
+                        denom: "ubtsg".to_string(),
+                    })
+                    .collect(),
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+        };
+        export.recompute_totals().unwrap();
+        export
+    }
+
+    #[test]
+    fn test_plan_reaches_targets_on_forked_state() -> anyhow::Result<()> {
+        let rt = Runtime::new()?;
+        let (dao, grantee) = (account("dao1"), account("grantee"));
+        let (val_a, val_b, val_c) = (operator("valA"), operator("valB"), operator("valC"));
+        let snapshot = ChainSnapshot {
+            height: 42,
+            delegations: vec![del(&dao, &val_a, 100)],
+            validators: bonded(&[&val_a, &val_b, &val_c]),
+            liquid: BTreeMap::from([(dao.clone(), Uint128::new(7))]),
+            ..Default::default()
+        };
+        let targets = vec![del("", &val_b, 60), del("", &val_c, 30)];
+        let export = plan(
+            &dao,
+            vec![(&val_a, &val_b, 60), (&val_a, &val_c, 30)],
+            vec![(&val_a, 10)],
+        );
+
+        let report = dry_run_plan(
+            offline_remote(&rt, "bitsong-2b", "bitsong")?,
+            &ChainProfile::bitsong_mainnet(),
+            &snapshot,
+            &export,
+            &[dao],
+            &grantee,
+            &targets,
+            &[],
+            2,
+        )?;
+        assert!(report.is_aligned(), "{:?}", report);
+        assert_eq!(report.total_live, Uint128::new(90));
+        Ok(())
+    }
+
+    #[test]
+    fn test_overdrawn_bundle_fails_the_dry_run() -> anyhow::Result<()> {
+        let rt = Runtime::new()?;
+        let (dao, grantee) = (account("dao1"), account("grantee"));
+        let (val_a, val_b) = (operator("valA"), operator("valB"));
+        let snapshot = ChainSnapshot {
+            delegations: vec![del(&dao, &val_a, 100)],
+            validators: bonded(&[&val_a, &val_b]),
+            ..Default::default()
+        };
+        let export = plan(
+            &dao,
+            vec![(&val_a, &val_b, 80), (&val_a, &val_b, 30)],
+            vec![],
+        );
+
+        let err = dry_run_plan(
+            offline_remote(&rt, "bitsong-2b", "bitsong")?,
+            &ChainProfile::bitsong_mainnet(),
+            &snapshot,
+            &export,
+            &[dao],
+            &grantee,
+            &[del("", &val_b, 110)],
+            &[],
+            32,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("redelegations[0], redelegations[1]"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_withdrawn_rewards_fund_delegations() -> anyhow::Result<()> {
+        let rt = Runtime::new()?;
+        let (dao, grantee) = (account("dao1"), account("grantee"));
+        let (val_a, val_b) = (operator("valA"), operator("valB"));
+        let snapshot = ChainSnapshot {
+            delegations: vec![del(&dao, &val_a, 100)],
+            validators: bonded(&[&val_a, &val_b]),
+            rewards: vec![PendingReward {
+                delegator: dao.clone(),
+                validator: val_a.clone(),
+                amount: Uint128::new(5),
+            }],
+            ..Default::default()
+        };
+        // the DAO holds nothing liquid, only the rewards the redelegation withdraws pay the delegation
+        let mut export = plan(&dao, vec![(&val_a, &val_b, 100)], vec![]);
+        export.delegations.data.push(DelegateMsg {
+            delegator_address: dao.clone(),
+            validator_address: val_b.clone(),
+            amount: "5".to_string(),
+            denom: "ubtsg".to_string(),
+        });
+        export.recompute_totals()?;
+
+        let report = dry_run_plan(
+            offline_remote(&rt, "bitsong-2b", "bitsong")?,
+            &ChainProfile::bitsong_mainnet(),
+            &snapshot,
+            &export,
+            &[dao],
+            &grantee,
+            &[del("", &val_b, 105)],
+            &[],
+            32,
+        )?;
+        assert!(report.is_aligned(), "{:?}", report);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jailed_destination_fails_the_dry_run() -> anyhow::Result<()> {
+        let rt = Runtime::new()?;
+        let (dao, grantee) = (account("dao1"), account("grantee"));
+        let (val_a, val_b) = (operator("valA"), operator("valB"));
+        let mut validators = bonded(&[&val_a, &val_b]);
+        validators[1].jailed = true;
+        let snapshot = ChainSnapshot {
+            delegations: vec![del(&dao, &val_a, 100)],
+            validators,
+            ..Default::default()
+        };
+        let export = plan(&dao, vec![(&val_a, &val_b, 100)], vec![]);
+
+        let err = dry_run_plan(
+            offline_remote(&rt, "bitsong-2b", "bitsong")?,
+            &ChainProfile::bitsong_mainnet(),
+            &snapshot,
+            &export,
+            &[dao],
+            &grantee,
+            &[del("", &val_b, 100)],
+            &[],
+            32,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains(&format!("destination {} is jailed", val_b)),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_for_another_grantee_is_rejected() -> anyhow::Result<()> {
+        let rt = Runtime::new()?;
+        let dao = account("dao1");
+        let mut fork = DryRun::fork(
+            offline_remote(&rt, "bitsong-2b", "bitsong")?,
+            &ChainSnapshot::default(),
+            &ChainProfile::bitsong_mainnet(),
+        )?;
+
+        let exec = authz_exec(&account("grantee"), vec![]);
+        let err = fork.execute_authz(&dao, &exec).unwrap_err();
+        assert!(err.to_string().contains("signed by"), "{}", err);
+        Ok(())
+    }
+}
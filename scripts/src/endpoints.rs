@@ -0,0 +1,354 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use cosmos_sdk_proto::cosmos::base::tendermint::v1beta1::{
+    service_client::ServiceClient, GetLatestBlockRequest,
+};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+
+use crate::{errors::PlannerError, query::is_pruned};
+
+/// Prefix of the variable holding a chain's comma separated gRPC endpoints, used when none are
+/// passed with `--grpc-url`. See [`grpc_urls_env`].
+pub const GRPC_URLS_ENV_PREFIX: &str = "GRPC_URLS_";
+/// Endpoints further behind the most recent one are left out, they cannot answer at its height.
+pub const MAX_HEIGHT_LAG: u64 = 20;
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often an idempotent query is sent before its error is returned, and how long to wait in
+/// between. Every retry moves on to the next endpoint.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before retry number `attempt + 1`, doubling up to `max_backoff`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+}
+
+/// Failures another attempt, or another endpoint, may not run into. A pruned height is one:
+/// the next endpoint may be an archive node.
+pub fn is_retryable(status: &tonic::Status) -> bool {
+    match status.code() {
+        tonic::Code::Unavailable
+        | tonic::Code::DeadlineExceeded
+        | tonic::Code::ResourceExhausted
+        | tonic::Code::Aborted
+        | tonic::Code::Cancelled => true,
+        // connection resets surface as unknown errors of the transport
+        tonic::Code::Unknown => {
+            let message = status.message();
+            message.contains("transport error") || message.contains("h2 protocol error")
+        }
+        _ => is_pruned(status),
+    }
+}
+
+/// Variable holding the endpoints of `chain_id`, e.g. `GRPC_URLS_BITSONG_2B` for `bitsong-2b`.
+pub fn grpc_urls_env(chain_id: &str) -> String {
+    let key: String = chain_id
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    format!("{}{}", GRPC_URLS_ENV_PREFIX, key)
+}
+
+/// Endpoints from `--grpc-url`, else from [`grpc_urls_env`] of `chain_id`, else the profile's.
+pub fn configured_urls(cli: &[String], chain_id: &str, defaults: &[String]) -> Vec<String> {
+    if !cli.is_empty() {
+        return cli.to_vec();
+    }
+    if let Ok(value) = std::env::var(grpc_urls_env(chain_id)) {
+        let urls = parse_urls(&value);
+        if !urls.is_empty() {
+            return urls;
+        }
+    }
+    defaults.to_vec()
+}
+
+fn parse_urls(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+struct Connection {
+    url: String,
+    height: u64,
+    channel: Channel,
+}
+
+/// Latest height and chain id the node at `channel` reports.
+async fn latest_block(channel: Channel) -> Result<(u64, String), tonic::Status> {
+    let resp = ServiceClient::new(channel)
+        .get_latest_block(GetLatestBlockRequest {})
+        .await?
+        .into_inner();
+    // nodes before cosmos-sdk 0.47 only fill the deprecated tendermint block
+    let header = match (resp.sdk_block, resp.block) {
+        (Some(block), _) => block.header.map(|h| (h.height, h.chain_id)),
+        (None, Some(block)) => block.header.map(|h| (h.height, h.chain_id)),
+        (None, None) => None,
+    };
+    let (height, chain_id) =
+        header.ok_or_else(|| tonic::Status::internal("latest block without a header"))?;
+    Ok((height.max(0) as u64, chain_id))
+}
+
+async fn check(url: &str, chain_id: &str) -> anyhow::Result<Connection> {
+    let channel = Endpoint::from_shared(url.to_string())?
+        .tls_config(
+            ClientTlsConfig::new()
+                .with_enabled_roots()
+                // grpcs are http/2 by spec
+                .assume_http2(true),
+        )?
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .connect()
+        .await?;
+    let (height, node_chain_id) = latest_block(channel.clone()).await?;
+    anyhow::ensure!(
+        node_chain_id == chain_id,
+        "serves {} instead of {}",
+        node_chain_id,
+        chain_id
+    );
+    Ok(Connection {
+        url: url.to_string(),
+        height,
+        channel,
+    })
+}
+
+/// Healthy gRPC endpoints of one chain. Queries go to the current endpoint and move on to the
+/// next one when it fails, see [`Endpoints::call`].
+#[derive(Clone)]
+pub struct Endpoints {
+    connections: Arc<Vec<Connection>>,
+    current: Arc<AtomicUsize>,
+    retry: RetryPolicy,
+}
+
+impl Endpoints {
+    /// Connects to every url and keeps those serving `chain_id` within [`MAX_HEIGHT_LAG`] blocks
+    /// of the most recent one, in the order given.
+    pub async fn connect(
+        urls: &[String],
+        chain_id: &str,
+        retry: RetryPolicy,
+    ) -> anyhow::Result<Self> {
+        let mut connections = Vec::new();
+        let mut failures = Vec::new();
+        for url in urls {
+            match check(url, chain_id).await {
+                Ok(connection) => {
+                    log::info!("{} is healthy at height {}", url, connection.height);
+                    connections.push(connection);
+                }
+                Err(err) => {
+                    log::warn!("skipping {}: {}", url, err);
+                    failures.push(format!("{}: {}", url, err));
+                }
+            }
+        }
+
+        let best = connections
+            .iter()
+            .map(|c| c.height)
+            .max()
+            .unwrap_or_default();
+        connections.retain(|c| {
+            let lagging = c.height + MAX_HEIGHT_LAG < best;
+            if lagging {
+                log::warn!(
+                    "skipping {}: at height {}, {} blocks behind",
+                    c.url,
+                    c.height,
+                    best - c.height
+                );
+                failures.push(format!("{}: {} blocks behind", c.url, best - c.height));
+            }
+            !lagging
+        });
+
+        if connections.is_empty() {
+            return Err(PlannerError::NoHealthyEndpoint {
+                chain_id: chain_id.to_string(),
+                failures: failures.join("\n  "),
+            }
+            .into());
+        }
+        Ok(Endpoints {
+            connections: Arc::new(connections),
+            current: Arc::new(AtomicUsize::new(0)),
+            retry,
+        })
+    }
+
+    /// Urls of the healthy endpoints, current one first.
+    pub fn urls(&self) -> Vec<String> {
+        let current = self.current.load(Ordering::Relaxed);
+        (0..self.connections.len())
+            .map(|i| {
+                self.connections[(current + i) % self.connections.len()]
+                    .url
+                    .clone()
+            })
+            .collect()
+    }
+
+    /// Highest height every healthy endpoint had reached when it was checked.
+    pub fn common_height(&self) -> u64 {
+        self.connections
+            .iter()
+            .map(|c| c.height)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Latest height of the current endpoint.
+    pub async fn latest_height(&self) -> anyhow::Result<u64> {
+        let (height, _) = self.call("tendermint/latest_block", latest_block).await?;
+        Ok(height)
+    }
+
+    /// Runs the idempotent query `f` against the current endpoint. Retryable failures are sent
+    /// again to the next endpoint after a backoff, until `max_attempts` is reached.
+    pub async fn call<T, F, Fut>(&self, query: &str, mut f: F) -> Result<T, tonic::Status>
+    where
+        F: FnMut(Channel) -> Fut,
+        Fut: Future<Output = Result<T, tonic::Status>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let index = self.current.load(Ordering::Relaxed) % self.connections.len();
+            let connection = &self.connections[index];
+            match f(connection.channel.clone()).await {
+                Ok(resp) => {
+                    log::debug!("{} served by {}", query, connection.url);
+                    return Ok(resp);
+                }
+                Err(status) if is_retryable(&status) && attempt + 1 < self.retry.max_attempts => {
+                    let next = (index + 1) % self.connections.len();
+                    // concurrent failures on the same endpoint rotate only once
+                    let _ = self.current.compare_exchange(
+                        index,
+                        next,
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    );
+                    let delay = self.retry.backoff(attempt);
+                    log::warn!(
+                        "{} failed on {}: {}, retrying on {} in {:?}",
+                        query,
+                        connection.url,
+                        status.message(),
+                        self.connections[next].url,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(status) => {
+                    log::debug!("{} failed on {}: {}", query, connection.url, status);
+                    return Err(status);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let retry = RetryPolicy {
+            max_attempts: 6,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(3),
+        };
+        let delays: Vec<_> = (0..5).map(|attempt| retry.backoff(attempt)).collect();
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 3000, 3000].map(Duration::from_millis)
+        );
+        assert_eq!(retry.backoff(u32::MAX), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_only_transient_failures_are_retried() {
+        assert!(is_retryable(&tonic::Status::unavailable(
+            "connection refused"
+        )));
+        assert!(is_retryable(&tonic::Status::deadline_exceeded("timeout")));
+        assert!(is_retryable(&tonic::Status::unknown(
+            "transport error: connection reset"
+        )));
+        // an archive node may still have the height
+        assert!(is_retryable(&tonic::Status::invalid_argument(
+            "failed to load state at height 100; version does not exist"
+        )));
+
+        assert!(!is_retryable(&tonic::Status::not_found(
+            "validator not found"
+        )));
+        assert!(!is_retryable(&tonic::Status::invalid_argument(
+            "invalid delegator address"
+        )));
+        assert!(!is_retryable(&tonic::Status::unknown(
+            "codespace staking code 3"
+        )));
+    }
+
+    #[test]
+    fn test_urls_are_read_from_a_comma_separated_list() {
+        assert_eq!(
+            parse_urls(" http://a:9090, ,https://b:443 "),
+            ["http://a:9090", "https://b:443"]
+        );
+        assert_eq!(
+            configured_urls(
+                &["http://cli:9090".to_string()],
+                "bitsong-2b",
+                &["http://default:9090".to_string()]
+            ),
+            ["http://cli:9090"]
+        );
+        assert_eq!(grpc_urls_env("bitsong-2b"), "GRPC_URLS_BITSONG_2B");
+        assert_eq!(grpc_urls_env("cosmoshub-4"), "GRPC_URLS_COSMOSHUB_4");
+    }
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+
+use crate::{
+    plan::{PlanEntry, PlanEntryId},
+    precheck::Violation,
+};
+
+/// Staking, authz and bank failures a realignment tx commonly hits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    TooManyUnbondingEntries,
+    TooManyRedelegationEntries,
+    TransitiveRedelegation,
+    InsufficientShares,
+    AuthorizationNotFound,
+    AuthorizationExpired,
+    InsufficientFunds,
+    Other { codespace: String, code: u32 },
+}
+
+impl FailureKind {
+    /// Maps the ABCI `(codespace, code)` of a failed tx, see the cosmos-sdk `x/*/types/errors.go`.
+    pub fn from_code(codespace: &str, code: u32) -> Option<Self> {
+        match (codespace, code) {
+            ("staking", 22) | ("staking", 24) => Some(FailureKind::InsufficientShares),
+            ("staking", 27) => Some(FailureKind::TooManyUnbondingEntries),
+            ("staking", 32) => Some(FailureKind::TransitiveRedelegation),
+            ("staking", 33) => Some(FailureKind::TooManyRedelegationEntries),
+            ("authz", 2) => Some(FailureKind::AuthorizationNotFound),
+            ("authz", 6) => Some(FailureKind::AuthorizationExpired),
+            ("sdk", 5) => Some(FailureKind::InsufficientFunds),
+            _ => None,
+        }
+    }
+
+    /// Simulation errors only carry the log, so they are matched on the sdk error messages.
+    pub fn from_log(log: &str) -> Option<Self> {
+        let log = log.to_lowercase();
+        let kind = if log.contains("too many unbonding delegation entries") {
+            FailureKind::TooManyUnbondingEntries
+        } else if log.contains("too many redelegation entries") {
+            FailureKind::TooManyRedelegationEntries
+        } else if log.contains("redelegation to this validator already in progress") {
+            FailureKind::TransitiveRedelegation
+        } else if log.contains("insufficient delegation shares")
+            || log.contains("not enough delegation shares")
+        {
+            FailureKind::InsufficientShares
+        } else if log.contains("authorization not found") {
+            FailureKind::AuthorizationNotFound
+        } else if log.contains("authorization expired") {
+            FailureKind::AuthorizationExpired
+        } else if log.contains("insufficient funds") {
+            FailureKind::InsufficientFunds
+        } else {
+            return None;
+        };
+        Some(kind)
+    }
+
+    pub fn classify(codespace: &str, code: u32, log: &str) -> Self {
+        FailureKind::from_code(codespace, code)
+            .or_else(|| FailureKind::from_log(log))
+            .unwrap_or_else(|| FailureKind::Other {
+                codespace: codespace.to_string(),
+                code,
+            })
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureKind::TooManyUnbondingEntries => write!(
+                f,
+                "too many unbonding entries for this (DAO, validator), wait for one to mature or merge the undelegations"
+            ),
+            FailureKind::TooManyRedelegationEntries => write!(
+                f,
+                "too many redelegation entries for this (DAO, src, dst), wait for one to mature or merge the redelegations"
+            ),
+            FailureKind::TransitiveRedelegation => write!(
+                f,
+                "transitive redelegation, the DAO received a redelegation into the source validator that has not matured yet"
+            ),
+            FailureKind::InsufficientShares => write!(
+                f,
+                "insufficient delegation shares, the DAO has less staked on the validator than the plan moves"
+            ),
+            FailureKind::AuthorizationNotFound => write!(
+                f,
+                "authz grant not found, the DAO has not granted this message type to the broadcasting wallet"
+            ),
+            FailureKind::AuthorizationExpired => {
+                write!(f, "authz grant expired, the DAO must renew the grant")
+            }
+            FailureKind::InsufficientFunds => write!(
+                f,
+                "insufficient funds, the DAO's liquid balance does not cover the delegation"
+            ),
+            FailureKind::Other { codespace, code } => {
+                write!(f, "failed with code {} (codespace {})", code, codespace)
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PlannerError {
+    #[error("{entry} failed: {kind}\n  DAO: {dao}\n  validator: {validator}\n  amount: {amount}\n  log: {raw_log}")]
+    Entry {
+        entry: PlanEntryId,
+        dao: String,
+        validator: String,
+        amount: String,
+        kind: FailureKind,
+        raw_log: String,
+    },
+    #[error("bundle of {entries} msgs for {dao} failed: {kind}\n  log: {raw_log}")]
+    Bundle {
+        dao: String,
+        entries: usize,
+        kind: FailureKind,
+        raw_log: String,
+    },
+    #[error("bundle for {dao} no longer matches the chain at height {height}:\n  {}", violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join("\n  "))]
+    StalePlan {
+        dao: String,
+        height: u64,
+        violations: Vec<Violation>,
+    },
+    #[error("the node has pruned height {height}, plan against an archive node or a more recent height: {message}")]
+    HeightPruned { height: u64, message: String },
+    #[error("no healthy gRPC endpoint serves {chain_id}:\n  {failures}")]
+    NoHealthyEndpoint { chain_id: String, failures: String },
+}
+
+impl PlannerError {
+    pub fn entry(entry: &PlanEntry, kind: FailureKind, raw_log: &str) -> Self {
+        PlannerError::Entry {
+            entry: entry.id,
+            dao: entry.msg.delegator().to_string(),
+            validator: entry.msg.validator_label(),
+            amount: format!("{}{}", entry.msg.amount(), entry.msg.denom()),
+            kind,
+            raw_log: raw_log.to_string(),
+        }
+    }
+}
+
+/// Index of the failing message inside the authz `MsgExec`.
+/// Both the tx and `MsgExec` prefix the error with `message index: N`. The inner index is only
+/// present when a wrapped message failed, not when `MsgExec` itself was rejected.
+pub fn failing_exec_index(log: &str) -> Option<usize> {
+    let marker = "message index: ";
+    if log.matches(marker).count() < 2 {
+        return None;
+    }
+    let start = log.rfind(marker)? + marker.len();
+    let digits: String = log[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Finds the first message whose prefix of `items` fails, by bisecting over prefix length.
+/// Later messages can depend on earlier ones (shares moved, redelegations started), so failures are
+/// assumed to persist once a prefix fails. Returns the index of that message and its error.
+pub fn bisect_failing_prefix<T>(
+    items: &[T],
+    mut fails: impl FnMut(&[T]) -> Option<String>,
+) -> Option<(usize, String)> {
+    if items.is_empty() {
+        return None;
+    }
+    let mut err = fails(items)?;
+    let (mut lo, mut hi) = (1, items.len());
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        match fails(&items[..mid]) {
+            Some(e) => {
+                hi = mid;
+                err = e;
+            }
+            None => lo = mid + 1,
+        }
+    }
+    Some((hi - 1, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::{PlanMsg, PlanSection, RedelegateMsg};
+
+    #[test]
+    fn test_classify_staking_and_authz_failures() {
+        assert_eq!(
+            FailureKind::classify("staking", 27, ""),
+            FailureKind::TooManyUnbondingEntries
+        );
+        assert_eq!(
+            FailureKind::classify("authz", 6, ""),
+            FailureKind::AuthorizationExpired
+        );
+        assert_eq!(
+            FailureKind::classify(
+                "",
+                0,
+                "failed to execute message; message index: 0: redelegation to this validator already in progress; first redelegation to this validator must complete before next redelegation: invalid request"
+            ),
+            FailureKind::TransitiveRedelegation
+        );
+        assert_eq!(
+            FailureKind::classify("wasm", 5, "boom"),
+            FailureKind::Other {
+                codespace: "wasm".to_string(),
+                code: 5
+            }
+        );
+    }
+
+    #[test]
+    fn test_failing_exec_index_uses_innermost() {
+        let log = "failed to execute message; message index: 0: failed to execute message; message index: 17: insufficient delegation shares";
+        assert_eq!(failing_exec_index(log), Some(17));
+        assert_eq!(
+            failing_exec_index(
+                "failed to execute message; message index: 0: authorization not found"
+            ),
+            None
+        );
+        assert_eq!(failing_exec_index("out of gas"), None);
+    }
+
+    #[test]
+    fn test_bisect_isolates_first_bad_message() {
+        let msgs: Vec<u32> = (0..32).collect();
+        let mut simulations = 0;
+        let found = bisect_failing_prefix(&msgs, |prefix| {
+            simulations += 1;
+            prefix
+                .contains(&21)
+                .then(|| format!("msg 21 failed, {} simulated", prefix.len()))
+        });
+
+        assert_eq!(found, Some((21, "msg 21 failed, 22 simulated".to_string())));
+        assert!(simulations <= 7, "{} simulations", simulations);
+        assert_eq!(bisect_failing_prefix(&msgs, |_| None), None);
+    }
+
+    #[test]
+    fn test_entry_error_names_dao_validator_and_amount() {
+        let entry = PlanEntry {
+            id: PlanEntryId {
+                section: PlanSection::Redelegation,
+                index: 4,
+            },
+            msg: PlanMsg::Redelegate(RedelegateMsg {
+                delegator_address: "dao1".to_string(),
+                validator_src_address: "valA".to_string(),
+                validator_dst_address: "valB".to_string(),
+                amount: "1000".to_string(),
+                denom: "uatom".to_string(),
+            }),
+        };
+
+        let err =
+            PlannerError::entry(&entry, FailureKind::TooManyRedelegationEntries, "log").to_string();
+        assert!(err.starts_with("redelegations[4] failed: too many redelegation entries"));
+        assert!(err.contains("DAO: dao1"));
+        assert!(err.contains("validator: valA → valB"));
+        assert!(err.contains("amount: 1000uatom"));
+    }
+}
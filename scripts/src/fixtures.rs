@@ -0,0 +1,26 @@
+//! Factories the unit tests build snapshots and targets from.
+
+use cosmwasm_std::Uint128;
+
+use crate::snapshot::{BondStatus, Delegation, ValidatorState};
+
+/// `dao`'s stake of `amount` on `val`, or a target of `val` when `dao` is empty.
+pub(crate) fn del(dao: &str, val: &str, amount: u128) -> Delegation {
+    Delegation {
+        del_addr: dao.to_string(),
+        operator_addr: val.to_string(),
+        amount: Uint128::new(amount),
+    }
+}
+
+/// A bonded validator without tokens, jailed or not.
+pub(crate) fn validator(operator: &str, jailed: bool) -> ValidatorState {
+    ValidatorState {
+        operator_address: operator.to_string(),
+        status: BondStatus::Bonded,
+        jailed,
+        signing: None,
+        tokens: Uint128::zero(),
+        delegator_shares: Default::default(),
+    }
+}
@@ -0,0 +1,25 @@
+pub mod backend;
+pub mod broadcast;
+pub mod bundle;
+pub mod cache;
+pub mod chain;
+pub mod compound;
+pub mod csv_export;
+pub mod diff;
+pub mod dry_run;
+pub mod endpoints;
+pub mod errors;
+#[cfg(test)]
+mod fixtures;
+pub mod plan;
+pub mod precheck;
+pub mod query;
+pub mod reconcile;
+pub mod report;
+pub mod rewards;
+pub mod shares;
+pub mod simulator;
+pub mod snapshot;
+pub mod treasury;
+pub mod unbonding;
+pub mod verify;
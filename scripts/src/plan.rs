@@ -0,0 +1,401 @@
+use std::{fmt, str::FromStr};
+
+use cosmos_sdk_proto::{cosmos::authz::v1beta1::MsgExec, prost::Message};
+use cosmrs::{tx::Msg, AccountId};
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::Uint128;
+
+#[cw_serde]
+pub struct RedelegateMsg {
+    pub delegator_address: String,
+    pub validator_src_address: String,
+    pub validator_dst_address: String,
+    pub amount: String,
+    pub denom: String,
+}
+
+#[cw_serde]
+pub struct UndelegateMsg {
+    pub delegator_address: String,
+    pub validator_address: String,
+    pub amount: String,
+    pub denom: String,
+}
+
+#[cw_serde]
+pub struct DelegateMsg {
+    pub delegator_address: String,
+    pub validator_address: String,
+    pub amount: String,
+    pub denom: String,
+}
+
+/// Withdraws the rewards `delegator_address` earned on `validator_address` to its liquid balance.
+#[cw_serde]
+pub struct WithdrawRewardsMsg {
+    pub delegator_address: String,
+    pub validator_address: String,
+    /// Rewards pending at the plan's height, the message withdraws whatever is pending then.
+    pub amount: String,
+    pub denom: String,
+}
+
+/// Sends liquid `denom` of `from_address`, a DAO, to `to_address`.
+#[cw_serde]
+pub struct SendMsg {
+    pub from_address: String,
+    pub to_address: String,
+    pub amount: String,
+    pub denom: String,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct Sends {
+    pub data: Vec<SendMsg>,
+    pub count: usize,
+    pub total_ubtsg: Uint128,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct Withdrawals {
+    pub data: Vec<WithdrawRewardsMsg>,
+    pub count: usize,
+    pub total_ubtsg: Uint128,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct Redelegations {
+    pub data: Vec<RedelegateMsg>,
+    pub count: usize,
+    pub total_ubtsg: Uint128,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct Delegations {
+    pub data: Vec<DelegateMsg>,
+    pub count: usize,
+    pub total_ubtsg: Uint128,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct Undelegations {
+    pub data: Vec<UndelegateMsg>,
+    pub count: usize,
+    pub total_ubtsg: Uint128,
+}
+
+/// The plan written to `delegation_messages.json`.
+#[cw_serde]
+#[derive(Default)]
+pub struct MessageExport {
+    /// Height of the chain state the plan was computed from, 0 for plans that predate it.
+    #[serde(default)]
+    pub height: u64,
+    /// Rewards withdrawn ahead of the stake they would otherwise be withdrawn with, see
+    /// `--withdraw-rewards`.
+    #[serde(default)]
+    pub withdrawals: Withdrawals,
+    pub redelegations: Redelegations,
+    pub delegations: Delegations,
+    pub undelegates: Undelegations,
+    /// Liquid ubtsg routed out of the DAOs, see the `unbonding` command.
+    #[serde(default)]
+    pub sends: Sends,
+}
+
+/// Section of the export a plan entry was read from.
+#[cw_serde]
+#[derive(Copy, Eq, Hash, PartialOrd, Ord)]
+pub enum PlanSection {
+    Withdrawal,
+    Redelegation,
+    Delegation,
+    Undelegation,
+    Send,
+}
+
+/// Stable reference to one message of the plan, displayed as e.g. `redelegations[3]`.
+#[cw_serde]
+#[derive(Copy, Eq, Hash, PartialOrd, Ord)]
+pub struct PlanEntryId {
+    pub section: PlanSection,
+    pub index: usize,
+}
+
+/// The section's field in the export, e.g. `redelegations`.
+impl fmt::Display for PlanSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PlanSection::Withdrawal => "withdrawals",
+            PlanSection::Redelegation => "redelegations",
+            PlanSection::Delegation => "delegations",
+            PlanSection::Undelegation => "undelegates",
+            PlanSection::Send => "sends",
+        })
+    }
+}
+
+impl fmt::Display for PlanEntryId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]", self.section, self.index)
+    }
+}
+
+#[cw_serde]
+pub enum PlanMsg {
+    WithdrawRewards(WithdrawRewardsMsg),
+    Redelegate(RedelegateMsg),
+    Delegate(DelegateMsg),
+    Undelegate(UndelegateMsg),
+    Send(SendMsg),
+}
+
+impl PlanMsg {
+    pub fn delegator(&self) -> &str {
+        match self {
+            PlanMsg::WithdrawRewards(msg) => &msg.delegator_address,
+            PlanMsg::Redelegate(msg) => &msg.delegator_address,
+            PlanMsg::Delegate(msg) => &msg.delegator_address,
+            PlanMsg::Undelegate(msg) => &msg.delegator_address,
+            PlanMsg::Send(msg) => &msg.from_address,
+        }
+    }
+
+    /// The validator the message acts on, `src → dst` for redelegations, the recipient of sends.
+    pub fn validator_label(&self) -> String {
+        match self {
+            PlanMsg::WithdrawRewards(msg) => msg.validator_address.clone(),
+            PlanMsg::Redelegate(msg) => format!(
+                "{} → {}",
+                msg.validator_src_address, msg.validator_dst_address
+            ),
+            PlanMsg::Delegate(msg) => msg.validator_address.clone(),
+            PlanMsg::Undelegate(msg) => msg.validator_address.clone(),
+            PlanMsg::Send(msg) => msg.to_address.clone(),
+        }
+    }
+
+    /// Amount moved, or for withdrawals the rewards expected.
+    pub fn amount(&self) -> &str {
+        match self {
+            PlanMsg::WithdrawRewards(msg) => &msg.amount,
+            PlanMsg::Redelegate(msg) => &msg.amount,
+            PlanMsg::Delegate(msg) => &msg.amount,
+            PlanMsg::Undelegate(msg) => &msg.amount,
+            PlanMsg::Send(msg) => &msg.amount,
+        }
+    }
+
+    /// Denom of `amount`.
+    pub fn denom(&self) -> &str {
+        match self {
+            PlanMsg::WithdrawRewards(msg) => &msg.denom,
+            PlanMsg::Redelegate(msg) => &msg.denom,
+            PlanMsg::Delegate(msg) => &msg.denom,
+            PlanMsg::Undelegate(msg) => &msg.denom,
+            PlanMsg::Send(msg) => &msg.denom,
+        }
+    }
+
+    /// Encodes the message as the `Any` that gets broadcast.
+    pub fn to_any(&self) -> anyhow::Result<cosmrs::Any> {
+        let any = match self {
+            PlanMsg::WithdrawRewards(msg) => form_withdraw_msg(msg)?.into_any(),
+            PlanMsg::Redelegate(msg) => form_redel_msg(msg)?.into_any(),
+            PlanMsg::Delegate(msg) => form_del_msg(msg)?.into_any(),
+            PlanMsg::Undelegate(msg) => form_undel_msg(msg)?.into_any(),
+            PlanMsg::Send(msg) => form_send_msg(msg)?.into_any(),
+        };
+        any.map_err(|e| anyhow::anyhow!("failed to encode {:?}: {}", self, e))
+    }
+}
+
+#[cw_serde]
+pub struct PlanEntry {
+    pub id: PlanEntryId,
+    pub msg: PlanMsg,
+}
+
+impl MessageExport {
+    /// Flattens the export in broadcast order: withdrawals, redelegations, delegations,
+    /// undelegations, then sends.
+    pub fn entries(&self) -> Vec<PlanEntry> {
+        let withdrawals = self.withdrawals.data.iter().enumerate().map(|(i, msg)| {
+            (
+                PlanSection::Withdrawal,
+                i,
+                PlanMsg::WithdrawRewards(msg.clone()),
+            )
+        });
+        let redels = self.redelegations.data.iter().enumerate().map(|(i, msg)| {
+            (
+                PlanSection::Redelegation,
+                i,
+                PlanMsg::Redelegate(msg.clone()),
+            )
+        });
+        let dels = self
+            .delegations
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, msg)| (PlanSection::Delegation, i, PlanMsg::Delegate(msg.clone())));
+        let undels = self.undelegates.data.iter().enumerate().map(|(i, msg)| {
+            (
+                PlanSection::Undelegation,
+                i,
+                PlanMsg::Undelegate(msg.clone()),
+            )
+        });
+
+        let sends = self
+            .sends
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, msg)| (PlanSection::Send, i, PlanMsg::Send(msg.clone())));
+
+        withdrawals
+            .chain(redels)
+            .chain(dels)
+            .chain(undels)
+            .chain(sends)
+            .map(|(section, index, msg)| PlanEntry {
+                id: PlanEntryId { section, index },
+                msg,
+            })
+            .collect()
+    }
+
+    /// Sets each section's `count` and `total_ubtsg` from its messages.
+    pub fn recompute_totals(&mut self) -> anyhow::Result<()> {
+        fn sum<'a>(amounts: impl Iterator<Item = &'a String>) -> anyhow::Result<Uint128> {
+            amounts.map(|a| Ok(Uint128::from_str(a)?)).sum()
+        }
+        self.withdrawals.count = self.withdrawals.data.len();
+        self.withdrawals.total_ubtsg = sum(self.withdrawals.data.iter().map(|m| &m.amount))?;
+        self.redelegations.count = self.redelegations.data.len();
+        self.redelegations.total_ubtsg = sum(self.redelegations.data.iter().map(|m| &m.amount))?;
+        self.delegations.count = self.delegations.data.len();
+        self.delegations.total_ubtsg = sum(self.delegations.data.iter().map(|m| &m.amount))?;
+        self.undelegates.count = self.undelegates.data.len();
+        self.undelegates.total_ubtsg = sum(self.undelegates.data.iter().map(|m| &m.amount))?;
+        self.sends.count = self.sends.data.len();
+        self.sends.total_ubtsg = sum(self.sends.data.iter().map(|m| &m.amount))?;
+        Ok(())
+    }
+
+    pub fn entry(&self, id: &PlanEntryId) -> Option<PlanEntry> {
+        let msg = match id.section {
+            PlanSection::Withdrawal => self
+                .withdrawals
+                .data
+                .get(id.index)
+                .cloned()
+                .map(PlanMsg::WithdrawRewards),
+            PlanSection::Redelegation => self
+                .redelegations
+                .data
+                .get(id.index)
+                .cloned()
+                .map(PlanMsg::Redelegate),
+            PlanSection::Delegation => self
+                .delegations
+                .data
+                .get(id.index)
+                .cloned()
+                .map(PlanMsg::Delegate),
+            PlanSection::Undelegation => self
+                .undelegates
+                .data
+                .get(id.index)
+                .cloned()
+                .map(PlanMsg::Undelegate),
+            PlanSection::Send => self.sends.data.get(id.index).cloned().map(PlanMsg::Send),
+        }?;
+        Some(PlanEntry { id: *id, msg })
+    }
+}
+
+/// Wraps `msgs` in the `MsgExec` the grantee signs, like the authz wallet does on broadcast.
+pub fn authz_exec(grantee: &str, msgs: Vec<cosmrs::Any>) -> cosmrs::Any {
+    cosmrs::Any {
+        type_url: "/cosmos.authz.v1beta1.MsgExec".to_string(),
+        value: MsgExec {
+            grantee: grantee.to_string(),
+            msgs,
+        }
+        .encode_to_vec(),
+    }
+}
+
+fn account(addr: &str) -> anyhow::Result<AccountId> {
+    AccountId::from_str(addr).map_err(|e| anyhow::anyhow!("invalid address {}: {}", addr, e))
+}
+
+fn coin(amount: &str, denom: &str) -> anyhow::Result<cosmrs::Coin> {
+    Ok(cosmrs::Coin {
+        amount: Uint128::from_str(amount)?.u128(),
+        denom: cosmrs::Denom::from_str(denom).map_err(|e| anyhow::anyhow!("{}", e))?,
+    })
+}
+
+fn form_redel_msg(redel: &RedelegateMsg) -> anyhow::Result<cosmrs::staking::MsgBeginRedelegate> {
+    Ok(cosmrs::staking::MsgBeginRedelegate {
+        // Delegator's address.
+        delegator_address: account(&redel.delegator_address)?,
+
+        // Source validator's address.
+        validator_src_address: account(&redel.validator_src_address)?,
+
+        // Destination validator's address.
+        validator_dst_address: account(&redel.validator_dst_address)?,
+
+        // Amount to UnDelegate
+        amount: coin(&redel.amount, &redel.denom)?,
+    })
+}
+
+fn form_del_msg(del: &DelegateMsg) -> anyhow::Result<cosmrs::staking::MsgDelegate> {
+    Ok(cosmrs::staking::MsgDelegate {
+        // Delegator's address.
+        delegator_address: account(&del.delegator_address)?,
+        validator_address: account(&del.validator_address)?,
+
+        // Amount to Delegate
+        amount: coin(&del.amount, &del.denom)?,
+    })
+}
+
+fn form_undel_msg(del: &UndelegateMsg) -> anyhow::Result<cosmrs::staking::MsgUndelegate> {
+    Ok(cosmrs::staking::MsgUndelegate {
+        // Delegator's address.
+        delegator_address: account(&del.delegator_address)?,
+        validator_address: account(&del.validator_address)?,
+
+        // Amount to Delegate
+        amount: coin(&del.amount, &del.denom)?,
+    })
+}
+
+fn form_withdraw_msg(
+    withdraw: &WithdrawRewardsMsg,
+) -> anyhow::Result<cosmrs::distribution::MsgWithdrawDelegatorReward> {
+    Ok(cosmrs::distribution::MsgWithdrawDelegatorReward {
+        delegator_address: account(&withdraw.delegator_address)?,
+        validator_address: account(&withdraw.validator_address)?,
+    })
+}
+
+fn form_send_msg(send: &SendMsg) -> anyhow::Result<cosmrs::bank::MsgSend> {
+    Ok(cosmrs::bank::MsgSend {
+        from_address: account(&send.from_address)?,
+        to_address: account(&send.to_address)?,
+        amount: vec![coin(&send.amount, &send.denom)?],
+    })
+}
@@ -0,0 +1,243 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use cosmwasm_std::Uint128;
+
+use crate::{
+    errors::PlannerError,
+    plan::PlanMsg,
+    snapshot::{ChainSnapshot, ValidatorClass},
+};
+
+/// A precondition of a bundle that no longer holds on chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// The DAO now has less staked on `validator` than the bundle moves away from it.
+    SourceShrunk {
+        validator: String,
+        planned: Uint128,
+        live: Uint128,
+        denom: String,
+    },
+    /// `validator` would receive stake but is jailed, tombstoned or no longer bonded.
+    InactiveDestination {
+        validator: String,
+        class: ValidatorClass,
+    },
+    UnknownDestination {
+        validator: String,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::SourceShrunk {
+                validator,
+                planned,
+                live,
+                denom,
+            } => write!(
+                f,
+                "{} now holds {}{} of the DAO's stake, the bundle moves {}{}",
+                validator, live, denom, planned, denom
+            ),
+            Violation::InactiveDestination { validator, class } => {
+                write!(f, "destination {} is {}", validator, class)
+            }
+            Violation::UnknownDestination { validator } => {
+                write!(f, "destination {} does not exist", validator)
+            }
+        }
+    }
+}
+
+/// Stake each validator loses to `msgs`, keyed by `(delegator, validator)`.
+pub fn source_outflows(msgs: &[PlanMsg]) -> anyhow::Result<BTreeMap<(String, String), Uint128>> {
+    let mut outflows: BTreeMap<(String, String), Uint128> = BTreeMap::new();
+    for msg in msgs {
+        let src = match msg {
+            PlanMsg::Redelegate(m) => &m.validator_src_address,
+            PlanMsg::Undelegate(m) => &m.validator_address,
+            PlanMsg::Delegate(_) | PlanMsg::WithdrawRewards(_) | PlanMsg::Send(_) => continue,
+        };
+        *outflows
+            .entry((msg.delegator().to_string(), src.clone()))
+            .or_default() += Uint128::from_str(msg.amount())?;
+    }
+    Ok(outflows)
+}
+
+/// Validators that receive stake from `msgs`.
+pub fn destinations(msgs: &[PlanMsg]) -> Vec<String> {
+    let mut dsts: Vec<String> = msgs
+        .iter()
+        .filter_map(|msg| match msg {
+            PlanMsg::Redelegate(m) => Some(m.validator_dst_address.clone()),
+            PlanMsg::Delegate(m) => Some(m.validator_address.clone()),
+            PlanMsg::Undelegate(_) | PlanMsg::WithdrawRewards(_) | PlanMsg::Send(_) => None,
+        })
+        .collect();
+    dsts.sort();
+    dsts.dedup();
+    dsts
+}
+
+/// Compares the `live` state a bundle will execute against with the `expected` state it was planned for.
+/// Fails if a source delegation shrank below what the bundle moves or a destination stopped being
+/// active. Other differences from the plan are returned as warnings.
+pub fn check_bundle(
+    dao: &str,
+    msgs: &[PlanMsg],
+    expected: &ChainSnapshot,
+    live: &ChainSnapshot,
+) -> anyhow::Result<Vec<String>> {
+    let mut violations = Vec::new();
+    let mut drift = Vec::new();
+    // a plan's messages all move its chain's staking denom
+    let denom = msgs.first().map(PlanMsg::denom).unwrap_or_default();
+
+    for ((delegator, validator), planned) in source_outflows(msgs)? {
+        let live_amount = live.delegation(&delegator, &validator);
+        if live_amount < planned {
+            violations.push(Violation::SourceShrunk {
+                validator,
+                planned,
+                live: live_amount,
+                denom: denom.to_string(),
+            });
+            continue;
+        }
+        let expected_amount = expected.delegation(&delegator, &validator);
+        if live_amount != expected_amount {
+            drift.push(format!(
+                "{} has {}{} on {}, the plan expected {}{}",
+                delegator, live_amount, denom, validator, expected_amount, denom
+            ));
+        }
+    }
+
+    for validator in destinations(msgs) {
+        match live.validator(&validator) {
+            Some(state) if state.is_active() => {}
+            Some(state) => violations.push(Violation::InactiveDestination {
+                validator,
+                class: state.class(),
+            }),
+            None => violations.push(Violation::UnknownDestination { validator }),
+        }
+    }
+
+    if !violations.is_empty() {
+        return Err(PlannerError::StalePlan {
+            dao: dao.to_string(),
+            height: live.height,
+            violations,
+        }
+        .into());
+    }
+    Ok(drift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        plan::{DelegateMsg, RedelegateMsg},
+        snapshot::{BondStatus, Delegation, ValidatorState},
+    };
+    use cosmwasm_std::Decimal256;
+
+    fn state(delegated: u128, dst_status: BondStatus, dst_jailed: bool) -> ChainSnapshot {
+        ChainSnapshot {
+            height: 10,
+            delegations: vec![Delegation {
+                del_addr: "dao1".to_string(),
+                operator_addr: "valA".to_string(),
+                amount: Uint128::new(delegated),
+            }],
+            validators: vec![ValidatorState {
+                operator_address: "valB".to_string(),
+                status: dst_status,
+                jailed: dst_jailed,
+                signing: None,
+                tokens: Uint128::zero(),
+                delegator_shares: Decimal256::zero(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn bundle() -> Vec<PlanMsg> {
+        vec![
+            PlanMsg::Redelegate(RedelegateMsg {
+                delegator_address: "dao1".to_string(),
+                validator_src_address: "valA".to_string(),
+                validator_dst_address: "valB".to_string(),
+                amount: "70".to_string(),
+                denom: "ubtsg".to_string(),
+            }),
+            PlanMsg::Redelegate(RedelegateMsg {
+                delegator_address: "dao1".to_string(),
+                validator_src_address: "valA".to_string(),
+                validator_dst_address: "valB".to_string(),
+                amount: "20".to_string(),
+                denom: "ubtsg".to_string(),
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_unchanged_state_passes() -> anyhow::Result<()> {
+        let expected = state(100, BondStatus::Bonded, false);
+        let drift = check_bundle("dao1", &bundle(), &expected, &expected)?;
+        assert!(drift.is_empty(), "{:?}", drift);
+
+        let drift = check_bundle(
+            "dao1",
+            &bundle(),
+            &expected,
+            &state(95, BondStatus::Bonded, false),
+        )?;
+        assert_eq!(drift.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shrunk_source_is_refused() {
+        // 90 leaves valA across both messages, a single message alone would fit in 80
+        let err = check_bundle(
+            "dao1",
+            &bundle(),
+            &state(100, BondStatus::Bonded, false),
+            &state(80, BondStatus::Bonded, false),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("valA now holds 80ubtsg of the DAO's stake, the bundle moves 90ubtsg"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_inactive_destination_is_refused() {
+        let expected = state(100, BondStatus::Bonded, false);
+        for live in [
+            state(100, BondStatus::Unbonding, false),
+            state(100, BondStatus::Bonded, true),
+        ] {
+            let err = check_bundle("dao1", &bundle(), &expected, &live).unwrap_err();
+            assert!(err.to_string().contains("destination valB is"), "{}", err);
+        }
+
+        let delegate = vec![PlanMsg::Delegate(DelegateMsg {
+            delegator_address: "dao1".to_string(),
+            validator_address: "valC".to_string(),
+            amount: "1".to_string(),
+            denom: "ubtsg".to_string(),
+        })];
+        let err = check_bundle("dao1", &delegate, &expected, &expected).unwrap_err();
+        assert!(err.to_string().contains("valC does not exist"), "{}", err);
+    }
+}
@@ -0,0 +1,509 @@
+use std::str::FromStr;
+
+use cosmos_sdk_proto::cosmos::{
+    bank::v1beta1::{query_client::QueryClient as BankQueryClient, QueryBalanceRequest},
+    base::query::v1beta1::PageRequest,
+    distribution::v1beta1::{
+        query_client::QueryClient as DistributionQueryClient, QueryDelegationTotalRewardsRequest,
+    },
+    slashing::v1beta1::{
+        query_client::QueryClient as SlashingQueryClient, QuerySigningInfoRequest,
+        ValidatorSigningInfo,
+    },
+    staking::v1beta1::{
+        query_client::QueryClient, BondStatus as ProtoBondStatus, DelegationResponse,
+        QueryDelegationRequest, QueryDelegatorDelegationsRequest,
+        QueryDelegatorUnbondingDelegationsRequest, QueryParamsRequest, QueryValidatorRequest,
+        Validator as ProtoValidator,
+    },
+};
+use cosmwasm_std::{Decimal256, Uint128, Uint256};
+
+use crate::{
+    backend::{BankBackend, DistributionBackend, StakingBackend},
+    endpoints::Endpoints,
+    errors::PlannerError,
+    snapshot::{
+        BondStatus, Delegation, DelegatorShares, PendingReward, SigningInfo, Unbonding,
+        ValidatorState,
+    },
+};
+
+/// gRPC metadata the cosmos-sdk reads the height of a query from.
+pub const BLOCK_HEIGHT_HEADER: &str = "x-cosmos-block-height";
+
+/// Staking and bank queries against the healthy gRPC endpoints, either at the latest height or
+/// all at one pinned height so that everything read is from the same block.
+pub struct ChainQuerier {
+    endpoints: Endpoints,
+    height: Option<u64>,
+    /// Bech32 prefix of consensus addresses, signing infos are keyed by them.
+    consensus_prefix: String,
+}
+
+fn is_not_found(status: &tonic::Status) -> bool {
+    status.code() == tonic::Code::NotFound || status.message().contains("not found")
+}
+
+/// The node no longer keeps the state of the requested height, see `BaseApp.CreateQueryContext`.
+pub(crate) fn is_pruned(status: &tonic::Status) -> bool {
+    let message = status.message();
+    message.contains("failed to load state at height") || message.contains("version does not exist")
+}
+
+fn query_error(height: Option<u64>, status: tonic::Status) -> anyhow::Error {
+    match height {
+        Some(height) if is_pruned(&status) => PlannerError::HeightPruned {
+            height,
+            message: status.message().to_string(),
+        }
+        .into(),
+        _ => status.into(),
+    }
+}
+
+fn next_page(next_key: Vec<u8>) -> Option<PageRequest> {
+    if next_key.is_empty() {
+        return None;
+    }
+    Some(PageRequest {
+        key: next_key,
+        offset: 0,
+        limit: 100,
+        count_total: false,
+        reverse: false,
+    })
+}
+
+/// Bech32 consensus address of `v` with `prefix`, the key signing infos are stored under.
+fn consensus_address(v: &ProtoValidator, prefix: &str) -> anyhow::Result<String> {
+    let pubkey = v
+        .consensus_pubkey
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("validator {} has no consensus key", v.operator_address))?;
+    let pubkey = cosmrs::crypto::PublicKey::try_from(pubkey)
+        .map_err(|e| anyhow::anyhow!("consensus key of {}: {}", v.operator_address, e))?;
+    let id = cosmrs::tendermint::account::Id::from(cosmrs::tendermint::PublicKey::from(pubkey));
+    Ok(cosmrs::AccountId::new(prefix, id.as_bytes())
+        .map_err(|e| anyhow::anyhow!("{}", e))?
+        .to_string())
+}
+
+fn signing_info(info: ValidatorSigningInfo) -> anyhow::Result<SigningInfo> {
+    let jailed_until = match info.jailed_until {
+        Some(ts) => {
+            rfc3339(ts).map_err(|e| anyhow::anyhow!("jailed until of {}: {}", info.address, e))?
+        }
+        None => String::new(),
+    };
+    Ok(SigningInfo {
+        tombstoned: info.tombstoned,
+        jailed_until,
+        missed_blocks: info.missed_blocks_counter.max(0) as u64,
+    })
+}
+
+fn rfc3339(ts: cosmos_sdk_proto::Timestamp) -> anyhow::Result<String> {
+    Ok(
+        cosmrs::tendermint::Time::from_unix_timestamp(ts.seconds, ts.nanos.max(0) as u32)
+            .map_err(|e| anyhow::anyhow!("{}", e))?
+            .to_rfc3339(),
+    )
+}
+
+/// A `LegacyDec` such as shares or a `DecCoin` amount. gRPC sends them as their 18 decimal
+/// atomics, some gateways as a decimal string.
+fn legacy_dec(value: &str) -> anyhow::Result<Decimal256> {
+    if value.is_empty() {
+        return Ok(Decimal256::zero());
+    }
+    if value.contains('.') {
+        return Ok(Decimal256::from_str(value)?);
+    }
+    Ok(Decimal256::new(Uint256::from_str(value)?))
+}
+
+/// Whole units of a `DecCoin` amount.
+fn dec_coin_amount(amount: &str) -> anyhow::Result<Uint128> {
+    Ok(Uint128::try_from(legacy_dec(amount)?.to_uint_floor())?)
+}
+
+fn validator_state(
+    v: ProtoValidator,
+    signing: Option<SigningInfo>,
+) -> anyhow::Result<ValidatorState> {
+    let status = match ProtoBondStatus::try_from(v.status) {
+        Ok(ProtoBondStatus::Bonded) => BondStatus::Bonded,
+        Ok(ProtoBondStatus::Unbonding) => BondStatus::Unbonding,
+        Ok(ProtoBondStatus::Unbonded) => BondStatus::Unbonded,
+        _ => anyhow::bail!(
+            "validator {} has unknown status {}",
+            v.operator_address,
+            v.status
+        ),
+    };
+    Ok(ValidatorState {
+        tokens: Uint128::from_str(&v.tokens)?,
+        delegator_shares: legacy_dec(&v.delegator_shares)?,
+        operator_address: v.operator_address,
+        status,
+        jailed: v.jailed,
+        signing,
+    })
+}
+
+impl ChainQuerier {
+    /// Queries the latest state.
+    pub fn new(endpoints: Endpoints, consensus_prefix: &str) -> Self {
+        ChainQuerier {
+            endpoints,
+            height: None,
+            consensus_prefix: consensus_prefix.to_string(),
+        }
+    }
+
+    /// Queries the state at `height` only. Fails right away if the node has already pruned it.
+    pub async fn pinned(
+        endpoints: Endpoints,
+        consensus_prefix: &str,
+        height: u64,
+    ) -> anyhow::Result<Self> {
+        let querier = ChainQuerier {
+            endpoints,
+            height: Some(height),
+            consensus_prefix: consensus_prefix.to_string(),
+        };
+        querier
+            .endpoints
+            .call("staking/params", |channel| {
+                let request = querier.request(QueryParamsRequest {});
+                async move { QueryClient::new(channel).params(request).await }
+            })
+            .await
+            .map_err(|status| query_error(querier.height, status))?;
+        Ok(querier)
+    }
+
+    /// Every page of `delegator`'s delegations.
+    async fn delegation_responses(
+        &self,
+        delegator: &str,
+    ) -> anyhow::Result<Vec<DelegationResponse>> {
+        let mut responses = Vec::new();
+        let mut pagination = None;
+        loop {
+            let resp = self
+                .endpoints
+                .call("staking/delegator_delegations", |channel| {
+                    let request = self.request(QueryDelegatorDelegationsRequest {
+                        delegator_addr: delegator.to_string(),
+                        pagination: pagination.clone(),
+                    });
+                    async move {
+                        QueryClient::new(channel)
+                            .delegator_delegations(request)
+                            .await
+                    }
+                })
+                .await
+                .map_err(|status| query_error(self.height, status))?
+                .into_inner();
+
+            responses.extend(resp.delegation_responses);
+            pagination = resp.pagination.and_then(|p| next_page(p.next_key));
+            if pagination.is_none() {
+                return Ok(responses);
+            }
+        }
+    }
+
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(height) = self.height {
+            request
+                .metadata_mut()
+                .insert(BLOCK_HEIGHT_HEADER, height.into());
+        }
+        request
+    }
+}
+
+impl StakingBackend for ChainQuerier {
+    /// The pinned height, else the latest one.
+    async fn block_height(&self) -> anyhow::Result<u64> {
+        if let Some(height) = self.height {
+            return Ok(height);
+        }
+        self.endpoints.latest_height().await
+    }
+
+    async fn delegator_delegations(&self, delegator: &str) -> anyhow::Result<Vec<Delegation>> {
+        Ok(self.delegator_stake(delegator).await?.0)
+    }
+
+    async fn delegator_stake(
+        &self,
+        delegator: &str,
+    ) -> anyhow::Result<(Vec<Delegation>, Vec<DelegatorShares>)> {
+        let mut delegations = Vec::new();
+        let mut shares = Vec::new();
+        for resp in self.delegation_responses(delegator).await? {
+            let (Some(del), Some(balance)) = (resp.delegation, resp.balance) else {
+                anyhow::bail!(
+                    "delegation response of {} without delegation or balance",
+                    delegator
+                );
+            };
+            shares.push(DelegatorShares {
+                delegator: del.delegator_address.clone(),
+                validator: del.validator_address.clone(),
+                shares: legacy_dec(&del.shares)?,
+            });
+            delegations.push(Delegation {
+                del_addr: del.delegator_address,
+                operator_addr: del.validator_address,
+                amount: Uint128::from_str(&balance.amount)?,
+            });
+        }
+        Ok((delegations, shares))
+    }
+
+    async fn delegation(&self, delegator: &str, validator: &str) -> anyhow::Result<Uint128> {
+        let resp = self
+            .endpoints
+            .call("staking/delegation", |channel| {
+                let request = self.request(QueryDelegationRequest {
+                    delegator_addr: delegator.to_string(),
+                    validator_addr: validator.to_string(),
+                });
+                async move { QueryClient::new(channel).delegation(request).await }
+            })
+            .await;
+
+        match resp {
+            Ok(resp) => {
+                let balance = resp
+                    .into_inner()
+                    .delegation_response
+                    .and_then(|d| d.balance)
+                    .map(|b| b.amount)
+                    .unwrap_or_default();
+                if balance.is_empty() {
+                    return Ok(Uint128::zero());
+                }
+                Ok(Uint128::from_str(&balance)?)
+            }
+            Err(status) if is_not_found(&status) => Ok(Uint128::zero()),
+            Err(status) => Err(query_error(self.height, status)),
+        }
+    }
+
+    async fn validator(&self, operator: &str) -> anyhow::Result<Option<ValidatorState>> {
+        let resp = self
+            .endpoints
+            .call("staking/validator", |channel| {
+                let request = self.request(QueryValidatorRequest {
+                    validator_addr: operator.to_string(),
+                });
+                async move { QueryClient::new(channel).validator(request).await }
+            })
+            .await;
+
+        let validator = match resp {
+            Ok(resp) => resp.into_inner().validator,
+            Err(status) if is_not_found(&status) => None,
+            Err(status) => return Err(query_error(self.height, status)),
+        };
+        let Some(validator) = validator else {
+            return Ok(None);
+        };
+
+        // validators that never signed a block have no signing info yet
+        let cons_address = consensus_address(&validator, &self.consensus_prefix)?;
+        let resp = self
+            .endpoints
+            .call("slashing/signing_info", |channel| {
+                let request = self.request(QuerySigningInfoRequest {
+                    cons_address: cons_address.clone(),
+                });
+                async move {
+                    SlashingQueryClient::new(channel)
+                        .signing_info(request)
+                        .await
+                }
+            })
+            .await;
+        let signing = match resp {
+            Ok(resp) => resp
+                .into_inner()
+                .val_signing_info
+                .map(signing_info)
+                .transpose()?,
+            Err(status) if is_not_found(&status) => None,
+            Err(status) => return Err(query_error(self.height, status)),
+        };
+        validator_state(validator, signing).map(Some)
+    }
+
+    async fn unbonding_delegations(&self, delegator: &str) -> anyhow::Result<Vec<Unbonding>> {
+        let mut unbonding = Vec::new();
+        let mut pagination = None;
+        loop {
+            let resp = self
+                .endpoints
+                .call("staking/delegator_unbonding_delegations", |channel| {
+                    let request = self.request(QueryDelegatorUnbondingDelegationsRequest {
+                        delegator_addr: delegator.to_string(),
+                        pagination: pagination.clone(),
+                    });
+                    async move {
+                        QueryClient::new(channel)
+                            .delegator_unbonding_delegations(request)
+                            .await
+                    }
+                })
+                .await
+                .map_err(|status| query_error(self.height, status))?
+                .into_inner();
+
+            for ubd in resp.unbonding_responses {
+                for entry in ubd.entries {
+                    let completion_time = entry
+                        .completion_time
+                        .map(rfc3339)
+                        .transpose()?
+                        .unwrap_or_default();
+                    unbonding.push(Unbonding {
+                        delegator: ubd.delegator_address.clone(),
+                        validator: ubd.validator_address.clone(),
+                        amount: Uint128::from_str(&entry.balance)?,
+                        creation_height: entry.creation_height.max(0) as u64,
+                        completion_time,
+                    });
+                }
+            }
+            pagination = resp.pagination.and_then(|p| next_page(p.next_key));
+            if pagination.is_none() {
+                return Ok(unbonding);
+            }
+        }
+    }
+}
+
+impl DistributionBackend for ChainQuerier {
+    async fn pending_rewards(
+        &self,
+        delegator: &str,
+        denom: &str,
+    ) -> anyhow::Result<Vec<PendingReward>> {
+        let resp = self
+            .endpoints
+            .call("distribution/delegation_total_rewards", |channel| {
+                let request = self.request(QueryDelegationTotalRewardsRequest {
+                    delegator_address: delegator.to_string(),
+                });
+                async move {
+                    DistributionQueryClient::new(channel)
+                        .delegation_total_rewards(request)
+                        .await
+                }
+            })
+            .await
+            .map_err(|status| query_error(self.height, status))?
+            .into_inner();
+
+        let mut rewards = Vec::new();
+        for reward in resp.rewards {
+            for coin in reward.reward.iter().filter(|c| c.denom == denom) {
+                rewards.push(PendingReward {
+                    delegator: delegator.to_string(),
+                    validator: reward.validator_address.clone(),
+                    amount: dec_coin_amount(&coin.amount)?,
+                });
+            }
+        }
+        Ok(rewards)
+    }
+}
+
+impl BankBackend for ChainQuerier {
+    async fn balance(&self, address: &str, denom: &str) -> anyhow::Result<Uint128> {
+        let balance = self
+            .endpoints
+            .call("bank/balance", |channel| {
+                let request = self.request(QueryBalanceRequest {
+                    address: address.to_string(),
+                    denom: denom.to_string(),
+                });
+                async move { BankQueryClient::new(channel).balance(request).await }
+            })
+            .await
+            .map_err(|status| query_error(self.height, status))?
+            .into_inner()
+            .balance;
+        match balance {
+            Some(coin) if !coin.amount.is_empty() => Ok(Uint128::from_str(&coin.amount)?),
+            _ => Ok(Uint128::zero()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmos_sdk_proto::{cosmos::crypto::ed25519::PubKey, prost::Message};
+
+    #[test]
+    fn test_pruned_height_is_reported_with_the_height() {
+        let pruned = tonic::Status::invalid_argument(
+            "failed to load state at height 100; version does not exist (latest height: 5000)",
+        );
+        let err = query_error(Some(100), pruned.clone());
+        assert!(matches!(
+            err.downcast_ref::<PlannerError>(),
+            Some(PlannerError::HeightPruned { height: 100, .. })
+        ));
+
+        // unpinned queries and other failures keep the node's error
+        assert!(query_error(None, pruned)
+            .downcast_ref::<PlannerError>()
+            .is_none());
+        let unavailable = tonic::Status::unavailable("connection refused");
+        assert!(query_error(Some(100), unavailable)
+            .downcast_ref::<PlannerError>()
+            .is_none());
+    }
+
+    #[test]
+    fn test_dec_coin_amount_is_truncated() -> anyhow::Result<()> {
+        // 1234.56ubtsg as the atomics of an 18 decimal Dec
+        assert_eq!(
+            dec_coin_amount("1234560000000000000000")?,
+            Uint128::new(1234)
+        );
+        assert_eq!(dec_coin_amount("1234.56")?, Uint128::new(1234));
+        assert_eq!(dec_coin_amount("0")?, Uint128::zero());
+        Ok(())
+    }
+
+    #[test]
+    fn test_consensus_address_uses_the_profiles_prefix() -> anyhow::Result<()> {
+        let validator = ProtoValidator {
+            operator_address: "bitsongvaloper1ugjdm344ttut92yyqjstdjexzldsmlp9tfcc7h".to_string(),
+            consensus_pubkey: Some(cosmos_sdk_proto::Any {
+                type_url: "/cosmos.crypto.ed25519.PubKey".to_string(),
+                value: PubKey {
+                    key: (1..=32).collect(),
+                }
+                .encode_to_vec(),
+            }),
+            ..Default::default()
+        };
+        // first 20 bytes of the key's sha256
+        assert_eq!(
+            consensus_address(&validator, "bitsongvalcons")?,
+            "bitsongvalcons14cskcth4y3ar0qkpxhh6y7drunxuvyy5jtdfh3"
+        );
+        Ok(())
+    }
+}
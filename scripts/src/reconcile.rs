@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::Uint128;
+
+use crate::snapshot::Delegation;
+
+/// Report of the `reconcile` command.
+pub const RECONCILE_JSON: &str = "delegation_reconcile.json";
+/// Plan that closes the gaps left after execution, in the same format as `delegation_messages.json`.
+pub const FOLLOWUP_MSG_JSON: &str = "delegation_followup.json";
+/// Verification of the follow-up plan, kept apart from the one of the original plan.
+pub const RECONCILE_VERIFICATION_JSON: &str = "delegation_reconcile_verification.json";
+
+/// A validator whose live DAO stake differs from its target.
+#[cw_serde]
+pub struct ValidatorGap {
+    pub operator_addr: String,
+    pub target: Uint128,
+    pub live: Uint128,
+}
+
+impl ValidatorGap {
+    pub fn surplus(&self) -> Uint128 {
+        self.live.saturating_sub(self.target)
+    }
+
+    pub fn deficit(&self) -> Uint128 {
+        self.target.saturating_sub(self.live)
+    }
+}
+
+/// How much of a DAO's stake sits on target validators and how much was left elsewhere.
+#[cw_serde]
+pub struct DaoGap {
+    pub dao: String,
+    pub on_target: Uint128,
+    pub off_target: Uint128,
+    pub off_target_validators: Vec<String>,
+}
+
+#[cw_serde]
+pub struct ReconcileReport {
+    pub height: u64,
+    pub total_target: Uint128,
+    pub total_live: Uint128,
+    pub aligned_validators: usize,
+    pub validator_gaps: Vec<ValidatorGap>,
+    pub daos: Vec<DaoGap>,
+}
+
+impl ReconcileReport {
+    pub fn is_aligned(&self) -> bool {
+        self.validator_gaps.is_empty() && self.daos.iter().all(|d| d.off_target.is_zero())
+    }
+}
+
+/// Compares the DAO delegations queried after execution with the per-validator `targets`.
+pub fn reconcile(
+    height: u64,
+    live: &[Delegation],
+    targets: &[Delegation],
+    dao_addrs: &[String],
+) -> ReconcileReport {
+    let mut target_by_val: BTreeMap<&str, Uint128> = BTreeMap::new();
+    for target in targets {
+        *target_by_val.entry(&target.operator_addr).or_default() += target.amount;
+    }
+    let mut live_by_val: BTreeMap<&str, Uint128> = BTreeMap::new();
+    for del in live {
+        *live_by_val.entry(&del.operator_addr).or_default() += del.amount;
+    }
+
+    let mut validators: Vec<&str> = target_by_val
+        .keys()
+        .chain(live_by_val.keys())
+        .copied()
+        .collect();
+    validators.sort();
+    validators.dedup();
+
+    let mut aligned_validators = 0;
+    let mut validator_gaps = Vec::new();
+    for operator in validators {
+        let target = target_by_val.get(operator).copied().unwrap_or_default();
+        let live = live_by_val.get(operator).copied().unwrap_or_default();
+        if target == live {
+            aligned_validators += 1;
+        } else {
+            validator_gaps.push(ValidatorGap {
+                operator_addr: operator.to_string(),
+                target,
+                live,
+            });
+        }
+    }
+    // largest gaps first
+    validator_gaps.sort_by_key(|gap| std::cmp::Reverse(gap.surplus().max(gap.deficit())));
+
+    let daos = dao_addrs
+        .iter()
+        .map(|dao| {
+            let mut gap = DaoGap {
+                dao: dao.clone(),
+                on_target: Uint128::zero(),
+                off_target: Uint128::zero(),
+                off_target_validators: Vec::new(),
+            };
+            for del in live.iter().filter(|d| &d.del_addr == dao) {
+                if target_by_val.contains_key(del.operator_addr.as_str()) {
+                    gap.on_target += del.amount;
+                } else if !del.amount.is_zero() {
+                    gap.off_target += del.amount;
+                    gap.off_target_validators.push(del.operator_addr.clone());
+                }
+            }
+            gap
+        })
+        .collect();
+
+    ReconcileReport {
+        height,
+        total_target: target_by_val.values().copied().sum(),
+        total_live: live_by_val.values().copied().sum(),
+        aligned_validators,
+        validator_gaps,
+        daos,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::del;
+
+    #[test]
+    fn test_fully_executed_plan_is_aligned() {
+        let targets = vec![del("", "valA", 100), del("", "valB", 50)];
+        let live = vec![
+            del("dao1", "valA", 60),
+            del("dao2", "valA", 40),
+            del("dao2", "valB", 50),
+        ];
+        let report = reconcile(7, &live, &targets, &["dao1".into(), "dao2".into()]);
+
+        assert!(report.is_aligned(), "{:?}", report);
+        assert_eq!(report.aligned_validators, 2);
+        assert_eq!(report.total_live, report.total_target);
+    }
+
+    #[test]
+    fn test_residual_gaps_are_listed_per_validator_and_dao() {
+        let targets = vec![del("", "valA", 100), del("", "valB", 50)];
+        // a failed bundle left stake behind on valC, valB is short
+        let live = vec![
+            del("dao1", "valA", 100),
+            del("dao1", "valB", 20),
+            del("dao2", "valC", 30),
+        ];
+        let report = reconcile(7, &live, &targets, &["dao1".into(), "dao2".into()]);
+
+        assert!(!report.is_aligned());
+        assert_eq!(report.aligned_validators, 1);
+        assert_eq!(
+            report.validator_gaps,
+            vec![
+                ValidatorGap {
+                    operator_addr: "valB".to_string(),
+                    target: Uint128::new(50),
+                    live: Uint128::new(20),
+                },
+                ValidatorGap {
+                    operator_addr: "valC".to_string(),
+                    target: Uint128::zero(),
+                    live: Uint128::new(30),
+                },
+            ]
+        );
+        assert_eq!(report.daos[0].off_target, Uint128::zero());
+        assert_eq!(report.daos[1].off_target, Uint128::new(30));
+        assert_eq!(report.daos[1].off_target_validators, vec!["valC"]);
+    }
+}
@@ -0,0 +1,501 @@
+use std::{collections::BTreeMap, fmt::Write, str::FromStr};
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{HexBinary, Int128, Uint128};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    chain::ChainProfile,
+    plan::MessageExport,
+    snapshot::{ChainSnapshot, Delegation, ValidatorClass},
+    treasury::{DaoTreasury, TreasuryReport},
+};
+
+/// Epoch report of a planning run, re-rendered with the fees once the plan is packed to broadcast.
+pub const REPORT_JSON: &str = "delegation_report.json";
+/// The report for the proposal description and the website's `content/` pages.
+pub const REPORT_MD: &str = "delegation_report.md";
+/// The report as a single HTML page without external assets.
+pub const REPORT_HTML: &str = "delegation_report.html";
+
+/// Hex sha256 of the plan as written to `delegation_messages.json`, so a reviewer can check
+/// the file they were handed is the one reported on.
+pub fn plan_hash(export: &MessageExport) -> anyhow::Result<String> {
+    let json = serde_json::to_string_pretty(export)?;
+    Ok(HexBinary::from(Sha256::digest(json.as_bytes()).as_slice()).to_hex())
+}
+
+/// The DAOs' stake on one validator before and after the plan.
+#[cw_serde]
+pub struct ValidatorRow {
+    pub operator_addr: String,
+    /// DAO stake at the snapshot height.
+    pub current: Uint128,
+    pub target: Uint128,
+    /// `target - current`.
+    pub delta: Int128,
+    /// DAO stake once the plan executed.
+    pub planned: Uint128,
+    /// `added`, `removed`, `increased`, `decreased` or `unchanged`.
+    pub status: String,
+    /// Why the validator is where the plan leaves it, empty when it simply meets its target.
+    pub reason: String,
+}
+
+/// Messages and amount of one plan section.
+#[cw_serde]
+pub struct SectionSummary {
+    pub section: String,
+    pub count: usize,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct EpochReport {
+    pub height: u64,
+    pub chain_id: String,
+    pub plan_hash: String,
+    pub daos: Vec<String>,
+    pub validators: Vec<ValidatorRow>,
+    pub dao_totals: Vec<DaoTreasury>,
+    pub messages: Vec<SectionSummary>,
+    /// Bundles the plan is broadcast in, only packed by gas once broadcasting.
+    pub bundles: usize,
+    /// Fee of all bundles, known once they were simulated for broadcasting.
+    pub fee: Option<Uint128>,
+}
+
+impl EpochReport {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read report {}: {}", path, e))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+/// Reports the plan computed from `snapshot` towards `targets`. Validators under private
+/// agreements in `omitted` are left out, as the plan leaves them alone.
+#[allow(clippy::too_many_arguments)]
+pub fn epoch_report(
+    chain: &ChainProfile,
+    snapshot: &ChainSnapshot,
+    export: &MessageExport,
+    targets: &[Delegation],
+    treasury: &TreasuryReport,
+    dao_addrs: &[String],
+    omitted: &[&str],
+    bundles: usize,
+) -> anyhow::Result<EpochReport> {
+    let msgs: Vec<_> = export.entries().into_iter().map(|e| e.msg).collect();
+    let mut projected = snapshot.clone();
+    projected.apply(&msgs)?;
+
+    let mut current: BTreeMap<&str, Uint128> = BTreeMap::new();
+    for del in &snapshot.delegations {
+        if dao_addrs.contains(&del.del_addr) && !omitted.contains(&del.operator_addr.as_str()) {
+            *current.entry(&del.operator_addr).or_default() += del.amount;
+        }
+    }
+    let mut target: BTreeMap<&str, Uint128> = BTreeMap::new();
+    for t in targets {
+        *target.entry(&t.operator_addr).or_default() += t.amount;
+    }
+
+    let mut operators: Vec<&str> = current.keys().chain(target.keys()).copied().collect();
+    operators.sort();
+    operators.dedup();
+    let mut validators = Vec::new();
+    for operator in operators {
+        let current = current.get(operator).copied().unwrap_or_default();
+        let target = target.get(operator).copied().unwrap_or_default();
+        let planned: Uint128 = dao_addrs
+            .iter()
+            .map(|dao| projected.delegation(dao, operator))
+            .sum();
+        let status = if current == target {
+            "unchanged"
+        } else if current.is_zero() {
+            "added"
+        } else if target.is_zero() {
+            "removed"
+        } else if target > current {
+            "increased"
+        } else {
+            "decreased"
+        };
+
+        let mut reasons = Vec::new();
+        let class = snapshot.validator(operator).map(|v| v.class());
+        if target.is_zero() {
+            reasons.push(match class {
+                None => "unknown".to_string(),
+                Some(ValidatorClass::Bonded) => "no-obligation-this-round".to_string(),
+                Some(class) => class.to_string(),
+            });
+        } else if class != Some(ValidatorClass::Bonded) {
+            reasons.push(class.map_or("unknown".to_string(), |c| c.to_string()));
+        }
+        if planned != target {
+            reasons.push(format!(
+                "plan leaves {}{} off target",
+                planned.abs_diff(target),
+                chain.denom
+            ));
+        }
+
+        validators.push(ValidatorRow {
+            operator_addr: operator.to_string(),
+            current,
+            target,
+            delta: Int128::try_from(target)? - Int128::try_from(current)?,
+            planned,
+            status: status.to_string(),
+            reason: reasons.join(", "),
+        });
+    }
+
+    let mut messages: Vec<SectionSummary> = Vec::new();
+    for entry in export.entries() {
+        let section = entry.id.section.to_string();
+        let amount = Uint128::from_str(entry.msg.amount())?;
+        match messages.iter_mut().find(|s| s.section == section) {
+            Some(summary) => {
+                summary.count += 1;
+                summary.amount += amount;
+            }
+            None => messages.push(SectionSummary {
+                section,
+                count: 1,
+                amount,
+            }),
+        }
+    }
+
+    Ok(EpochReport {
+        height: snapshot.height,
+        chain_id: chain.chain_id.clone(),
+        plan_hash: plan_hash(export)?,
+        daos: dao_addrs.to_vec(),
+        validators,
+        dao_totals: treasury.daos.clone(),
+        messages,
+        bundles,
+        fee: None,
+    })
+}
+
+/// `+1.5` or `-1.5`, `0` for no change.
+pub(crate) fn signed(chain: &ChainProfile, delta: Int128) -> String {
+    let sign = match delta.i128().signum() {
+        1 => "+",
+        -1 => "-",
+        _ => "",
+    };
+    format!("{}{}", sign, chain.display_amount(delta.unsigned_abs()))
+}
+
+/// Header, message and validator rows shared by the Markdown and HTML renderings, amounts in
+/// the chain's display denom.
+struct Tables {
+    facts: Vec<(String, String)>,
+    validators: Vec<Vec<String>>,
+    daos: Vec<Vec<String>>,
+    messages: Vec<Vec<String>>,
+}
+
+/// Title, header and rows of one table.
+type Section<'a> = (&'static str, &'static [&'static str], &'a [Vec<String>]);
+
+impl Tables {
+    fn sections(&self) -> [Section<'_>; 3] {
+        [
+            ("Validators", &VALIDATOR_HEADER, &self.validators),
+            ("DAOs", &DAO_HEADER, &self.daos),
+            ("Messages", &MESSAGE_HEADER, &self.messages),
+        ]
+    }
+}
+
+const VALIDATOR_HEADER: [&str; 7] = [
+    "validator",
+    "current",
+    "target",
+    "delta",
+    "planned",
+    "status",
+    "reason",
+];
+const DAO_HEADER: [&str; 6] = [
+    "DAO",
+    "liquid",
+    "liquid after",
+    "delegated",
+    "unbonding",
+    "obligation",
+];
+const MESSAGE_HEADER: [&str; 3] = ["section", "messages", "amount"];
+
+impl EpochReport {
+    fn tables(&self, chain: &ChainProfile) -> Tables {
+        let amount = |a| chain.display_amount(a);
+        let fee = match self.fee {
+            Some(fee) => format!("{} {}", amount(fee), chain.display_denom),
+            None => "simulated when broadcasting".to_string(),
+        };
+        let facts = vec![
+            ("Chain".to_string(), self.chain_id.clone()),
+            ("Height".to_string(), self.height.to_string()),
+            ("Plan sha256".to_string(), self.plan_hash.clone()),
+            ("DAOs".to_string(), self.daos.join(", ")),
+            ("Bundles".to_string(), self.bundles.to_string()),
+            ("Fee".to_string(), fee),
+        ];
+        let validators = self
+            .validators
+            .iter()
+            .map(|v| {
+                vec![
+                    v.operator_addr.clone(),
+                    amount(v.current),
+                    amount(v.target),
+                    signed(chain, v.delta),
+                    amount(v.planned),
+                    v.status.clone(),
+                    v.reason.clone(),
+                ]
+            })
+            .collect();
+        let daos = self
+            .dao_totals
+            .iter()
+            .map(|d| {
+                vec![
+                    d.dao.clone(),
+                    amount(d.liquid),
+                    amount(d.projected_liquid),
+                    amount(d.delegated),
+                    amount(d.unbonding),
+                    amount(d.obligation),
+                ]
+            })
+            .collect();
+        let mut messages: Vec<Vec<String>> = self
+            .messages
+            .iter()
+            .map(|s| vec![s.section.clone(), s.count.to_string(), amount(s.amount)])
+            .collect();
+        messages.push(vec![
+            "total".to_string(),
+            self.messages
+                .iter()
+                .map(|s| s.count)
+                .sum::<usize>()
+                .to_string(),
+            amount(self.messages.iter().map(|s| s.amount).sum()),
+        ]);
+        Tables {
+            facts,
+            validators,
+            daos,
+            messages,
+        }
+    }
+
+    /// The report as Markdown, for the proposal description and the website.
+    pub fn markdown(&self, chain: &ChainProfile) -> String {
+        let tables = self.tables(chain);
+        let mut md = String::new();
+        let _ = writeln!(md, "# Delegation realignment at height {}\n", self.height);
+        for (name, value) in &tables.facts {
+            let _ = writeln!(md, "- **{}**: {}", name, value);
+        }
+        for (title, header, rows) in tables.sections() {
+            let _ = writeln!(md, "\n## {}\n", title);
+            let _ = writeln!(md, "| {} |", header.join(" | "));
+            let _ = writeln!(md, "|{}", "---|".repeat(header.len()));
+            for row in rows {
+                let cells: Vec<String> = row.iter().map(|c| c.replace('|', "\\|")).collect();
+                let _ = writeln!(md, "| {} |", cells.join(" | "));
+            }
+        }
+        let _ = writeln!(md, "\nAmounts in {}.", chain.display_denom);
+        md
+    }
+
+    /// The report as a single HTML page with its styles inlined.
+    pub fn html(&self, chain: &ChainProfile) -> String {
+        let tables = self.tables(chain);
+        let title = format!("Delegation realignment at height {}", self.height);
+        let mut html = String::new();
+        let _ = writeln!(
+            html,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<h1>{}</h1>\n<dl>",
+            escape(&title),
+            STYLE,
+            escape(&title)
+        );
+        for (name, value) in &tables.facts {
+            let _ = writeln!(html, "<dt>{}</dt><dd>{}</dd>", escape(name), escape(value));
+        }
+        let _ = writeln!(html, "</dl>");
+        for (title, header, rows) in tables.sections() {
+            let _ = writeln!(html, "<h2>{}</h2>\n<table>\n<tr>", title);
+            for cell in header {
+                let _ = write!(html, "<th>{}</th>", escape(cell));
+            }
+            let _ = writeln!(html, "</tr>");
+            for row in rows {
+                let _ = write!(html, "<tr>");
+                for cell in row {
+                    let _ = write!(html, "<td>{}</td>", escape(cell));
+                }
+                let _ = writeln!(html, "</tr>");
+            }
+            let _ = writeln!(html, "</table>");
+        }
+        let _ = writeln!(
+            html,
+            "<p>Amounts in {}.</p>\n</body>\n</html>",
+            escape(&chain.display_denom)
+        );
+        html
+    }
+}
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+dt { font-weight: bold; float: left; clear: left; width: 8em; }
+dd { margin-left: 9em; font-family: monospace; word-break: break-all; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; }
+td { font-family: monospace; }
+";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{del, validator},
+        plan::{Delegations, RedelegateMsg, Redelegations, Undelegations},
+        treasury::treasury_report,
+    };
+
+    /// dao1 moves its 3_000_000 on the jailed valC onto valA, valB stays short of its target.
+    fn report() -> anyhow::Result<EpochReport> {
+        let chain = ChainProfile::bitsong_mainnet();
+        let mut export = MessageExport {
+            height: 7,
+            withdrawals: Default::default(),
+            sends: Default::default(),
+            redelegations: Redelegations {
+                data: vec![RedelegateMsg {
+                    delegator_address: "dao1".to_string(),
+                    validator_src_address: "valC".to_string(),
+                    validator_dst_address: "valA".to_string(),
+                    amount: "3000000".to_string(),
+                    denom: "ubtsg".to_string(),
+                }],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            delegations: Delegations::default(),
+            undelegates: Undelegations::default(),
+        };
+        export.recompute_totals()?;
+        let snapshot = ChainSnapshot {
+            height: 7,
+            delegations: vec![
+                del("dao1", "valA", 1_000_000),
+                del("dao1", "valB", 1_000_000),
+                del("dao1", "valC", 3_000_000),
+                del("dao1", "private", 9_000_000),
+            ],
+            validators: vec![
+                validator("valA", false),
+                validator("valB", false),
+                validator("valC", true),
+            ],
+            ..Default::default()
+        };
+        let targets = [del("", "valA", 4_000_000), del("", "valB", 2_000_000)];
+        let daos = ["dao1".to_string()];
+        let treasury = treasury_report(&snapshot, &export, &targets, &daos, &["private"])?;
+        epoch_report(
+            &chain,
+            &snapshot,
+            &export,
+            &targets,
+            &treasury,
+            &daos,
+            &["private"],
+            1,
+        )
+    }
+
+    #[test]
+    fn test_validators_are_reported_with_their_status_and_reason() -> anyhow::Result<()> {
+        let report = report()?;
+        let rows: Vec<_> = report
+            .validators
+            .iter()
+            .map(|v| {
+                (
+                    v.operator_addr.as_str(),
+                    v.delta.i128(),
+                    v.status.as_str(),
+                    v.reason.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("valA", 3_000_000, "increased", ""),
+                (
+                    "valB",
+                    1_000_000,
+                    "increased",
+                    "plan leaves 1000000ubtsg off target"
+                ),
+                ("valC", -3_000_000, "removed", "jailed"),
+            ]
+        );
+        assert_eq!(
+            report.messages,
+            [SectionSummary {
+                section: "redelegations".to_string(),
+                count: 1,
+                amount: Uint128::new(3_000_000),
+            }]
+        );
+        assert_eq!(report.plan_hash.len(), 64);
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_renders_as_markdown_and_html() -> anyhow::Result<()> {
+        let chain = ChainProfile::bitsong_mainnet();
+        let mut report = report()?;
+        report.fee = Some(Uint128::new(12_500));
+
+        let md = report.markdown(&chain);
+        assert!(
+            md.contains("| valC | 3 | 0 | -3 | 0 | removed | jailed |"),
+            "{}",
+            md
+        );
+        assert!(md.contains("- **Fee**: 0.0125 BTSG"), "{}", md);
+
+        report.validators[0].reason = "<script>".to_string();
+        let html = report.html(&chain);
+        assert!(html.contains("<td>&lt;script&gt;</td>"));
+        assert!(!html.contains("<script>"));
+        Ok(())
+    }
+}
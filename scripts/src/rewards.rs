@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+
+use cosmwasm_std::Uint128;
+
+use crate::{
+    plan::{MessageExport, PlanMsg, WithdrawRewardsMsg},
+    snapshot::PendingReward,
+};
+
+/// Validators whose rewards `msg` withdraws to its delegator. Besides withdrawing them
+/// explicitly, the distribution module withdraws a delegation's rewards whenever its shares
+/// change: on both sides of a redelegation, on delegating to and undelegating from it.
+pub fn withdrawn_from(msg: &PlanMsg) -> Vec<&str> {
+    match msg {
+        PlanMsg::WithdrawRewards(m) => vec![&m.validator_address],
+        PlanMsg::Redelegate(m) => vec![&m.validator_src_address, &m.validator_dst_address],
+        PlanMsg::Delegate(m) => vec![&m.validator_address],
+        PlanMsg::Undelegate(m) => vec![&m.validator_address],
+        PlanMsg::Send(_) => vec![],
+    }
+}
+
+/// Rewards pending per (delegator, validator), drained as messages withdraw them.
+#[derive(Clone, Debug, Default)]
+pub struct RewardLedger {
+    pending: BTreeMap<(String, String), Uint128>,
+}
+
+impl RewardLedger {
+    pub fn new(rewards: &[PendingReward]) -> Self {
+        let mut pending: BTreeMap<(String, String), Uint128> = BTreeMap::new();
+        for reward in rewards {
+            *pending
+                .entry((reward.delegator.clone(), reward.validator.clone()))
+                .or_default() += reward.amount;
+        }
+        RewardLedger { pending }
+    }
+
+    /// Rewards `msg` moves to its delegator's liquid balance. A delegation's rewards are only
+    /// withdrawn once, rewards accruing until it executes are not projected.
+    pub fn withdraw(&mut self, msg: &PlanMsg) -> Uint128 {
+        withdrawn_from(msg)
+            .into_iter()
+            .filter_map(|validator| {
+                self.pending
+                    .remove(&(msg.delegator().to_string(), validator.to_string()))
+            })
+            .sum()
+    }
+
+    /// Rewards no message withdrew.
+    pub fn into_rewards(self) -> Vec<PendingReward> {
+        self.pending
+            .into_iter()
+            .filter(|(_, amount)| !amount.is_zero())
+            .map(|((delegator, validator), amount)| PendingReward {
+                delegator,
+                validator,
+                amount,
+            })
+            .collect()
+    }
+}
+
+/// Rewards each delegator gets withdrawn by `msgs`, explicitly or along with its stake.
+pub fn withdrawn_rewards(rewards: &[PendingReward], msgs: &[PlanMsg]) -> BTreeMap<String, Uint128> {
+    let mut ledger = RewardLedger::new(rewards);
+    let mut withdrawn: BTreeMap<String, Uint128> = BTreeMap::new();
+    for msg in msgs {
+        let amount = ledger.withdraw(msg);
+        if !amount.is_zero() {
+            *withdrawn.entry(msg.delegator().to_string()).or_default() += amount;
+        }
+    }
+    withdrawn
+}
+
+/// Replaces the plan's withdrawals with one for every delegation its stake messages withdraw
+/// rewards from, so they show up in the plan instead of as drift of the liquid balance.
+pub fn prepend_withdrawals(
+    export: &mut MessageExport,
+    rewards: &[PendingReward],
+    denom: &str,
+) -> anyhow::Result<()> {
+    export.withdrawals.data.clear();
+    let mut ledger = RewardLedger::new(rewards);
+    let mut withdrawals = Vec::new();
+    for entry in export.entries() {
+        for validator in withdrawn_from(&entry.msg) {
+            let key = (entry.msg.delegator().to_string(), validator.to_string());
+            match ledger.pending.remove(&key) {
+                Some(amount) if !amount.is_zero() => withdrawals.push(WithdrawRewardsMsg {
+                    delegator_address: key.0,
+                    validator_address: key.1,
+                    amount: amount.to_string(),
+                    denom: denom.to_string(),
+                }),
+                _ => {}
+            }
+        }
+    }
+    export.withdrawals.data = withdrawals;
+    export.recompute_totals()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::{
+        DelegateMsg, Delegations, PlanEntryId, PlanSection, RedelegateMsg, Redelegations,
+        UndelegateMsg, Undelegations,
+    };
+
+    fn reward(dao: &str, val: &str, amount: u128) -> PendingReward {
+        PendingReward {
+            delegator: dao.to_string(),
+            validator: val.to_string(),
+            amount: Uint128::new(amount),
+        }
+    }
+
+    fn export() -> MessageExport {
+        let mut export = MessageExport {
+            height: 1,
+            withdrawals: Default::default(),
+            sends: Default::default(),
+            redelegations: Redelegations {
+                data: vec![RedelegateMsg {
+                    delegator_address: "dao1".to_string(),
+                    validator_src_address: "valA".to_string(),
+                    validator_dst_address: "valB".to_string(),
+                    amount: "40".to_string(),
+                    denom: "ubtsg".to_string(),
+                }],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            delegations: Delegations {
+                data: vec![DelegateMsg {
+                    delegator_address: "dao1".to_string(),
+                    validator_address: "valB".to_string(),
+                    amount: "5".to_string(),
+                    denom: "ubtsg".to_string(),
+                }],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            undelegates: Undelegations {
+                data: vec![UndelegateMsg {
+                    delegator_address: "dao2".to_string(),
+                    validator_address: "valA".to_string(),
+                    amount: "10".to_string(),
+                    denom: "ubtsg".to_string(),
+                }],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+        };
+        export.recompute_totals().unwrap();
+        export
+    }
+
+    #[test]
+    fn test_rewards_are_withdrawn_once_per_delegation_touched() {
+        let rewards = [
+            reward("dao1", "valA", 3),
+            reward("dao1", "valB", 2),
+            reward("dao1", "valC", 100),
+            reward("dao2", "valA", 7),
+        ];
+        let msgs: Vec<PlanMsg> = export().entries().into_iter().map(|e| e.msg).collect();
+
+        // valB's rewards go with the redelegation, not again with the delegation
+        let withdrawn = withdrawn_rewards(&rewards, &msgs);
+        assert_eq!(
+            withdrawn,
+            BTreeMap::from([
+                ("dao1".to_string(), Uint128::new(5)),
+                ("dao2".to_string(), Uint128::new(7)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_withdrawals_are_prepended_for_touched_delegations() -> anyhow::Result<()> {
+        let rewards = [
+            reward("dao1", "valA", 3),
+            reward("dao1", "valC", 100),
+            reward("dao2", "valA", 7),
+        ];
+        let mut export = export();
+        prepend_withdrawals(&mut export, &rewards, "ubtsg")?;
+
+        let pairs: Vec<_> = export
+            .withdrawals
+            .data
+            .iter()
+            .map(|w| (w.delegator_address.as_str(), w.validator_address.as_str()))
+            .collect();
+        assert_eq!(pairs, [("dao1", "valA"), ("dao2", "valA")]);
+        assert_eq!(export.withdrawals.total_ubtsg, Uint128::new(10));
+        assert_eq!(
+            export.entries()[0].id,
+            PlanEntryId {
+                section: PlanSection::Withdrawal,
+                index: 0
+            }
+        );
+
+        // planning again does not withdraw twice
+        prepend_withdrawals(&mut export, &rewards, "ubtsg")?;
+        assert_eq!(export.withdrawals.count, 2);
+        Ok(())
+    }
+}
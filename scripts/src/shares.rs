@@ -0,0 +1,439 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Decimal256, Uint128, Uint256};
+
+use crate::{
+    plan::{MessageExport, PlanEntryId, PlanMsg, PlanSection},
+    snapshot::{ChainSnapshot, DelegatorShares, ValidatorState},
+};
+
+const ATOMICS_PER_UNIT: Uint256 = Uint256::from_u128(1_000_000_000_000_000_000);
+
+/// A validator's tokens per delegator share, which drops below 1 when it is slashed. Amounts are
+/// converted like the staking module does, on the 18 decimal atomics of the shares.
+#[derive(Clone, Copy, Debug)]
+pub struct ExchangeRate {
+    tokens: Uint256,
+    shares: Uint256,
+}
+
+impl ExchangeRate {
+    /// `None` for validators without stake, or from snapshots that predate shares.
+    pub fn of(validator: &ValidatorState) -> Option<Self> {
+        if validator.tokens.is_zero() || validator.delegator_shares.is_zero() {
+            return None;
+        }
+        Some(ExchangeRate {
+            tokens: validator.tokens.into(),
+            shares: validator.delegator_shares.atomics(),
+        })
+    }
+
+    /// Shares moving `amount` takes, rounded half up (`SharesFromTokens`).
+    pub fn shares(&self, amount: Uint128) -> Decimal256 {
+        let doubled = Uint256::from(amount) * self.shares * Uint256::from(2u8);
+        Decimal256::new((doubled + self.tokens) / (self.tokens * Uint256::from(2u8)))
+    }
+
+    /// Tokens `shares` are worth as the node reports the delegation balance: rounded half up
+    /// to 18 decimals, then down. Within 0.5e-18 below a whole token it rounds up to an amount
+    /// the shares cannot move.
+    pub fn tokens(&self, shares: Decimal256) -> Uint128 {
+        let atomics = shares.atomics() * self.tokens * Uint256::from(2u8) * ATOMICS_PER_UNIT;
+        let rounded = (atomics / self.shares + Uint256::one()) / Uint256::from(2u8);
+        Uint128::try_from(rounded / ATOMICS_PER_UNIT).unwrap_or(Uint128::MAX)
+    }
+
+    /// Largest amount a delegation of `shares` can move. The staking module refuses amounts
+    /// whose shares, rounded down, exceed the delegation's, and caps the rest at all its shares.
+    pub fn max_movable(&self, shares: Decimal256) -> Uint128 {
+        let bound = (shares.atomics() + Uint256::one()) * self.tokens - Uint256::one();
+        Uint128::try_from(bound / self.shares).unwrap_or(Uint128::MAX)
+    }
+}
+
+/// Shares left of each recorded delegation while a plan moves stake in and out of it.
+#[derive(Clone, Debug, Default)]
+pub struct ShareLedger {
+    rates: BTreeMap<String, ExchangeRate>,
+    shares: BTreeMap<(String, String), Decimal256>,
+}
+
+impl ShareLedger {
+    pub fn new(snapshot: &ChainSnapshot) -> Self {
+        ShareLedger {
+            rates: snapshot
+                .validators
+                .iter()
+                .filter_map(|v| Some((v.operator_address.clone(), ExchangeRate::of(v)?)))
+                .collect(),
+            shares: snapshot
+                .shares
+                .iter()
+                .map(|s| ((s.delegator.clone(), s.validator.clone()), s.shares))
+                .collect(),
+        }
+    }
+
+    /// Takes `amount` out of the delegation and tells whether that took all of its shares, as the
+    /// staking module does for the largest amount they can move. The delegation is then gone,
+    /// whatever its reported balance was. `false` if its shares or validator rate are not recorded.
+    pub fn take(&mut self, delegator: &str, validator: &str, amount: Uint128) -> bool {
+        let key = (delegator.to_string(), validator.to_string());
+        let (Some(rate), Some(shares)) = (self.rates.get(validator), self.shares.get_mut(&key))
+        else {
+            return false;
+        };
+        if amount == rate.max_movable(*shares) {
+            *shares = Decimal256::zero();
+            return true;
+        }
+        *shares = shares.saturating_sub(rate.shares(amount));
+        false
+    }
+
+    /// Adds the shares `amount` buys to the delegation, if the validator rate is recorded.
+    pub fn give(&mut self, delegator: &str, validator: &str, amount: Uint128) {
+        if let Some(rate) = self.rates.get(validator) {
+            *self
+                .shares
+                .entry((delegator.to_string(), validator.to_string()))
+                .or_default() += rate.shares(amount);
+        }
+    }
+
+    pub fn into_shares(self) -> Vec<DelegatorShares> {
+        self.shares
+            .into_iter()
+            .filter(|(_, shares)| !shares.is_zero())
+            .map(|((delegator, validator), shares)| DelegatorShares {
+                delegator,
+                validator,
+                shares,
+            })
+            .collect()
+    }
+}
+
+/// Reported stake a full-shares move leaves behind on its source, where no shares back it. The
+/// move empties the source, and its destination or the unbonding gets that much less than the
+/// reported balance.
+#[cw_serde]
+pub struct ShareDust {
+    pub entry: PlanEntryId,
+    pub delegator: String,
+    pub validator: String,
+    /// Destination of a redelegation, `None` for an undelegation.
+    pub destination: Option<String>,
+    pub amount: Uint128,
+}
+
+/// Replays the plan in broadcast order and returns the dust every full-shares move leaves.
+pub fn share_dust(
+    export: &MessageExport,
+    snapshot: &ChainSnapshot,
+) -> anyhow::Result<Vec<ShareDust>> {
+    let mut ledger = ShareLedger::new(snapshot);
+    let mut staked: BTreeMap<(String, String), Uint128> = BTreeMap::new();
+    for del in &snapshot.delegations {
+        *staked
+            .entry((del.del_addr.clone(), del.operator_addr.clone()))
+            .or_default() += del.amount;
+    }
+
+    let mut dust = Vec::new();
+    for entry in export.entries() {
+        let amount = Uint128::from_str(entry.msg.amount())?;
+        let delegator = entry.msg.delegator().to_string();
+        let (src, dst) = match &entry.msg {
+            PlanMsg::WithdrawRewards(_) | PlanMsg::Send(_) => continue,
+            PlanMsg::Redelegate(m) => (
+                Some(&m.validator_src_address),
+                Some(&m.validator_dst_address),
+            ),
+            PlanMsg::Delegate(m) => (None, Some(&m.validator_address)),
+            PlanMsg::Undelegate(m) => (Some(&m.validator_address), None),
+        };
+        if let Some(src) = src {
+            let balance = staked.entry((delegator.clone(), src.clone())).or_default();
+            let left = balance.saturating_sub(amount);
+            *balance = left;
+            if ledger.take(&delegator, src, amount) && !left.is_zero() {
+                *balance = Uint128::zero();
+                dust.push(ShareDust {
+                    entry: entry.id,
+                    delegator: delegator.clone(),
+                    validator: src.clone(),
+                    destination: dst.cloned(),
+                    amount: left,
+                });
+            }
+        }
+        if let Some(dst) = dst {
+            *staked.entry((delegator.clone(), dst.clone())).or_default() += amount;
+            ledger.give(&delegator, dst, amount);
+        }
+    }
+    Ok(dust)
+}
+
+/// An undelegation or redelegation whose amount was lowered to what its shares can move.
+#[cw_serde]
+pub struct ShareAdjustment {
+    pub entry: PlanEntryId,
+    pub delegator: String,
+    pub validator: String,
+    pub planned: Uint128,
+    pub adjusted: Uint128,
+}
+
+/// Fits every amount moved out of a delegation to the shares left of it, in broadcast order, so
+/// rounding never asks for more shares than there are. Moving the rest of a delegation becomes
+/// the largest amount its remaining shares allow, which the staking module caps at exactly all
+/// of them. Delegations whose shares or validator rate the snapshot lacks are left as planned.
+pub fn fit_to_shares(
+    export: &mut MessageExport,
+    snapshot: &ChainSnapshot,
+) -> anyhow::Result<Vec<ShareAdjustment>> {
+    let mut remaining: BTreeMap<(String, String), Decimal256> = snapshot
+        .shares
+        .iter()
+        .map(|s| ((s.delegator.clone(), s.validator.clone()), s.shares))
+        .collect();
+
+    let outflows = export
+        .redelegations
+        .data
+        .iter_mut()
+        .enumerate()
+        .map(|(i, m)| {
+            (
+                PlanSection::Redelegation,
+                i,
+                &m.delegator_address,
+                &m.validator_src_address,
+                &mut m.amount,
+            )
+        })
+        .chain(
+            export
+                .undelegates
+                .data
+                .iter_mut()
+                .enumerate()
+                .map(|(i, m)| {
+                    (
+                        PlanSection::Undelegation,
+                        i,
+                        &m.delegator_address,
+                        &m.validator_address,
+                        &mut m.amount,
+                    )
+                }),
+        );
+
+    let mut adjustments = Vec::new();
+    for (section, index, delegator, validator, amount) in outflows {
+        let Some(rate) = snapshot.validator(validator).and_then(ExchangeRate::of) else {
+            continue;
+        };
+        let Some(shares) = remaining.get_mut(&(delegator.clone(), validator.clone())) else {
+            continue;
+        };
+
+        let planned: Uint128 = amount.parse()?;
+        let adjusted = planned.min(rate.max_movable(*shares));
+        anyhow::ensure!(
+            !adjusted.is_zero(),
+            "{} has no shares of {} left to move {}",
+            delegator,
+            validator,
+            planned
+        );
+        *shares = shares.saturating_sub(rate.shares(adjusted));
+
+        if adjusted != planned {
+            *amount = adjusted.to_string();
+            adjustments.push(ShareAdjustment {
+                entry: PlanEntryId { section, index },
+                delegator: delegator.clone(),
+                validator: validator.clone(),
+                planned,
+                adjusted,
+            });
+        }
+    }
+    export.recompute_totals()?;
+    Ok(adjustments)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::{
+        plan::{Delegations, RedelegateMsg, Redelegations, UndelegateMsg, Undelegations},
+        snapshot::{BondStatus, Delegation},
+        verify::verify_plan,
+    };
+
+    /// A validator slashed by 70%: 3 tokens back 10 shares.
+    fn slashed() -> ValidatorState {
+        ValidatorState {
+            operator_address: "valA".to_string(),
+            status: BondStatus::Bonded,
+            jailed: false,
+            signing: None,
+            tokens: Uint128::new(3),
+            delegator_shares: Decimal256::from_str("10").unwrap(),
+        }
+    }
+
+    fn dec(value: &str) -> Decimal256 {
+        Decimal256::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn test_conversions_round_like_the_staking_module() {
+        let rate = ExchangeRate::of(&slashed()).unwrap();
+        assert_eq!(rate.shares(Uint128::new(1)), dec("3.333333333333333333"));
+        assert_eq!(rate.tokens(dec("10")), Uint128::new(3));
+        assert_eq!(rate.max_movable(dec("10")), Uint128::new(3));
+        // worth 1.9999999999999999995 tokens, reported as 2 but only 1 can be moved
+        let shares = dec("6.666666666666666665");
+        assert_eq!(rate.tokens(shares), Uint128::new(2));
+        assert_eq!(rate.max_movable(shares), Uint128::new(1));
+        assert!(ExchangeRate::of(&ValidatorState {
+            tokens: Uint128::zero(),
+            ..slashed()
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn test_moving_a_whole_delegation_is_fitted_to_its_shares() -> anyhow::Result<()> {
+        let mut export = MessageExport {
+            height: 1,
+            withdrawals: Default::default(),
+            sends: Default::default(),
+            redelegations: Redelegations {
+                data: vec![],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            delegations: Delegations {
+                data: vec![],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            undelegates: Undelegations {
+                data: vec![UndelegateMsg {
+                    delegator_address: "dao1".to_string(),
+                    validator_address: "valA".to_string(),
+                    amount: "2".to_string(),
+                    denom: "ubtsg".to_string(),
+                }],
+                count: 1,
+                total_ubtsg: Uint128::new(2),
+            },
+        };
+        let snapshot = ChainSnapshot {
+            height: 1,
+            delegations: vec![Delegation {
+                del_addr: "dao1".to_string(),
+                operator_addr: "valA".to_string(),
+                amount: Uint128::new(2),
+            }],
+            validators: vec![slashed()],
+            shares: vec![DelegatorShares {
+                delegator: "dao1".to_string(),
+                validator: "valA".to_string(),
+                shares: dec("6.666666666666666665"),
+            }],
+            ..Default::default()
+        };
+
+        // a plan without recorded shares is left alone
+        let mut unchanged = export.clone();
+        let adjustments = fit_to_shares(
+            &mut unchanged,
+            &ChainSnapshot {
+                shares: vec![],
+                ..snapshot.clone()
+            },
+        )?;
+        assert!(adjustments.is_empty());
+        assert_eq!(unchanged, export);
+
+        let adjustments = fit_to_shares(&mut export, &snapshot)?;
+        assert_eq!(adjustments.len(), 1);
+        assert_eq!(
+            adjustments[0].entry,
+            PlanEntryId {
+                section: PlanSection::Undelegation,
+                index: 0
+            }
+        );
+        assert_eq!(export.undelegates.data[0].amount, "1");
+        assert_eq!(export.undelegates.total_ubtsg, Uint128::new(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_adjusted_plan_passes_verification() -> anyhow::Result<()> {
+        // 2 reported on the slashed validator, of which the shares can only move 1
+        let snapshot = ChainSnapshot {
+            height: 1,
+            delegations: vec![Delegation {
+                del_addr: "dao1".to_string(),
+                operator_addr: "valA".to_string(),
+                amount: Uint128::new(2),
+            }],
+            validators: vec![slashed()],
+            shares: vec![DelegatorShares {
+                delegator: "dao1".to_string(),
+                validator: "valA".to_string(),
+                shares: dec("6.666666666666666665"),
+            }],
+            ..Default::default()
+        };
+        let mut export = MessageExport {
+            height: 1,
+            ..Default::default()
+        };
+        export.redelegations.data.push(RedelegateMsg {
+            delegator_address: "dao1".to_string(),
+            validator_src_address: "valA".to_string(),
+            validator_dst_address: "valB".to_string(),
+            amount: "2".to_string(),
+            denom: "ubtsg".to_string(),
+        });
+        export.recompute_totals()?;
+        let targets = vec![Delegation {
+            del_addr: String::new(),
+            operator_addr: "valB".to_string(),
+            amount: Uint128::new(2),
+        }];
+
+        assert_eq!(fit_to_shares(&mut export, &snapshot)?.len(), 1);
+        let report = verify_plan(&export, &snapshot, &targets, &[])?;
+        assert!(report.passed, "{:?}", report);
+        assert_eq!(report.verification.share_dust.len(), 1);
+        assert_eq!(report.verification.share_dust[0].amount, Uint128::new(1));
+
+        // the projection empties the source instead of leaving the remainder on it
+        let mut projected = snapshot.clone();
+        projected.apply(
+            &export
+                .entries()
+                .into_iter()
+                .map(|e| e.msg)
+                .collect::<Vec<_>>(),
+        )?;
+        assert_eq!(projected.delegation("dao1", "valA"), Uint128::zero());
+        assert_eq!(projected.delegation("dao1", "valB"), Uint128::new(1));
+        Ok(())
+    }
+}
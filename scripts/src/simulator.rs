@@ -0,0 +1,899 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use cosmos_sdk_proto::{cosmos::authz::v1beta1::MsgExec, prost::Message};
+use cosmrs::tx::Msg;
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Decimal256, Uint128};
+
+use crate::{
+    backend::{BankBackend, DistributionBackend, StakingBackend, WalletBackend},
+    broadcast::{TxOutcome, TxTracker},
+    bundle::GasEstimate,
+    snapshot::{
+        BondStatus, Delegation, DelegatorShares, PendingReward, SigningInfo, Unbonding,
+        ValidatorState,
+    },
+};
+
+/// Entries the staking module keeps per delegator/validator pair (`MaxEntries`).
+pub const MAX_ENTRIES: usize = 7;
+/// Gas charged for a tx, plus [`GAS_PER_MSG`] for each message it executes.
+pub const BASE_GAS: u64 = 80_000;
+pub const GAS_PER_MSG: u64 = 150_000;
+/// Blocks until unbonding and redelegation entries mature, about 21 days of 6s blocks.
+pub const UNBONDING_BLOCKS: u64 = 302_400;
+/// Simulated blocks are this far apart, starting at the unix epoch.
+pub const BLOCK_TIME_SECS: u64 = 6;
+
+#[cw_serde]
+pub struct UnbondingEntry {
+    pub delegator: String,
+    pub validator: String,
+    pub amount: Uint128,
+    pub completion_height: u64,
+}
+
+#[cw_serde]
+pub struct RedelegationEntry {
+    pub delegator: String,
+    pub src_validator: String,
+    pub dst_validator: String,
+    pub amount: Uint128,
+    pub completion_height: u64,
+}
+
+/// An sdk error as it ends up in a tx's codespace, code and raw log.
+struct SdkError {
+    codespace: &'static str,
+    code: u32,
+    log: String,
+}
+
+impl SdkError {
+    fn new(codespace: &'static str, code: u32, log: impl Into<String>) -> Self {
+        SdkError {
+            codespace,
+            code,
+            log: log.into(),
+        }
+    }
+}
+
+enum StakingOp {
+    Delegate {
+        validator: String,
+        amount: Uint128,
+    },
+    Undelegate {
+        validator: String,
+        amount: Uint128,
+    },
+    Redelegate {
+        src: String,
+        dst: String,
+        amount: Uint128,
+    },
+    WithdrawRewards {
+        validator: String,
+    },
+    Send {
+        to: String,
+        amount: Uint128,
+    },
+}
+
+#[derive(Clone, Default)]
+struct SimState {
+    height: u64,
+    balances: BTreeMap<String, Uint128>,
+    delegations: BTreeMap<(String, String), Uint128>,
+    validators: BTreeMap<String, ValidatorState>,
+    unbonding: Vec<UnbondingEntry>,
+    redelegations: Vec<RedelegationEntry>,
+    /// (granter, grantee) pairs with a staking authorization.
+    grants: BTreeSet<(String, String)>,
+    /// Pending rewards per (delegator, validator).
+    rewards: BTreeMap<(String, String), Uint128>,
+    txs: BTreeMap<String, TxOutcome>,
+}
+
+impl SimState {
+    fn delegated(&self, delegator: &str, validator: &str) -> Uint128 {
+        self.delegations
+            .get(&(delegator.to_string(), validator.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn set_delegation(&mut self, delegator: &str, validator: &str, amount: Uint128) {
+        let key = (delegator.to_string(), validator.to_string());
+        if amount.is_zero() {
+            self.delegations.remove(&key);
+        } else {
+            self.delegations.insert(key, amount);
+        }
+    }
+
+    fn advance(&mut self, blocks: u64) {
+        self.height += blocks;
+        let height = self.height;
+        let (matured, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.unbonding)
+            .into_iter()
+            .partition(|e| e.completion_height <= height);
+        self.unbonding = pending;
+        for entry in matured {
+            *self.balances.entry(entry.delegator).or_default() += entry.amount;
+        }
+        self.redelegations.retain(|e| e.completion_height > height);
+    }
+
+    fn take_shares(
+        &mut self,
+        delegator: &str,
+        validator: &str,
+        amount: Uint128,
+    ) -> Result<(), SdkError> {
+        let delegated = self.delegated(delegator, validator);
+        if delegated < amount {
+            return Err(SdkError::new(
+                "staking",
+                22,
+                format!(
+                    "{} delegated to {}, {} requested: not enough delegation shares",
+                    delegated, validator, amount
+                ),
+            ));
+        }
+        self.set_delegation(delegator, validator, delegated - amount);
+        Ok(())
+    }
+
+    /// Pays the pending rewards of a delegation out to its delegator.
+    fn withdraw_rewards(&mut self, delegator: &str, validator: &str) {
+        if let Some(amount) = self
+            .rewards
+            .remove(&(delegator.to_string(), validator.to_string()))
+        {
+            *self.balances.entry(delegator.to_string()).or_default() += amount;
+        }
+    }
+
+    fn spend(&mut self, delegator: &str, amount: Uint128) -> Result<(), SdkError> {
+        let balance = self.balances.entry(delegator.to_string()).or_default();
+        if *balance < amount {
+            return Err(SdkError::new(
+                "sdk",
+                5,
+                format!(
+                    "spendable balance {} is smaller than {}: insufficient funds",
+                    balance, amount
+                ),
+            ));
+        }
+        *balance -= amount;
+        Ok(())
+    }
+
+    fn ensure_validator(&self, validator: &str) -> Result<(), SdkError> {
+        if self.validators.contains_key(validator) {
+            Ok(())
+        } else {
+            Err(SdkError::new("staking", 3, "validator does not exist"))
+        }
+    }
+
+    /// Executes `op`, withdrawing the rewards of every delegation whose shares it changes like
+    /// the distribution module's hooks do.
+    fn apply(&mut self, delegator: &str, op: StakingOp) -> Result<(), SdkError> {
+        let completion_height = self.height + UNBONDING_BLOCKS;
+        match op {
+            StakingOp::WithdrawRewards { validator } => {
+                self.ensure_validator(&validator)?;
+                if self.delegated(delegator, &validator).is_zero() {
+                    return Err(SdkError::new(
+                        "distribution",
+                        7,
+                        "no delegation distribution info",
+                    ));
+                }
+                self.withdraw_rewards(delegator, &validator);
+            }
+            StakingOp::Send { to, amount } => {
+                self.spend(delegator, amount)?;
+                *self.balances.entry(to).or_default() += amount;
+            }
+            StakingOp::Delegate { validator, amount } => {
+                self.ensure_validator(&validator)?;
+                self.withdraw_rewards(delegator, &validator);
+                self.spend(delegator, amount)?;
+                let delegated = self.delegated(delegator, &validator);
+                self.set_delegation(delegator, &validator, delegated + amount);
+            }
+            StakingOp::Undelegate { validator, amount } => {
+                self.ensure_validator(&validator)?;
+                let entries = self
+                    .unbonding
+                    .iter()
+                    .filter(|e| e.delegator == delegator && e.validator == validator)
+                    .count();
+                if entries >= MAX_ENTRIES {
+                    return Err(SdkError::new(
+                        "staking",
+                        27,
+                        "too many unbonding delegation entries for (delegator, validator) tuple",
+                    ));
+                }
+                self.take_shares(delegator, &validator, amount)?;
+                self.withdraw_rewards(delegator, &validator);
+                self.unbonding.push(UnbondingEntry {
+                    delegator: delegator.to_string(),
+                    validator,
+                    amount,
+                    completion_height,
+                });
+            }
+            StakingOp::Redelegate { src, dst, amount } => {
+                if src == dst {
+                    return Err(SdkError::new(
+                        "staking",
+                        30,
+                        "cannot redelegate to the same validator",
+                    ));
+                }
+                self.ensure_validator(&src)?;
+                self.ensure_validator(&dst)?;
+                // stake that arrived on `src` through a redelegation cannot hop again until the
+                // first redelegation completes
+                if self
+                    .redelegations
+                    .iter()
+                    .any(|e| e.delegator == delegator && e.dst_validator == src)
+                {
+                    return Err(SdkError::new(
+                        "staking",
+                        32,
+                        "redelegation to this validator already in progress; first redelegation \
+                         to this validator must complete before next redelegation",
+                    ));
+                }
+                let entries = self
+                    .redelegations
+                    .iter()
+                    .filter(|e| {
+                        e.delegator == delegator && e.src_validator == src && e.dst_validator == dst
+                    })
+                    .count();
+                if entries >= MAX_ENTRIES {
+                    return Err(SdkError::new(
+                        "staking",
+                        33,
+                        "too many redelegation entries for (delegator, src-validator, dst-validator) tuple",
+                    ));
+                }
+                self.take_shares(delegator, &src, amount)?;
+                self.withdraw_rewards(delegator, &src);
+                self.withdraw_rewards(delegator, &dst);
+                let delegated = self.delegated(delegator, &dst);
+                self.set_delegation(delegator, &dst, delegated + amount);
+                self.redelegations.push(RedelegationEntry {
+                    delegator: delegator.to_string(),
+                    src_validator: src,
+                    dst_validator: dst,
+                    amount,
+                    completion_height,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the top level messages of a tx signed by `signer`, returns how many staking
+    /// messages ran. Errors carry the nested message indexes the chain puts in its logs.
+    fn execute(
+        &mut self,
+        denom: &str,
+        signer: &str,
+        msgs: &[cosmrs::Any],
+    ) -> Result<u64, SdkError> {
+        let mut executed = 0;
+        for (i, any) in msgs.iter().enumerate() {
+            let wrap = |e: SdkError| SdkError {
+                log: format!("failed to execute message; message index: {}: {}", i, e.log),
+                ..e
+            };
+            if any.type_url != "/cosmos.authz.v1beta1.MsgExec" {
+                let (delegator, op) = staking_op(denom, any).map_err(wrap)?;
+                if delegator != signer {
+                    return Err(wrap(SdkError::new(
+                        "sdk",
+                        4,
+                        format!("{} cannot sign for {}: unauthorized", signer, delegator),
+                    )));
+                }
+                self.apply(&delegator, op).map_err(wrap)?;
+                executed += 1;
+                continue;
+            }
+
+            let exec = MsgExec::decode(any.value.as_slice())
+                .map_err(|e| wrap(SdkError::new("sdk", 2, format!("tx parse error: {}", e))))?;
+            if exec.grantee != signer {
+                return Err(wrap(SdkError::new(
+                    "sdk",
+                    4,
+                    format!(
+                        "MsgExec grantee {} is not the signer {}: unauthorized",
+                        exec.grantee, signer
+                    ),
+                )));
+            }
+            for (j, inner) in exec.msgs.iter().enumerate() {
+                let inner_wrap = |e: SdkError| {
+                    wrap(SdkError {
+                        log: format!("failed to execute message; message index: {}: {}", j, e.log),
+                        ..e
+                    })
+                };
+                let (delegator, op) = staking_op(denom, inner).map_err(inner_wrap)?;
+                if !self
+                    .grants
+                    .contains(&(delegator.clone(), exec.grantee.clone()))
+                {
+                    return Err(inner_wrap(SdkError::new(
+                        "authz",
+                        2,
+                        "authorization not found",
+                    )));
+                }
+                self.apply(&delegator, op).map_err(inner_wrap)?;
+                executed += 1;
+            }
+        }
+        Ok(executed)
+    }
+}
+
+fn staking_op(denom: &str, any: &cosmrs::Any) -> Result<(String, StakingOp), SdkError> {
+    let decode_err =
+        |e: cosmrs::ErrorReport| SdkError::new("sdk", 2, format!("tx parse error: {}", e));
+    let amount = |c: cosmrs::Coin| -> Result<Uint128, SdkError> {
+        if c.denom.as_ref() != denom || c.amount == 0 {
+            return Err(SdkError::new(
+                "sdk",
+                10,
+                format!(
+                    "invalid delegation amount {}{}: invalid coins",
+                    c.amount, c.denom
+                ),
+            ));
+        }
+        Ok(Uint128::new(c.amount))
+    };
+    Ok(match any.type_url.as_str() {
+        "/cosmos.staking.v1beta1.MsgDelegate" => {
+            let msg = cosmrs::staking::MsgDelegate::from_any(any).map_err(decode_err)?;
+            (
+                msg.delegator_address.to_string(),
+                StakingOp::Delegate {
+                    validator: msg.validator_address.to_string(),
+                    amount: amount(msg.amount)?,
+                },
+            )
+        }
+        "/cosmos.staking.v1beta1.MsgUndelegate" => {
+            let msg = cosmrs::staking::MsgUndelegate::from_any(any).map_err(decode_err)?;
+            (
+                msg.delegator_address.to_string(),
+                StakingOp::Undelegate {
+                    validator: msg.validator_address.to_string(),
+                    amount: amount(msg.amount)?,
+                },
+            )
+        }
+        "/cosmos.staking.v1beta1.MsgBeginRedelegate" => {
+            let msg = cosmrs::staking::MsgBeginRedelegate::from_any(any).map_err(decode_err)?;
+            (
+                msg.delegator_address.to_string(),
+                StakingOp::Redelegate {
+                    src: msg.validator_src_address.to_string(),
+                    dst: msg.validator_dst_address.to_string(),
+                    amount: amount(msg.amount)?,
+                },
+            )
+        }
+        "/cosmos.bank.v1beta1.MsgSend" => {
+            let msg = cosmrs::bank::MsgSend::from_any(any).map_err(decode_err)?;
+            let [coin] = <[cosmrs::Coin; 1]>::try_from(msg.amount).map_err(|coins| {
+                SdkError::new(
+                    "sdk",
+                    10,
+                    format!("{} coins sent, the simulator sends one", coins.len()),
+                )
+            })?;
+            (
+                msg.from_address.to_string(),
+                StakingOp::Send {
+                    to: msg.to_address.to_string(),
+                    amount: amount(coin)?,
+                },
+            )
+        }
+        "/cosmos.distribution.v1beta1.MsgWithdrawDelegatorReward" => {
+            let msg = cosmrs::distribution::MsgWithdrawDelegatorReward::from_any(any)
+                .map_err(decode_err)?;
+            (
+                msg.delegator_address.to_string(),
+                StakingOp::WithdrawRewards {
+                    validator: msg.validator_address.to_string(),
+                },
+            )
+        }
+        other => {
+            return Err(SdkError::new(
+                "sdk",
+                6,
+                format!("unrecognized message type {}: unknown request", other),
+            ))
+        }
+    })
+}
+
+/// In-memory chain with the staking rules the planner runs into: delegations, unbonding and
+/// redelegation entries with their limits, transitive redelegations, jailing and authz grants.
+///
+/// Clones share state, so one simulator can back the staking and bank queries, the signing
+/// wallet and the tx tracker of a broadcast at the same time. Every broadcast tx is included in
+/// the next block; a failed tx is recorded with its code and leaves the state untouched.
+#[derive(Clone)]
+pub struct StakingSimulator {
+    denom: String,
+    grantee: String,
+    state: Arc<Mutex<SimState>>,
+}
+
+impl StakingSimulator {
+    /// A chain at `height` whose wallet signs as `grantee`.
+    pub fn new(denom: &str, grantee: &str, height: u64) -> Self {
+        StakingSimulator {
+            denom: denom.to_string(),
+            grantee: grantee.to_string(),
+            state: Arc::new(Mutex::new(SimState {
+                height,
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, SimState> {
+        self.state.lock().expect("simulator state poisoned")
+    }
+
+    pub fn with_validator(self, operator: &str) -> Self {
+        self.state().validators.insert(
+            operator.to_string(),
+            ValidatorState {
+                operator_address: operator.to_string(),
+                status: BondStatus::Bonded,
+                jailed: false,
+                signing: None,
+                tokens: Uint128::zero(),
+                delegator_shares: Decimal256::zero(),
+            },
+        );
+        self
+    }
+
+    /// Seeds a delegation without touching the delegator's balance.
+    pub fn with_delegation(self, delegator: &str, validator: &str, amount: u128) -> Self {
+        {
+            let mut state = self.state();
+            let delegated = state.delegated(delegator, validator);
+            state.set_delegation(delegator, validator, delegated + Uint128::new(amount));
+        }
+        self
+    }
+
+    pub fn with_balance(self, address: &str, amount: u128) -> Self {
+        self.state()
+            .balances
+            .insert(address.to_string(), Uint128::new(amount));
+        self
+    }
+
+    /// Seeds rewards `delegator` has earned on `validator`.
+    pub fn with_rewards(self, delegator: &str, validator: &str, amount: u128) -> Self {
+        *self
+            .state()
+            .rewards
+            .entry((delegator.to_string(), validator.to_string()))
+            .or_default() += Uint128::new(amount);
+        self
+    }
+
+    /// Lets the wallet execute staking messages on behalf of `granter`.
+    pub fn with_grant(self, granter: &str) -> Self {
+        self.state()
+            .grants
+            .insert((granter.to_string(), self.grantee.clone()));
+        self
+    }
+
+    /// Jails `operator`, which also takes it out of the active set.
+    pub fn jail(&self, operator: &str) {
+        if let Some(v) = self.state().validators.get_mut(operator) {
+            v.jailed = true;
+            v.status = BondStatus::Unbonding;
+        }
+    }
+
+    /// Tombstones `operator` as if it double signed, jailing it for good.
+    pub fn tombstone(&self, operator: &str) {
+        self.jail(operator);
+        if let Some(v) = self.state().validators.get_mut(operator) {
+            v.signing
+                .get_or_insert_with(SigningInfo::default)
+                .tombstoned = true;
+        }
+    }
+
+    /// Moves the chain `blocks` ahead, paying out matured unbondings and dropping matured
+    /// redelegation entries.
+    pub fn advance(&self, blocks: u64) {
+        self.state().advance(blocks);
+    }
+
+    pub fn height(&self) -> u64 {
+        self.state().height
+    }
+
+    /// Every delegation on the chain, ordered by delegator then validator.
+    pub fn delegations(&self) -> Vec<Delegation> {
+        self.state()
+            .delegations
+            .iter()
+            .map(|((del_addr, operator_addr), amount)| Delegation {
+                del_addr: del_addr.clone(),
+                operator_addr: operator_addr.clone(),
+                amount: *amount,
+            })
+            .collect()
+    }
+
+    pub fn liquid(&self, address: &str) -> Uint128 {
+        self.state()
+            .balances
+            .get(address)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn unbonding_entries(&self) -> Vec<UnbondingEntry> {
+        self.state().unbonding.clone()
+    }
+
+    pub fn redelegation_entries(&self) -> Vec<RedelegationEntry> {
+        self.state().redelegations.clone()
+    }
+
+    /// Every tx broadcast so far, failed ones included.
+    pub fn txs(&self) -> Vec<TxOutcome> {
+        self.state().txs.values().cloned().collect()
+    }
+}
+
+impl StakingBackend for StakingSimulator {
+    async fn block_height(&self) -> anyhow::Result<u64> {
+        Ok(self.height())
+    }
+
+    async fn delegator_delegations(&self, delegator: &str) -> anyhow::Result<Vec<Delegation>> {
+        Ok(self
+            .delegations()
+            .into_iter()
+            .filter(|d| d.del_addr == delegator)
+            .collect())
+    }
+
+    /// Simulated validators are never slashed, a share is worth one token.
+    async fn delegator_stake(
+        &self,
+        delegator: &str,
+    ) -> anyhow::Result<(Vec<Delegation>, Vec<DelegatorShares>)> {
+        let delegations = self.delegator_delegations(delegator).await?;
+        let shares = delegations
+            .iter()
+            .map(|d| DelegatorShares {
+                delegator: d.del_addr.clone(),
+                validator: d.operator_addr.clone(),
+                shares: Decimal256::from_atomics(d.amount, 0).unwrap_or_default(),
+            })
+            .collect();
+        Ok((delegations, shares))
+    }
+
+    async fn delegation(&self, delegator: &str, validator: &str) -> anyhow::Result<Uint128> {
+        Ok(self.state().delegated(delegator, validator))
+    }
+
+    async fn validator(&self, operator: &str) -> anyhow::Result<Option<ValidatorState>> {
+        let state = self.state();
+        Ok(state.validators.get(operator).map(|v| {
+            let tokens: Uint128 = state
+                .delegations
+                .iter()
+                .filter(|((_, val), _)| val == operator)
+                .map(|(_, amount)| *amount)
+                .sum();
+            ValidatorState {
+                tokens,
+                delegator_shares: Decimal256::from_atomics(tokens, 0).unwrap_or_default(),
+                ..v.clone()
+            }
+        }))
+    }
+
+    async fn unbonding_delegations(&self, delegator: &str) -> anyhow::Result<Vec<Unbonding>> {
+        self.unbonding_entries()
+            .into_iter()
+            .filter(|e| e.delegator == delegator)
+            .map(|e| {
+                let completion = cosmrs::tendermint::Time::from_unix_timestamp(
+                    (e.completion_height * BLOCK_TIME_SECS) as i64,
+                    0,
+                )
+                .map_err(|err| anyhow::anyhow!("{}", err))?;
+                Ok(Unbonding {
+                    delegator: e.delegator,
+                    validator: e.validator,
+                    amount: e.amount,
+                    creation_height: e.completion_height - UNBONDING_BLOCKS,
+                    completion_time: completion.to_rfc3339(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl DistributionBackend for StakingSimulator {
+    async fn pending_rewards(
+        &self,
+        delegator: &str,
+        denom: &str,
+    ) -> anyhow::Result<Vec<PendingReward>> {
+        if denom != self.denom {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .state()
+            .rewards
+            .iter()
+            .filter(|((del, _), _)| del == delegator)
+            .map(|((del, val), amount)| PendingReward {
+                delegator: del.clone(),
+                validator: val.clone(),
+                amount: *amount,
+            })
+            .collect())
+    }
+}
+
+impl BankBackend for StakingSimulator {
+    async fn balance(&self, address: &str, denom: &str) -> anyhow::Result<Uint128> {
+        if denom != self.denom {
+            return Ok(Uint128::zero());
+        }
+        Ok(self.liquid(address))
+    }
+}
+
+impl WalletBackend for StakingSimulator {
+    fn address(&self) -> String {
+        self.grantee.clone()
+    }
+
+    async fn simulate(&self, msgs: Vec<cosmrs::Any>) -> anyhow::Result<GasEstimate> {
+        let mut scratch = self.state().clone();
+        let executed = scratch
+            .execute(&self.denom, &self.grantee, &msgs)
+            .map_err(|e| anyhow::anyhow!("simulation failed: {}", e.log))?;
+        let gas = BASE_GAS + GAS_PER_MSG * executed;
+        // 0.025ubtsg per unit of gas, rounded up
+        Ok(GasEstimate {
+            gas,
+            fee: Uint128::from(gas.div_ceil(40)),
+        })
+    }
+
+    async fn broadcast(
+        &self,
+        msgs: Vec<cosmrs::Any>,
+        timeout_height: u64,
+    ) -> anyhow::Result<String> {
+        let mut state = self.state();
+        anyhow::ensure!(
+            timeout_height > state.height,
+            "tx timeout height {} already reached at height {}",
+            timeout_height,
+            state.height
+        );
+        state.advance(1);
+        let txhash = format!("{:064X}", state.txs.len() + 1);
+
+        let mut next = state.clone();
+        let outcome = match next.execute(&self.denom, &self.grantee, &msgs) {
+            Ok(executed) => {
+                *state = next;
+                TxOutcome {
+                    txhash: txhash.clone(),
+                    height: state.height,
+                    codespace: String::new(),
+                    code: 0,
+                    raw_log: String::new(),
+                    gas_used: BASE_GAS + GAS_PER_MSG * executed,
+                }
+            }
+            Err(e) => TxOutcome {
+                txhash: txhash.clone(),
+                height: state.height,
+                codespace: e.codespace.to_string(),
+                code: e.code,
+                raw_log: e.log,
+                gas_used: BASE_GAS,
+            },
+        };
+        state.txs.insert(txhash.clone(), outcome);
+        Ok(txhash)
+    }
+}
+
+impl TxTracker for StakingSimulator {
+    async fn find_tx(&mut self, hash: &str) -> anyhow::Result<Option<TxOutcome>> {
+        Ok(self.state().txs.get(hash).cloned())
+    }
+
+    async fn latest_height(&mut self) -> anyhow::Result<u64> {
+        Ok(self.height())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        errors::{failing_exec_index, FailureKind},
+        plan::{authz_exec, PlanMsg, RedelegateMsg, UndelegateMsg},
+        snapshot::ValidatorClass,
+    };
+    use tokio::runtime::Runtime;
+
+    const DAO: &str = "bitsong166d42nyufxrh3jps5wx3egdkmvvg7jl6k33yut";
+    const GRANTEE: &str = "bitsong1nphhydjshzjevd03afzlce0xnlrnsm27hy9hgd";
+    const VAL_A: &str = "bitsongvaloper1qxw4fjged2xve8ez7nu779tm8ejw92rv0vcuqr";
+    const VAL_B: &str = "bitsongvaloper1xnc32z84cc9vwftvv4w0v02a2slug3tjt6qyct";
+    const VAL_C: &str = "bitsongvaloper1wetqg989uyj3mpk07h8yt3qvu2cdlsv7fp3zda";
+
+    fn redelegate(src: &str, dst: &str, amount: &str) -> PlanMsg {
+        PlanMsg::Redelegate(RedelegateMsg {
+            delegator_address: DAO.to_string(),
+            validator_src_address: src.to_string(),
+            validator_dst_address: dst.to_string(),
+            amount: amount.to_string(),
+            denom: "ubtsg".to_string(),
+        })
+    }
+
+    fn undelegate(validator: &str, amount: &str) -> PlanMsg {
+        PlanMsg::Undelegate(UndelegateMsg {
+            delegator_address: DAO.to_string(),
+            validator_address: validator.to_string(),
+            amount: amount.to_string(),
+            denom: "ubtsg".to_string(),
+        })
+    }
+
+    fn exec(sim: &StakingSimulator, rt: &Runtime, msgs: &[PlanMsg]) -> anyhow::Result<TxOutcome> {
+        let anys = msgs
+            .iter()
+            .map(|m| m.to_any())
+            .collect::<anyhow::Result<_>>()?;
+        let hash =
+            rt.block_on(sim.broadcast(vec![authz_exec(GRANTEE, anys)], sim.height() + 10))?;
+        rt.block_on(sim.clone().find_tx(&hash))?
+            .ok_or_else(|| anyhow::anyhow!("tx {} not found", hash))
+    }
+
+    fn chain() -> StakingSimulator {
+        StakingSimulator::new("ubtsg", GRANTEE, 100)
+            .with_validator(VAL_A)
+            .with_validator(VAL_B)
+            .with_validator(VAL_C)
+            .with_delegation(DAO, VAL_A, 1_000)
+            .with_grant(DAO)
+    }
+
+    #[test]
+    fn test_transitive_redelegation_waits_for_completion() -> anyhow::Result<()> {
+        let rt = Runtime::new()?;
+        let sim = chain();
+
+        assert!(exec(&sim, &rt, &[redelegate(VAL_A, VAL_B, "400")])?.is_success());
+        assert_eq!(sim.redelegation_entries().len(), 1);
+
+        // the failing hop is the second message of the exec and rolls back the first
+        let failed = exec(
+            &sim,
+            &rt,
+            &[
+                redelegate(VAL_A, VAL_C, "100"),
+                redelegate(VAL_B, VAL_C, "100"),
+            ],
+        )?;
+        assert_eq!(
+            FailureKind::classify(&failed.codespace, failed.code, &failed.raw_log),
+            FailureKind::TransitiveRedelegation
+        );
+        assert_eq!(failing_exec_index(&failed.raw_log), Some(1));
+        assert_eq!(rt.block_on(sim.delegation(DAO, VAL_A))?, Uint128::new(600));
+
+        sim.advance(UNBONDING_BLOCKS);
+        assert!(sim.redelegation_entries().is_empty());
+        assert!(exec(&sim, &rt, &[redelegate(VAL_B, VAL_C, "400")])?.is_success());
+        assert_eq!(rt.block_on(sim.delegation(DAO, VAL_C))?, Uint128::new(400));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbonding_entries_are_capped_and_mature_to_liquid() -> anyhow::Result<()> {
+        let rt = Runtime::new()?;
+        let sim = chain();
+
+        let undelegations = vec![undelegate(VAL_A, "10"); MAX_ENTRIES];
+        assert!(exec(&sim, &rt, &undelegations)?.is_success());
+        let failed = exec(&sim, &rt, &[undelegate(VAL_A, "10")])?;
+        assert_eq!(
+            FailureKind::classify(&failed.codespace, failed.code, &failed.raw_log),
+            FailureKind::TooManyUnbondingEntries
+        );
+
+        assert_eq!(sim.liquid(DAO), Uint128::zero());
+        sim.advance(UNBONDING_BLOCKS);
+        assert!(sim.unbonding_entries().is_empty());
+        assert_eq!(sim.liquid(DAO), Uint128::new(70));
+        assert_eq!(rt.block_on(sim.delegation(DAO, VAL_A))?, Uint128::new(930));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_without_grant_is_rejected() -> anyhow::Result<()> {
+        let rt = Runtime::new()?;
+        let sim = StakingSimulator::new("ubtsg", GRANTEE, 100)
+            .with_validator(VAL_A)
+            .with_validator(VAL_B)
+            .with_delegation(DAO, VAL_A, 1_000);
+
+        let anys = vec![redelegate(VAL_A, VAL_B, "1").to_any()?];
+        let err = rt
+            .block_on(sim.simulate(vec![authz_exec(GRANTEE, anys)]))
+            .unwrap_err();
+        assert_eq!(
+            FailureKind::from_log(&err.to_string()),
+            Some(FailureKind::AuthorizationNotFound)
+        );
+
+        sim.jail(VAL_B);
+        let state = rt
+            .block_on(sim.validator(VAL_B))?
+            .expect("validator exists");
+        assert_eq!(state.class(), ValidatorClass::Jailed);
+
+        sim.tombstone(VAL_A);
+        let state = rt
+            .block_on(sim.validator(VAL_A))?
+            .expect("validator exists");
+        assert_eq!(state.class(), ValidatorClass::Tombstoned);
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Decimal256, Uint128};
+
+use crate::{plan::PlanMsg, rewards::RewardLedger, shares::ShareLedger};
+
+/// Chain state the plan was computed from, written next to `delegation_messages.json`.
+pub const SNAPSHOT_JSON: &str = "delegation_snapshot.json";
+
+#[cw_serde]
+pub struct Delegation {
+    pub del_addr: String,
+    pub operator_addr: String,
+    pub amount: Uint128,
+}
+
+/// Shares `delegator` holds of `validator`. Undelegations and redelegations are executed in
+/// shares, the token amounts of [`Delegation`] are their value rounded down.
+#[cw_serde]
+pub struct DelegatorShares {
+    pub delegator: String,
+    pub validator: String,
+    pub shares: Decimal256,
+}
+
+/// Stake `delegator` is unbonding from `validator`, liquid again at `completion_time`.
+#[cw_serde]
+pub struct Unbonding {
+    pub delegator: String,
+    pub validator: String,
+    pub amount: Uint128,
+    pub creation_height: u64,
+    /// RFC 3339 time the entry matures.
+    pub completion_time: String,
+}
+
+/// Staking rewards `delegator` has earned on `validator` and not withdrawn yet, truncated to
+/// whole ubtsg.
+#[cw_serde]
+pub struct PendingReward {
+    pub delegator: String,
+    pub validator: String,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+#[derive(Copy, Eq)]
+pub enum BondStatus {
+    Bonded,
+    Unbonding,
+    Unbonded,
+}
+
+/// The slashing module's record of a validator's consensus key.
+#[cw_serde]
+#[derive(Default)]
+pub struct SigningInfo {
+    /// Tombstoned validators were slashed for double signing and can never be unjailed.
+    pub tombstoned: bool,
+    /// RFC 3339 time until which the validator stays jailed.
+    pub jailed_until: String,
+    /// Blocks missed in the current signed blocks window.
+    pub missed_blocks: u64,
+}
+
+#[cw_serde]
+pub struct ValidatorState {
+    pub operator_address: String,
+    pub status: BondStatus,
+    pub jailed: bool,
+    /// `None` if the validator never signed a block, or the snapshot predates signing infos.
+    #[serde(default)]
+    pub signing: Option<SigningInfo>,
+    /// Bonded tokens, lower than `delegator_shares` once the validator was slashed. Zero in
+    /// snapshots that predate them.
+    #[serde(default)]
+    pub tokens: Uint128,
+    #[serde(default)]
+    pub delegator_shares: Decimal256,
+}
+
+/// What a validator can do with stake, most severe condition first.
+#[cw_serde]
+#[derive(Copy, Eq)]
+pub enum ValidatorClass {
+    Bonded,
+    Unbonding,
+    Unbonded,
+    Jailed,
+    Tombstoned,
+}
+
+impl fmt::Display for ValidatorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let class = match self {
+            ValidatorClass::Bonded => "bonded",
+            ValidatorClass::Unbonding => "unbonding",
+            ValidatorClass::Unbonded => "unbonded",
+            ValidatorClass::Jailed => "jailed",
+            ValidatorClass::Tombstoned => "tombstoned",
+        };
+        f.write_str(class)
+    }
+}
+
+impl ValidatorState {
+    pub fn class(&self) -> ValidatorClass {
+        if self.signing.as_ref().is_some_and(|s| s.tombstoned) {
+            return ValidatorClass::Tombstoned;
+        }
+        if self.jailed {
+            return ValidatorClass::Jailed;
+        }
+        match self.status {
+            BondStatus::Bonded => ValidatorClass::Bonded,
+            BondStatus::Unbonding => ValidatorClass::Unbonding,
+            BondStatus::Unbonded => ValidatorClass::Unbonded,
+        }
+    }
+
+    /// Only bonded, unjailed validators can safely receive stake.
+    pub fn is_active(&self) -> bool {
+        self.class() == ValidatorClass::Bonded
+    }
+}
+
+/// DAO delegations, liquid balances and validator states at `height`.
+#[cw_serde]
+#[derive(Default)]
+pub struct ChainSnapshot {
+    pub height: u64,
+    pub delegations: Vec<Delegation>,
+    pub validators: Vec<ValidatorState>,
+    /// Spendable ubtsg of each DAO.
+    #[serde(default)]
+    pub liquid: BTreeMap<String, Uint128>,
+    /// Class of every target validator that exists, keyed by operator address.
+    #[serde(default)]
+    pub target_classes: BTreeMap<String, ValidatorClass>,
+    /// Unbonding entries of each DAO.
+    #[serde(default)]
+    pub unbonding: Vec<Unbonding>,
+    /// Pending rewards of each DAO.
+    #[serde(default)]
+    pub rewards: Vec<PendingReward>,
+    /// Shares behind each of `delegations`.
+    #[serde(default)]
+    pub shares: Vec<DelegatorShares>,
+}
+
+impl ChainSnapshot {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read snapshot {}: {}", path, e))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Amount `delegator` has staked on `validator`, zero if there is no delegation.
+    pub fn delegation(&self, delegator: &str, validator: &str) -> Uint128 {
+        self.delegations
+            .iter()
+            .filter(|d| d.del_addr == delegator && d.operator_addr == validator)
+            .map(|d| d.amount)
+            .sum()
+    }
+
+    /// Shares `delegator` holds of `validator`, `None` if the snapshot did not record them.
+    pub fn shares(&self, delegator: &str, validator: &str) -> Option<Decimal256> {
+        self.shares
+            .iter()
+            .find(|s| s.delegator == delegator && s.validator == validator)
+            .map(|s| s.shares)
+    }
+
+    pub fn validator(&self, operator: &str) -> Option<&ValidatorState> {
+        self.validators
+            .iter()
+            .find(|v| v.operator_address == operator)
+    }
+
+    /// Projects the state after `msgs` were executed, so later bundles are compared against it.
+    /// The rewards the messages withdraw are added to the liquid balance, and a move that takes
+    /// all of a delegation's shares leaves nothing of it.
+    pub fn apply(&mut self, msgs: &[PlanMsg]) -> anyhow::Result<()> {
+        let mut rewards = RewardLedger::new(&self.rewards);
+        let mut shares = ShareLedger::new(self);
+        let mut balances: BTreeMap<(String, String), Uint128> = BTreeMap::new();
+        for del in &self.delegations {
+            *balances
+                .entry((del.del_addr.clone(), del.operator_addr.clone()))
+                .or_default() += del.amount;
+        }
+
+        for msg in msgs {
+            let amount = Uint128::from_str(msg.amount())?;
+            let delegator = msg.delegator().to_string();
+            let withdrawn = rewards.withdraw(msg);
+            if let Some(liquid) = self.liquid.get_mut(&delegator) {
+                *liquid += withdrawn;
+            }
+            let (src, dst) = match msg {
+                PlanMsg::WithdrawRewards(_) | PlanMsg::Send(_) => (None, None),
+                PlanMsg::Redelegate(m) => (
+                    Some(m.validator_src_address.clone()),
+                    Some(m.validator_dst_address.clone()),
+                ),
+                PlanMsg::Delegate(m) => (None, Some(m.validator_address.clone())),
+                PlanMsg::Undelegate(m) => (Some(m.validator_address.clone()), None),
+            };
+            if let Some(src) = src {
+                let balance = balances
+                    .entry((delegator.clone(), src.clone()))
+                    .or_default();
+                *balance = balance.checked_sub(amount).map_err(|_| {
+                    anyhow::anyhow!(
+                        "{} moves {} from {} but only {} is delegated",
+                        delegator,
+                        amount,
+                        src,
+                        balance
+                    )
+                })?;
+                if shares.take(&delegator, &src, amount) {
+                    *balance = Uint128::zero();
+                }
+            }
+            if let PlanMsg::Delegate(_) | PlanMsg::Send(_) = msg {
+                // delegations and sends are only checked against the liquid balance when it was
+                // recorded
+                if let Some(liquid) = self.liquid.get_mut(&delegator) {
+                    *liquid = liquid.checked_sub(amount).map_err(|_| {
+                        anyhow::anyhow!(
+                            "{} spends {} but only has {} liquid",
+                            delegator,
+                            amount,
+                            liquid
+                        )
+                    })?;
+                }
+            }
+            if let Some(dst) = dst {
+                shares.give(&delegator, &dst, amount);
+                *balances.entry((delegator, dst)).or_default() += amount;
+            }
+        }
+
+        self.delegations = balances
+            .into_iter()
+            .filter(|(_, amount)| !amount.is_zero())
+            .map(|((del_addr, operator_addr), amount)| Delegation {
+                del_addr,
+                operator_addr,
+                amount,
+            })
+            .collect();
+        self.rewards = rewards.into_rewards();
+        self.shares = shares.into_shares();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::{DelegateMsg, RedelegateMsg, UndelegateMsg};
+
+    #[test]
+    fn test_apply_moves_stake_between_validators() -> anyhow::Result<()> {
+        let mut snapshot = ChainSnapshot {
+            height: 1,
+            delegations: vec![Delegation {
+                del_addr: "dao1".to_string(),
+                operator_addr: "valA".to_string(),
+                amount: Uint128::new(100),
+            }],
+            validators: vec![],
+            liquid: BTreeMap::from([("dao1".to_string(), Uint128::new(5))]),
+            ..Default::default()
+        };
+        snapshot.apply(&[
+            PlanMsg::Redelegate(RedelegateMsg {
+                delegator_address: "dao1".to_string(),
+                validator_src_address: "valA".to_string(),
+                validator_dst_address: "valB".to_string(),
+                amount: "60".to_string(),
+                denom: "ubtsg".to_string(),
+            }),
+            PlanMsg::Undelegate(UndelegateMsg {
+                delegator_address: "dao1".to_string(),
+                validator_address: "valA".to_string(),
+                amount: "40".to_string(),
+                denom: "ubtsg".to_string(),
+            }),
+            PlanMsg::Delegate(DelegateMsg {
+                delegator_address: "dao1".to_string(),
+                validator_address: "valB".to_string(),
+                amount: "5".to_string(),
+                denom: "ubtsg".to_string(),
+            }),
+        ])?;
+
+        assert_eq!(snapshot.delegation("dao1", "valA"), Uint128::zero());
+        assert_eq!(snapshot.delegation("dao1", "valB"), Uint128::new(65));
+        assert_eq!(snapshot.delegations.len(), 1);
+        assert_eq!(snapshot.liquid["dao1"], Uint128::zero());
+
+        let err = snapshot
+            .apply(&[PlanMsg::Undelegate(UndelegateMsg {
+                delegator_address: "dao1".to_string(),
+                validator_address: "valA".to_string(),
+                amount: "1".to_string(),
+                denom: "ubtsg".to_string(),
+            })])
+            .unwrap_err();
+        assert!(err.to_string().contains("only 0 is delegated"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_class_reports_the_most_severe_condition() {
+        let mut validator = ValidatorState {
+            operator_address: "valA".to_string(),
+            status: BondStatus::Unbonding,
+            jailed: false,
+            signing: None,
+            tokens: Uint128::zero(),
+            delegator_shares: Decimal256::zero(),
+        };
+        assert_eq!(validator.class(), ValidatorClass::Unbonding);
+
+        // jailing unbonds a validator, the jail is what matters
+        validator.jailed = true;
+        assert_eq!(validator.class(), ValidatorClass::Jailed);
+
+        validator.signing = Some(SigningInfo {
+            tombstoned: true,
+            ..Default::default()
+        });
+        assert_eq!(validator.class(), ValidatorClass::Tombstoned);
+        assert!(!validator.is_active());
+        assert_eq!(
+            serde_json::to_string(&validator.class()).unwrap(),
+            "\"tombstoned\""
+        );
+    }
+}
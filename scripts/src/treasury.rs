@@ -0,0 +1,269 @@
+use std::fmt;
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Decimal, Uint128};
+
+use crate::{
+    chain::ChainProfile,
+    plan::MessageExport,
+    rewards::withdrawn_rewards,
+    snapshot::{ChainSnapshot, Delegation},
+};
+
+/// Per-DAO treasury report, written next to `delegation_messages.json`.
+pub const TREASURY_JSON: &str = "delegation_treasury.json";
+
+/// Where one DAO's stake denom sits at the snapshot height, and its part of the obligation.
+#[cw_serde]
+pub struct DaoTreasury {
+    pub dao: String,
+    pub liquid: Uint128,
+    /// Stake on validators the realignment manages, private agreements excluded.
+    pub delegated: Uint128,
+    /// Stake under private agreements, never realigned.
+    pub omitted: Uint128,
+    pub unbonding: Uint128,
+    pub pending_rewards: Uint128,
+    /// Part of `pending_rewards` the plan withdraws, explicitly or along with the stake it moves.
+    pub withdrawn_rewards: Uint128,
+    /// `liquid` once the plan executed, withdrawn rewards included.
+    pub projected_liquid: Uint128,
+    /// Stake the DAO holds on target validators once the plan executed.
+    pub obligation: Uint128,
+    /// `obligation` as a share of all targets.
+    pub obligation_share: Decimal,
+    /// Delegations counted in `delegated`.
+    pub delegation_count: usize,
+}
+
+#[cw_serde]
+pub struct TreasuryReport {
+    pub height: u64,
+    pub total_obligation: Uint128,
+    pub daos: Vec<DaoTreasury>,
+}
+
+/// Reports each of `dao_addrs` from the snapshot the plan was computed from, projecting the
+/// plan to attribute the targets' stake.
+pub fn treasury_report(
+    snapshot: &ChainSnapshot,
+    plan: &MessageExport,
+    targets: &[Delegation],
+    dao_addrs: &[String],
+    omitted: &[&str],
+) -> anyhow::Result<TreasuryReport> {
+    let msgs: Vec<_> = plan.entries().into_iter().map(|e| e.msg).collect();
+    let mut projected = snapshot.clone();
+    projected.apply(&msgs)?;
+    let withdrawn = withdrawn_rewards(&snapshot.rewards, &msgs);
+    let total_obligation: Uint128 = targets.iter().map(|t| t.amount).sum();
+
+    let mut daos = Vec::new();
+    for dao in dao_addrs {
+        let (omitted_dels, managed): (Vec<&Delegation>, Vec<&Delegation>) = snapshot
+            .delegations
+            .iter()
+            .filter(|d| d.del_addr == *dao)
+            .partition(|d| omitted.contains(&d.operator_addr.as_str()));
+        let obligation: Uint128 = targets
+            .iter()
+            .map(|t| projected.delegation(dao, &t.operator_addr))
+            .sum();
+
+        daos.push(DaoTreasury {
+            dao: dao.clone(),
+            liquid: snapshot.liquid.get(dao).copied().unwrap_or_default(),
+            delegated: managed.iter().map(|d| d.amount).sum(),
+            omitted: omitted_dels.iter().map(|d| d.amount).sum(),
+            unbonding: snapshot
+                .unbonding
+                .iter()
+                .filter(|u| u.delegator == *dao)
+                .map(|u| u.amount)
+                .sum(),
+            pending_rewards: snapshot
+                .rewards
+                .iter()
+                .filter(|r| r.delegator == *dao)
+                .map(|r| r.amount)
+                .sum(),
+            withdrawn_rewards: withdrawn.get(dao).copied().unwrap_or_default(),
+            projected_liquid: projected.liquid.get(dao).copied().unwrap_or_default(),
+            obligation,
+            obligation_share: if total_obligation.is_zero() {
+                Decimal::zero()
+            } else {
+                Decimal::from_ratio(obligation, total_obligation)
+            },
+            delegation_count: managed.len(),
+        });
+    }
+
+    Ok(TreasuryReport {
+        height: snapshot.height,
+        total_obligation,
+        daos,
+    })
+}
+
+/// Two decimal percentage, e.g. `75.00%`.
+fn percent(share: Decimal) -> String {
+    let bps = Uint128::new(10_000).mul_floor(share).u128();
+    format!("{}.{:02}%", bps / 100, bps % 100)
+}
+
+impl TreasuryReport {
+    /// One row per DAO, amounts in the chain's display denom.
+    pub fn table<'a>(&'a self, chain: &'a ChainProfile) -> TreasuryTable<'a> {
+        TreasuryTable {
+            report: self,
+            chain,
+        }
+    }
+}
+
+pub struct TreasuryTable<'a> {
+    report: &'a TreasuryReport,
+    chain: &'a ChainProfile,
+}
+
+impl fmt::Display for TreasuryTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let amount = |a| self.chain.display_amount(a);
+        writeln!(
+            f,
+            "{:<46} {:>16} {:>16} {:>16} {:>16} {:>14} {:>12} {:>16} {:>7} {:>5}",
+            "DAO",
+            "liquid",
+            "liquid after",
+            "delegated",
+            "omitted",
+            "unbonding",
+            "rewards",
+            "obligation",
+            "share",
+            "dels"
+        )?;
+        for dao in &self.report.daos {
+            writeln!(
+                f,
+                "{:<46} {:>16} {:>16} {:>16} {:>16} {:>14} {:>12} {:>16} {:>7} {:>5}",
+                dao.dao,
+                amount(dao.liquid),
+                amount(dao.projected_liquid),
+                amount(dao.delegated),
+                amount(dao.omitted),
+                amount(dao.unbonding),
+                amount(dao.pending_rewards),
+                amount(dao.obligation),
+                percent(dao.obligation_share),
+                dao.delegation_count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{
+        fixtures::del,
+        plan::{Delegations, RedelegateMsg, Redelegations, Undelegations},
+        snapshot::{PendingReward, Unbonding},
+    };
+
+    #[test]
+    fn test_each_dao_only_reports_its_own_stake() -> anyhow::Result<()> {
+        let snapshot = ChainSnapshot {
+            height: 42,
+            delegations: vec![
+                del("dao1", "valA", 100),
+                del("dao1", "team", 7),
+                del("dao2", "valB", 300),
+            ],
+            liquid: BTreeMap::from([
+                ("dao1".to_string(), Uint128::new(5)),
+                ("dao2".to_string(), Uint128::new(1)),
+            ]),
+            unbonding: vec![Unbonding {
+                delegator: "dao2".to_string(),
+                validator: "valB".to_string(),
+                amount: Uint128::new(20),
+                creation_height: 40,
+                completion_time: "2026-11-09T00:00:00Z".to_string(),
+            }],
+            rewards: vec![
+                PendingReward {
+                    delegator: "dao1".to_string(),
+                    validator: "valA".to_string(),
+                    amount: Uint128::new(3),
+                },
+                PendingReward {
+                    delegator: "dao2".to_string(),
+                    validator: "valB".to_string(),
+                    amount: Uint128::new(4),
+                },
+            ],
+            ..Default::default()
+        };
+        let plan = MessageExport {
+            height: 42,
+            withdrawals: Default::default(),
+            sends: Default::default(),
+            redelegations: Redelegations {
+                data: vec![RedelegateMsg {
+                    delegator_address: "dao2".to_string(),
+                    validator_src_address: "valB".to_string(),
+                    validator_dst_address: "valA".to_string(),
+                    amount: "100".to_string(),
+                    denom: "ubtsg".to_string(),
+                }],
+                count: 1,
+                total_ubtsg: Uint128::new(100),
+            },
+            delegations: Delegations {
+                data: vec![],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            undelegates: Undelegations {
+                data: vec![],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+        };
+        let targets = [del("", "valA", 200), del("", "valB", 200)];
+        let daos = ["dao1".to_string(), "dao2".to_string()];
+
+        let report = treasury_report(&snapshot, &plan, &targets, &daos, &["team"])?;
+        let [dao1, dao2] = &report.daos[..] else {
+            panic!("{:?}", report.daos);
+        };
+        assert_eq!(
+            (dao1.delegated, dao1.omitted, dao1.delegation_count),
+            (Uint128::new(100), Uint128::new(7), 1)
+        );
+        // not dao1's stake on top of its own
+        assert_eq!(
+            (dao2.delegated, dao2.delegation_count),
+            (Uint128::new(300), 1)
+        );
+        assert_eq!(dao1.liquid, Uint128::new(5));
+        assert_eq!(dao2.unbonding, Uint128::new(20));
+        assert_eq!(dao1.pending_rewards, Uint128::new(3));
+        // redelegating from valB withdraws dao2's rewards there, dao1's stay pending
+        assert_eq!(dao1.withdrawn_rewards, Uint128::zero());
+        assert_eq!(dao1.projected_liquid, Uint128::new(5));
+        assert_eq!(dao2.withdrawn_rewards, Uint128::new(4));
+        assert_eq!(dao2.projected_liquid, Uint128::new(5));
+        assert_eq!(dao1.obligation, Uint128::new(100));
+        assert_eq!(dao2.obligation, Uint128::new(300));
+        assert_eq!(dao2.obligation_share, Decimal::percent(75));
+        let table = report.table(&ChainProfile::bitsong_mainnet()).to_string();
+        assert!(table.contains("75.00%"), "{}", table);
+        Ok(())
+    }
+}
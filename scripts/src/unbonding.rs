@@ -0,0 +1,273 @@
+use std::{collections::BTreeMap, fmt};
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::Uint128;
+
+use crate::{
+    chain::ChainProfile,
+    compound::{compound, CompoundMode},
+    plan::{MessageExport, SendMsg},
+    snapshot::{ChainSnapshot, Delegation, Unbonding},
+};
+
+/// Unbonding schedule written by the `unbonding` command.
+pub const UNBONDING_JSON: &str = "delegation_unbonding.json";
+/// Plan routing the DAOs' freed liquid funds, written by the `unbonding` command.
+pub const UNBONDING_FOLLOWUP_JSON: &str = "delegation_unbonding_followup.json";
+
+/// One unbonding entry maturing, and the DAO's liquid balance once it did.
+#[cw_serde]
+pub struct LiquidStep {
+    pub completion_time: String,
+    pub dao: String,
+    pub validator: String,
+    pub amount: Uint128,
+    pub liquid_after: Uint128,
+}
+
+#[cw_serde]
+pub struct UnbondingSchedule {
+    pub height: u64,
+    /// Entries of the earlier snapshot that are no longer unbonding, their funds are liquid.
+    pub matured: Vec<Unbonding>,
+    /// Entries still unbonding, soonest first.
+    pub pending: Vec<Unbonding>,
+    /// Liquid balance of each DAO at `height`.
+    pub liquid: BTreeMap<String, Uint128>,
+    /// Each DAO's liquid balance as the pending entries mature, assuming nothing else moves.
+    pub timeline: Vec<LiquidStep>,
+}
+
+/// Schedules the unbonding entries of `live`. Entries of `earlier`, e.g. the snapshot a plan was
+/// made from, that `live` no longer lists have matured since.
+pub fn unbonding_schedule(
+    live: &ChainSnapshot,
+    earlier: Option<&ChainSnapshot>,
+) -> UnbondingSchedule {
+    let same = |a: &Unbonding, b: &Unbonding| {
+        a.delegator == b.delegator
+            && a.validator == b.validator
+            && a.creation_height == b.creation_height
+            && a.completion_time == b.completion_time
+    };
+    let matured = earlier
+        .map(|earlier| {
+            earlier
+                .unbonding
+                .iter()
+                .filter(|e| !live.unbonding.iter().any(|l| same(e, l)))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut pending = live.unbonding.clone();
+    // RFC 3339 timestamps in UTC sort chronologically
+    pending.sort_by(|a, b| {
+        (&a.completion_time, &a.delegator, &a.validator).cmp(&(
+            &b.completion_time,
+            &b.delegator,
+            &b.validator,
+        ))
+    });
+
+    let mut liquid = live.liquid.clone();
+    let timeline = pending
+        .iter()
+        .map(|entry| {
+            let balance = liquid.entry(entry.delegator.clone()).or_default();
+            *balance += entry.amount;
+            LiquidStep {
+                completion_time: entry.completion_time.clone(),
+                dao: entry.delegator.clone(),
+                validator: entry.validator.clone(),
+                amount: entry.amount,
+                liquid_after: *balance,
+            }
+        })
+        .collect();
+
+    UnbondingSchedule {
+        height: live.height,
+        matured,
+        pending,
+        liquid: live.liquid.clone(),
+        timeline,
+    }
+}
+
+/// Plans what each DAO holds liquid above `keep`, freed unbonding funds included: sent to
+/// `reserve_address` when given, else delegated to the targets the DAOs' stake leaves short.
+pub fn route_freed_funds(
+    live: &ChainSnapshot,
+    targets: &[Delegation],
+    dao_addrs: &[String],
+    keep: Uint128,
+    reserve_address: Option<&str>,
+    denom: &str,
+) -> anyhow::Result<MessageExport> {
+    let mut export = MessageExport {
+        height: live.height,
+        ..Default::default()
+    };
+    match reserve_address {
+        Some(reserve) => {
+            for dao in dao_addrs {
+                let liquid = live.liquid.get(dao).copied().unwrap_or_default();
+                let surplus = liquid.saturating_sub(keep);
+                if !surplus.is_zero() {
+                    export.sends.data.push(SendMsg {
+                        from_address: dao.clone(),
+                        to_address: reserve.to_string(),
+                        amount: surplus.to_string(),
+                        denom: denom.to_string(),
+                    });
+                }
+            }
+            export.recompute_totals()?;
+        }
+        None => {
+            compound(
+                &mut export,
+                live,
+                targets,
+                dao_addrs,
+                CompoundMode::UnderTarget,
+                keep,
+                denom,
+            )?;
+        }
+    }
+    Ok(export)
+}
+
+impl UnbondingSchedule {
+    /// Matured entries, then the liquid timeline, amounts in the chain's display denom.
+    pub fn table<'a>(&'a self, chain: &'a ChainProfile) -> UnbondingTable<'a> {
+        UnbondingTable {
+            schedule: self,
+            chain,
+        }
+    }
+}
+
+pub struct UnbondingTable<'a> {
+    schedule: &'a UnbondingSchedule,
+    chain: &'a ChainProfile,
+}
+
+impl fmt::Display for UnbondingTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let amount = |a| self.chain.display_amount(a);
+        for entry in &self.schedule.matured {
+            writeln!(
+                f,
+                "matured {} {} {} from {}, due {}",
+                entry.delegator,
+                amount(entry.amount),
+                self.chain.display_denom,
+                entry.validator,
+                entry.completion_time
+            )?;
+        }
+        writeln!(
+            f,
+            "{:<22} {:<46} {:<53} {:>16} {:>16}",
+            "completes", "DAO", "validator", "amount", "liquid after"
+        )?;
+        for step in &self.schedule.timeline {
+            writeln!(
+                f,
+                "{:<22} {:<46} {:<53} {:>16} {:>16}",
+                step.completion_time,
+                step.dao,
+                step.validator,
+                amount(step.amount),
+                amount(step.liquid_after)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(dao: &str, val: &str, amount: u128, completion_time: &str) -> Unbonding {
+        Unbonding {
+            delegator: dao.to_string(),
+            validator: val.to_string(),
+            amount: Uint128::new(amount),
+            creation_height: 10,
+            completion_time: completion_time.to_string(),
+        }
+    }
+
+    fn live() -> ChainSnapshot {
+        ChainSnapshot {
+            height: 100,
+            liquid: BTreeMap::from([
+                ("dao1".to_string(), Uint128::new(50)),
+                ("dao2".to_string(), Uint128::new(3)),
+            ]),
+            unbonding: vec![
+                entry("dao1", "valB", 20, "2026-11-20T00:00:00Z"),
+                entry("dao1", "valA", 30, "2026-11-09T00:00:00Z"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_liquid_grows_as_entries_mature() {
+        let earlier = ChainSnapshot {
+            unbonding: vec![
+                entry("dao2", "valA", 40, "2026-10-01T00:00:00Z"),
+                entry("dao1", "valA", 30, "2026-11-09T00:00:00Z"),
+            ],
+            ..Default::default()
+        };
+        let schedule = unbonding_schedule(&live(), Some(&earlier));
+
+        assert_eq!(
+            schedule.matured,
+            [entry("dao2", "valA", 40, "2026-10-01T00:00:00Z")]
+        );
+        let steps: Vec<_> = schedule
+            .timeline
+            .iter()
+            .map(|s| (s.validator.as_str(), s.liquid_after.u128()))
+            .collect();
+        assert_eq!(steps, [("valA", 80), ("valB", 100)]);
+        assert!(schedule
+            .table(&ChainProfile::bitsong_mainnet())
+            .to_string()
+            .contains("2026-11-09T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_freed_funds_above_what_is_kept_go_to_the_reserve() -> anyhow::Result<()> {
+        let daos = ["dao1".to_string(), "dao2".to_string()];
+        let export = route_freed_funds(
+            &live(),
+            &[],
+            &daos,
+            Uint128::new(10),
+            Some("reserve"),
+            "ubtsg",
+        )?;
+
+        assert_eq!(
+            export.sends.data,
+            [SendMsg {
+                from_address: "dao1".to_string(),
+                to_address: "reserve".to_string(),
+                amount: "40".to_string(),
+                denom: "ubtsg".to_string(),
+            }]
+        );
+        assert_eq!(export.entries().len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,746 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::Uint128;
+
+use crate::{
+    plan::{MessageExport, PlanEntryId, PlanMsg},
+    precheck::{destinations, Violation},
+    rewards::RewardLedger,
+    shares::{share_dust, ShareDust, ShareLedger},
+    snapshot::{ChainSnapshot, Delegation},
+};
+
+/// Verification result of the last plan, including the override reason if it was exported anyway.
+pub const VERIFICATION_JSON: &str = "delegation_verification.json";
+
+/// A target validator that does not end up with exactly its target.
+#[cw_serde]
+pub struct Discrepancy {
+    pub validator: String,
+    pub final_amount: Uint128,
+    pub target: Uint128,
+}
+
+impl Discrepancy {
+    pub fn diff(&self) -> Uint128 {
+        self.final_amount.abs_diff(self.target)
+    }
+}
+
+/// A validator outside the targets that keeps DAO stake after the plan.
+#[cw_serde]
+pub struct UnexpectedValidator {
+    pub validator: String,
+    pub amount: Uint128,
+}
+
+/// A validator the plan takes more stake from than is delegated to it.
+#[cw_serde]
+pub struct NegativeBalance {
+    pub validator: String,
+    pub shortfall: Uint128,
+}
+
+#[cw_serde]
+#[derive(Copy, Eq)]
+pub enum Shortfall {
+    /// The DAO has less staked on the source validator than the message moves.
+    Shares,
+    /// The DAO's liquid balance does not cover the delegation.
+    Funds,
+}
+
+/// The first message that would fail when the plan is executed in broadcast order.
+#[cw_serde]
+pub struct ReplayFailure {
+    pub entry: PlanEntryId,
+    pub dao: String,
+    pub validator: String,
+    pub amount: Uint128,
+    pub available: Uint128,
+    pub shortfall: Shortfall,
+    /// Denom of `amount` and `available`.
+    #[serde(default)]
+    pub denom: String,
+}
+
+impl fmt::Display for ReplayFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let available = match self.shortfall {
+            Shortfall::Shares => "staked on the source validator",
+            Shortfall::Funds => "liquid",
+        };
+        write!(
+            f,
+            "{} would fail with insufficient {}: {} has {}{} {}, the message moves {}{} ({})",
+            self.entry,
+            match self.shortfall {
+                Shortfall::Shares => "shares",
+                Shortfall::Funds => "funds",
+            },
+            self.dao,
+            self.available,
+            self.denom,
+            available,
+            self.amount,
+            self.denom,
+            self.validator
+        )
+    }
+}
+
+/// Executes the plan message by message against each DAO's own delegations and liquid balance.
+/// The export order is the broadcast order within a DAO, and DAOs do not share balances, so this
+/// finds the message that would fail first on chain. Undelegated stake is unbonding and never
+/// becomes liquid during the run, the pending rewards each message withdraws do. A move that takes
+/// all of a delegation's shares empties it.
+pub fn replay_plan(
+    export: &MessageExport,
+    snapshot: &ChainSnapshot,
+) -> anyhow::Result<Option<ReplayFailure>> {
+    let mut staked: BTreeMap<(String, String), Uint128> = BTreeMap::new();
+    for del in &snapshot.delegations {
+        *staked
+            .entry((del.del_addr.clone(), del.operator_addr.clone()))
+            .or_default() += del.amount;
+    }
+    let mut liquid = snapshot.liquid.clone();
+    let mut rewards = RewardLedger::new(&snapshot.rewards);
+    let mut shares = ShareLedger::new(snapshot);
+
+    for entry in export.entries() {
+        let amount = Uint128::from_str(entry.msg.amount())?;
+        let dao = entry.msg.delegator().to_string();
+        let failure = |available, shortfall| ReplayFailure {
+            entry: entry.id,
+            dao: dao.clone(),
+            validator: entry.msg.validator_label(),
+            amount,
+            available,
+            shortfall,
+            denom: entry.msg.denom().to_string(),
+        };
+
+        // withdrawn before the message moves any stake, a delegation may spend them
+        *liquid.entry(dao.clone()).or_default() += rewards.withdraw(&entry.msg);
+        let (src, dst) = match &entry.msg {
+            PlanMsg::WithdrawRewards(_) => continue,
+            PlanMsg::Redelegate(m) => (
+                Some(m.validator_src_address.as_str()),
+                Some(m.validator_dst_address.as_str()),
+            ),
+            PlanMsg::Delegate(m) => (None, Some(m.validator_address.as_str())),
+            PlanMsg::Undelegate(m) => (Some(m.validator_address.as_str()), None),
+            PlanMsg::Send(_) => (None, None),
+        };
+        match src {
+            Some(src) => {
+                let balance = staked.entry((dao.clone(), src.to_string())).or_default();
+                let left = match balance.checked_sub(amount) {
+                    Ok(left) => left,
+                    Err(_) => return Ok(Some(failure(*balance, Shortfall::Shares))),
+                };
+                let emptied = shares.take(&dao, src, amount);
+                *balance = if emptied { Uint128::zero() } else { left };
+            }
+            None => {
+                let balance = liquid.entry(dao.clone()).or_default();
+                match balance.checked_sub(amount) {
+                    Ok(left) => *balance = left,
+                    Err(_) => return Ok(Some(failure(*balance, Shortfall::Funds))),
+                }
+            }
+        }
+        if let Some(dst) = dst {
+            shares.give(&dao, dst, amount);
+            *staked.entry((dao, dst.to_string())).or_default() += amount;
+        }
+    }
+    Ok(None)
+}
+
+/// Final state the plan leads to, compared with the targets.
+#[cw_serde]
+pub struct Verification {
+    pub total_final: Uint128,
+    pub total_target: Uint128,
+    pub discrepancies: Vec<Discrepancy>,
+    pub unexpected_validators: Vec<UnexpectedValidator>,
+    pub negative_balances: Vec<NegativeBalance>,
+    pub replay_failure: Option<ReplayFailure>,
+    /// Stake full-shares moves leave without shares. Their sources end empty and their
+    /// destinations that much under target, which is not a discrepancy.
+    #[serde(default)]
+    pub share_dust: Vec<ShareDust>,
+    /// Validators the plan moves stake onto that the snapshot records as jailed, tombstoned or no
+    /// longer bonded. The precheck refuses their bundles at broadcast.
+    #[serde(default)]
+    pub inactive_destinations: Vec<String>,
+    /// Set when the plan failed verification and was explicitly let through.
+    pub override_reason: Option<String>,
+}
+
+impl Verification {
+    pub fn passed(&self) -> bool {
+        self.discrepancies.is_empty()
+            && self.unexpected_validators.is_empty()
+            && self.negative_balances.is_empty()
+            && self.replay_failure.is_none()
+            && self.inactive_destinations.is_empty()
+    }
+
+    /// Fails unless the plan passed or `override_reason` explains why it may proceed anyway.
+    pub fn gate(mut self, override_reason: Option<String>) -> anyhow::Result<Self> {
+        if self.passed() {
+            return Ok(self);
+        }
+        match override_reason {
+            Some(reason) if !reason.trim().is_empty() => {
+                log::warn!("plan failed verification, overridden: {}", reason);
+                self.override_reason = Some(reason);
+                Ok(self)
+            }
+            _ => anyhow::bail!(
+                "plan failed verification: {} discrepancies, {} unexpected validators, {} negative balances, {} inactive destinations{}. Fix the plan or pass an override reason",
+                self.discrepancies.len(),
+                self.unexpected_validators.len(),
+                self.negative_balances.len(),
+                self.inactive_destinations.len(),
+                self.replay_failure
+                    .as_ref()
+                    .map(|f| format!(", {}", f))
+                    .unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// Stake balance of the plan: what the DAOs hold after it must be what they held, plus new
+/// delegations, minus undelegations, and that must be the target total.
+#[cw_serde]
+pub struct Conservation {
+    pub current_total: Uint128,
+    /// DAO stake on ignored validators, which the targets do not cover.
+    pub ignored_total: Uint128,
+    pub redelegated: Uint128,
+    pub delegated: Uint128,
+    pub undelegated: Uint128,
+    /// Stake full-shares undelegations leave without shares, gone from the DAOs along with them.
+    #[serde(default)]
+    pub undelegated_dust: Uint128,
+    /// Rewards the plan withdraws to the DAOs' liquid balances, which no stake moves with.
+    #[serde(default)]
+    pub withdrawn: Uint128,
+    /// Liquid funds the plan sends out of the DAOs, which no stake moves with.
+    #[serde(default)]
+    pub sent: Uint128,
+    pub target_total: Uint128,
+    /// Sections whose declared `count` or `total_ubtsg` does not match their messages.
+    pub mismatched_totals: Vec<String>,
+}
+
+impl Conservation {
+    pub fn holds(&self) -> bool {
+        let expected = self.current_total.u128() + self.delegated.u128();
+        let removed = self.undelegated.u128() + self.undelegated_dust.u128();
+        self.mismatched_totals.is_empty()
+            && expected >= removed
+            && expected - removed == self.target_total.u128() + self.ignored_total.u128()
+    }
+}
+
+pub fn conservation(
+    export: &MessageExport,
+    snapshot: &ChainSnapshot,
+    targets: &[Delegation],
+    ignored: &[&str],
+) -> anyhow::Result<Conservation> {
+    let mut declared = export.clone();
+    declared.recompute_totals()?;
+
+    // a plan's messages all move its chain's staking denom
+    let entries = export.entries();
+    let denom = entries.first().map(|e| e.msg.denom()).unwrap_or_default();
+    let mut mismatched_totals = Vec::new();
+    for (section, count, total, actual_count, actual_total) in [
+        (
+            "withdrawals",
+            export.withdrawals.count,
+            export.withdrawals.total_ubtsg,
+            declared.withdrawals.count,
+            declared.withdrawals.total_ubtsg,
+        ),
+        (
+            "redelegations",
+            export.redelegations.count,
+            export.redelegations.total_ubtsg,
+            declared.redelegations.count,
+            declared.redelegations.total_ubtsg,
+        ),
+        (
+            "delegations",
+            export.delegations.count,
+            export.delegations.total_ubtsg,
+            declared.delegations.count,
+            declared.delegations.total_ubtsg,
+        ),
+        (
+            "undelegates",
+            export.undelegates.count,
+            export.undelegates.total_ubtsg,
+            declared.undelegates.count,
+            declared.undelegates.total_ubtsg,
+        ),
+        (
+            "sends",
+            export.sends.count,
+            export.sends.total_ubtsg,
+            declared.sends.count,
+            declared.sends.total_ubtsg,
+        ),
+    ] {
+        if count != actual_count || total != actual_total {
+            mismatched_totals.push(format!(
+                "{} declares {} msgs / {}{}, has {} msgs / {}{}",
+                section, count, total, denom, actual_count, actual_total, denom
+            ));
+        }
+    }
+
+    Ok(Conservation {
+        current_total: snapshot.delegations.iter().map(|d| d.amount).sum(),
+        ignored_total: snapshot
+            .delegations
+            .iter()
+            .filter(|d| ignored.contains(&d.operator_addr.as_str()))
+            .map(|d| d.amount)
+            .sum(),
+        redelegated: declared.redelegations.total_ubtsg,
+        delegated: declared.delegations.total_ubtsg,
+        undelegated: declared.undelegates.total_ubtsg,
+        undelegated_dust: share_dust(export, snapshot)?
+            .into_iter()
+            .filter(|d| d.destination.is_none())
+            .map(|d| d.amount)
+            .sum(),
+        withdrawn: declared.withdrawals.total_ubtsg,
+        sent: declared.sends.total_ubtsg,
+        target_total: targets.iter().map(|t| t.amount).sum(),
+        mismatched_totals,
+    })
+}
+
+/// Machine-readable result of the `verify` command.
+#[cw_serde]
+pub struct VerifyReport {
+    pub passed: bool,
+    /// Height the plan records, it must be the snapshot's and never 0.
+    #[serde(default)]
+    pub plan_height: u64,
+    pub snapshot_height: u64,
+    pub conservation: Conservation,
+    pub verification: Verification,
+}
+
+/// Re-derives everything a reviewer needs from the plan, the snapshot it claims to start from and the targets.
+pub fn verify_plan(
+    export: &MessageExport,
+    snapshot: &ChainSnapshot,
+    targets: &[Delegation],
+    ignored: &[&str],
+) -> anyhow::Result<VerifyReport> {
+    let conservation = conservation(export, snapshot, targets, ignored)?;
+    let verification = verify_final_state(export, snapshot, targets, ignored)?;
+    // a plan without a height cannot be matched to its snapshot, a zeroed height fails too
+    let same_height = export.height != 0 && export.height == snapshot.height;
+    Ok(VerifyReport {
+        passed: same_height && conservation.holds() && verification.passed(),
+        plan_height: export.height,
+        snapshot_height: snapshot.height,
+        conservation,
+        verification,
+    })
+}
+
+fn signed(amount: Uint128) -> anyhow::Result<i128> {
+    Ok(i128::try_from(amount.u128())?)
+}
+
+fn unsigned(amount: i128) -> Uint128 {
+    Uint128::new(amount.unsigned_abs())
+}
+
+/// Applies every message of `export` to the per-validator totals of `snapshot` and compares the result
+/// with `targets`, then replays the plan per DAO. Stake left on `ignored` validators is not reported
+/// as unexpected.
+pub fn verify_final_state(
+    export: &MessageExport,
+    snapshot: &ChainSnapshot,
+    targets: &[Delegation],
+    ignored: &[&str],
+) -> anyhow::Result<Verification> {
+    let mut final_state: BTreeMap<String, i128> = BTreeMap::new();
+    for del in &snapshot.delegations {
+        *final_state.entry(del.operator_addr.clone()).or_default() += signed(del.amount)?;
+    }
+    let mut target_by_val: BTreeMap<String, Uint128> = BTreeMap::new();
+    for target in targets {
+        *target_by_val
+            .entry(target.operator_addr.clone())
+            .or_default() += target.amount;
+    }
+
+    for redel in &export.redelegations.data {
+        let amount = signed(Uint128::from_str(&redel.amount)?)?;
+        *final_state
+            .entry(redel.validator_src_address.clone())
+            .or_default() -= amount;
+        *final_state
+            .entry(redel.validator_dst_address.clone())
+            .or_default() += amount;
+    }
+    for del in &export.delegations.data {
+        *final_state
+            .entry(del.validator_address.clone())
+            .or_default() += signed(Uint128::from_str(&del.amount)?)?;
+    }
+    for undel in &export.undelegates.data {
+        *final_state
+            .entry(undel.validator_address.clone())
+            .or_default() -= signed(Uint128::from_str(&undel.amount)?)?;
+    }
+    // full-shares moves empty their source, the destination only gets what the shares were worth
+    let share_dust = share_dust(export, snapshot)?;
+    let mut dust_into: BTreeMap<String, Uint128> = BTreeMap::new();
+    for dust in &share_dust {
+        *final_state.entry(dust.validator.clone()).or_default() -= signed(dust.amount)?;
+        if let Some(dst) = &dust.destination {
+            *dust_into.entry(dst.clone()).or_default() += dust.amount;
+        }
+    }
+
+    let mut discrepancies = Vec::new();
+    let mut total_final = Uint128::zero();
+    for (validator, &target) in &target_by_val {
+        let final_amount = unsigned(final_state.get(validator).copied().unwrap_or(0).max(0));
+        total_final += final_amount;
+        let dust = dust_into.get(validator).copied().unwrap_or_default();
+        if final_amount != target.saturating_sub(dust) {
+            discrepancies.push(Discrepancy {
+                validator: validator.clone(),
+                final_amount,
+                target,
+            });
+        }
+    }
+    // largest first
+    discrepancies.sort_by_key(|d| std::cmp::Reverse(d.diff()));
+
+    let mut unexpected_validators = Vec::new();
+    let mut negative_balances = Vec::new();
+    for (validator, &amount) in &final_state {
+        if amount < 0 {
+            negative_balances.push(NegativeBalance {
+                validator: validator.clone(),
+                shortfall: unsigned(amount),
+            });
+        } else if amount > 0
+            && !target_by_val.contains_key(validator)
+            && !ignored.contains(&validator.as_str())
+        {
+            unexpected_validators.push(UnexpectedValidator {
+                validator: validator.clone(),
+                amount: unsigned(amount),
+            });
+        }
+    }
+
+    let msgs: Vec<PlanMsg> = export.entries().into_iter().map(|e| e.msg).collect();
+    let inactive_destinations = destinations(&msgs)
+        .into_iter()
+        .filter_map(|validator| {
+            let state = snapshot.validator(&validator)?;
+            (!state.is_active()).then(|| {
+                Violation::InactiveDestination {
+                    class: state.class(),
+                    validator,
+                }
+                .to_string()
+            })
+        })
+        .collect();
+
+    Ok(Verification {
+        total_final,
+        total_target: target_by_val.values().copied().sum(),
+        discrepancies,
+        unexpected_validators,
+        negative_balances,
+        share_dust,
+        inactive_destinations,
+        replay_failure: replay_plan(export, snapshot)?,
+        override_reason: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{del, validator},
+        plan::{
+            DelegateMsg, Delegations, RedelegateMsg, Redelegations, UndelegateMsg, Undelegations,
+        },
+    };
+
+    fn snapshot(delegations: Vec<Delegation>) -> ChainSnapshot {
+        ChainSnapshot {
+            height: 1,
+            delegations,
+            ..Default::default()
+        }
+    }
+
+    fn export(redels: Vec<(&str, &str, u128)>, undels: Vec<(&str, u128)>) -> MessageExport {
+        let mut export = MessageExport {
+            height: 1,
+            withdrawals: Default::default(),
+            sends: Default::default(),
+            redelegations: Redelegations {
+                data: redels
+                    .into_iter()
+                    .map(|(src, dst, amount)| RedelegateMsg {
+                        delegator_address: "dao1".to_string(),
+                        validator_src_address: src.to_string(),
+                        validator_dst_address: dst.to_string(),
+                        amount: amount.to_string(),
+                        denom: "ubtsg".to_string(),
+                    })
+                    .collect(),
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            delegations: Delegations {
+                data: vec![],
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+            undelegates: Undelegations {
+                data: undels
+                    .into_iter()
+                    .map(|(val, amount)| UndelegateMsg {
+                        delegator_address: "dao1".to_string(),
+                        validator_address: val.to_string(),
+                        amount: amount.to_string(),
+                        denom: "ubtsg".to_string(),
+                    })
+                    .collect(),
+                count: 0,
+                total_ubtsg: Uint128::zero(),
+            },
+        };
+        export.recompute_totals().unwrap();
+        export
+    }
+
+    #[test]
+    fn test_correct_plan_passes() -> anyhow::Result<()> {
+        let current = vec![del("dao1", "valA", 100), del("dao1", "omitted", 5)];
+        let targets = vec![del("", "valB", 60), del("", "valC", 20)];
+        let plan = export(
+            vec![("valA", "valB", 60), ("valA", "valC", 20)],
+            vec![("valA", 20)],
+        );
+
+        let verification = verify_final_state(&plan, &snapshot(current), &targets, &["omitted"])?;
+        assert!(verification.passed(), "{:?}", verification);
+        assert_eq!(verification.total_final, Uint128::new(80));
+        verification.gate(None)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_onto_a_jailed_target_fails() -> anyhow::Result<()> {
+        let current = vec![del("dao1", "valA", 100), del("dao1", "omitted", 5)];
+        let targets = vec![del("", "valB", 60), del("", "valC", 20)];
+        let plan = export(
+            vec![("valA", "valB", 60), ("valA", "valC", 20)],
+            vec![("valA", 20)],
+        );
+        let mut jailed = snapshot(current);
+        jailed.validators = vec![validator("valB", true)];
+
+        // valB still reaches its target, the bundle would not
+        let verification = verify_final_state(&plan, &jailed, &targets, &["omitted"])?;
+        assert!(verification.discrepancies.is_empty());
+        assert_eq!(
+            verification.inactive_destinations,
+            vec!["destination valB is jailed"]
+        );
+        assert!(!verification.passed());
+        assert!(verification
+            .gate(None)
+            .unwrap_err()
+            .to_string()
+            .contains("1 inactive destinations"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_overdrawn_validator_is_reported_instead_of_panicking() -> anyhow::Result<()> {
+        let current = vec![del("dao1", "valA", 100)];
+        let targets = vec![del("", "valB", 150)];
+        let plan = export(vec![("valA", "valB", 150)], vec![]);
+
+        let verification = verify_final_state(&plan, &snapshot(current), &targets, &[])?;
+        assert_eq!(
+            verification.replay_failure.as_ref().map(|f| f.shortfall),
+            Some(Shortfall::Shares)
+        );
+        assert_eq!(
+            verification.negative_balances,
+            vec![NegativeBalance {
+                validator: "valA".to_string(),
+                shortfall: Uint128::new(50),
+            }]
+        );
+        assert!(verification.discrepancies.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_plan_needs_an_override_reason() -> anyhow::Result<()> {
+        let current = vec![del("dao1", "valA", 100)];
+        let targets = vec![del("", "valB", 100)];
+        let plan = export(vec![("valA", "valB", 90)], vec![]);
+
+        let verification = verify_final_state(&plan, &snapshot(current), &targets, &[])?;
+        assert_eq!(verification.discrepancies[0].diff(), Uint128::new(10));
+        assert_eq!(verification.unexpected_validators[0].validator, "valA");
+
+        let err = verification.clone().gate(None).unwrap_err().to_string();
+        assert!(
+            err.contains("1 discrepancies, 1 unexpected validators"),
+            "{}",
+            err
+        );
+        assert!(verification.clone().gate(Some(" ".to_string())).is_err());
+
+        let overridden = verification.gate(Some("dust left on purpose".to_string()))?;
+        assert_eq!(
+            overridden.override_reason.as_deref(),
+            Some("dust left on purpose")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_checks_each_dao_separately() -> anyhow::Result<()> {
+        // valA holds 100 in total, but only 30 of it belongs to dao1
+        let current = snapshot(vec![del("dao1", "valA", 30), del("dao2", "valA", 70)]);
+        let plan = export(vec![("valA", "valB", 20), ("valA", "valB", 20)], vec![]);
+
+        let failure = replay_plan(&plan, &current)?.unwrap();
+        assert_eq!(failure.entry.to_string(), "redelegations[1]");
+        assert_eq!(failure.available, Uint128::new(10));
+        assert!(
+            failure.to_string().starts_with(
+                "redelegations[1] would fail with insufficient shares: dao1 has 10ubtsg"
+            ),
+            "{}",
+            failure
+        );
+
+        // a validator-level check alone would accept the plan
+        let verification = verify_final_state(
+            &plan,
+            &current,
+            &[del("", "valA", 60), del("", "valB", 40)],
+            &[],
+        )?;
+        assert!(verification.negative_balances.is_empty());
+        assert!(verification.discrepancies.is_empty());
+        assert!(!verification.passed());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_spends_liquid_balance_on_delegations() -> anyhow::Result<()> {
+        let mut plan = export(vec![], vec![]);
+        plan.delegations.data = ["40", "40"]
+            .iter()
+            .map(|amount| DelegateMsg {
+                delegator_address: "dao1".to_string(),
+                validator_address: "valA".to_string(),
+                amount: amount.to_string(),
+                denom: "ubtsg".to_string(),
+            })
+            .collect();
+        let mut current = snapshot(vec![]);
+        current.liquid.insert("dao1".to_string(), Uint128::new(50));
+
+        let failure = replay_plan(&plan, &current)?.unwrap();
+        assert_eq!(failure.entry.to_string(), "delegations[1]");
+        assert_eq!(failure.shortfall, Shortfall::Funds);
+
+        current.liquid.insert("dao1".to_string(), Uint128::new(80));
+        assert_eq!(replay_plan(&plan, &current)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_checks_conservation_and_declared_totals() -> anyhow::Result<()> {
+        let current = snapshot(vec![del("dao1", "valA", 100), del("dao1", "omitted", 5)]);
+        let targets = vec![del("", "valB", 60), del("", "valC", 20)];
+        let mut plan = export(
+            vec![("valA", "valB", 60), ("valA", "valC", 20)],
+            vec![("valA", 20)],
+        );
+
+        let report = verify_plan(&plan, &current, &targets, &["omitted"])?;
+        assert!(report.passed, "{:?}", report);
+        assert_eq!(report.conservation.current_total, Uint128::new(105));
+        assert_eq!(report.conservation.undelegated, Uint128::new(20));
+
+        // a tampered total is caught even though the messages themselves are fine
+        plan.undelegates.total_ubtsg = Uint128::new(2);
+        let report = verify_plan(&plan, &current, &targets, &["omitted"])?;
+        assert!(!report.passed);
+        assert_eq!(
+            report.conservation.mismatched_totals,
+            vec!["undelegates declares 1 msgs / 2ubtsg, has 1 msgs / 20ubtsg"]
+        );
+
+        // so is one of a section that moves no stake
+        plan.recompute_totals()?;
+        plan.sends.count = 1;
+        let report = verify_plan(&plan, &current, &targets, &["omitted"])?;
+        assert_eq!(
+            report.conservation.mismatched_totals,
+            vec!["sends declares 1 msgs / 0ubtsg, has 0 msgs / 0ubtsg"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_from_another_height_fails() -> anyhow::Result<()> {
+        let mut current = snapshot(vec![del("dao1", "valA", 100)]);
+        current.height = 1_000;
+        let targets = vec![del("", "valB", 100)];
+        let mut plan = export(vec![("valA", "valB", 100)], vec![]);
+
+        plan.height = 1_000;
+        assert!(verify_plan(&plan, &current, &targets, &[])?.passed);
+
+        plan.height = 999;
+        let report = verify_plan(&plan, &current, &targets, &[])?;
+        assert!(!report.passed);
+        assert!(report.verification.passed());
+
+        // a zeroed height does not skip the check
+        plan.height = 0;
+        assert!(!verify_plan(&plan, &current, &targets, &[])?.passed);
+        Ok(())
+    }
+}